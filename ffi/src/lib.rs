@@ -0,0 +1,170 @@
+// C bindings for driving the interpreter one commit at a time. See `undag.h`
+// for the interface as seen from C.
+
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use undag::{Instance, ProgramSource, StepResult};
+
+pub struct UndagInstance {
+    source: Box<dyn ProgramSource>,
+    instance: Instance,
+    running: bool,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    error: Option<CString>,
+}
+
+impl UndagInstance {
+    fn open(path: &CStr) -> anyhow::Result<Self> {
+        let source = undag::open(path.to_str()?)?;
+        let (start, end) = undag::endpoints(&*source)?;
+        let mut instance = Instance::new();
+        instance.start(&*source, start, end)?;
+        Ok(Self {
+            source,
+            instance,
+            running: true,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            error: None,
+        })
+    }
+}
+
+// Strings handed to C cannot contain NUL bytes, so they are dropped.
+fn to_c_string(bytes: impl Into<Vec<u8>>) -> *mut c_char {
+    let mut bytes = bytes.into();
+    bytes.retain(|&byte| byte != 0);
+    CString::new(bytes).unwrap().into_raw()
+}
+
+/// Opens the program at `path`, a repository or an exported DAG file, ready to
+/// be stepped through. Returns null if it cannot be loaded.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn undag_open(path: *const c_char) -> *mut UndagInstance {
+    match UndagInstance::open(CStr::from_ptr(path)) {
+        Ok(instance) => Box::into_raw(Box::new(instance)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Executes one commit. Returns 1 if the program is still running, 0 once it
+/// has finished, 2 if it is waiting for a line of input to be written, and -1
+/// if it failed, after which `undag_error` describes why.
+///
+/// # Safety
+/// `instance` must have been returned by `undag_open` and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn undag_step(instance: *mut UndagInstance) -> c_int {
+    let UndagInstance {
+        source,
+        instance,
+        running,
+        input,
+        output,
+        error,
+    } = &mut *instance;
+    if !*running {
+        return 0;
+    }
+    match instance.step(&**source, output) {
+        StepResult::Continued => 1,
+        StepResult::Halted => {
+            *running = false;
+            0
+        }
+        StepResult::NeedsInput => match input.iter().position(|&byte| byte == b'\n') {
+            Some(len) => {
+                let line = input.drain(..=len).collect::<Vec<_>>();
+                instance.push_input(line);
+                1
+            }
+            None => 2,
+        },
+        StepResult::Error(e) => {
+            *running = false;
+            *error = CString::new(format!("{:#}", e).replace('\0', "")).ok();
+            -1
+        }
+    }
+}
+
+/// Queues `data` to be read by the program's input instructions, which only
+/// consume complete lines.
+///
+/// # Safety
+/// `instance` must be live and `data` a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn undag_write_input(instance: *mut UndagInstance, data: *const c_char) {
+    (*instance)
+        .input
+        .extend(CStr::from_ptr(data).to_bytes().iter().copied());
+}
+
+/// Takes everything the program has printed since the last call. The result
+/// must be released with `undag_free_string`.
+///
+/// # Safety
+/// `instance` must be live.
+#[no_mangle]
+pub unsafe extern "C" fn undag_read_output(instance: *mut UndagInstance) -> *mut c_char {
+    to_c_string(std::mem::take(&mut (*instance).output))
+}
+
+/// Formats the variable `var`, which may be a path such as `table/key`, as the
+/// print instructions would. Returns null if it is undefined. The result must
+/// be released with `undag_free_string`.
+///
+/// # Safety
+/// `instance` must be live and `var` a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn undag_read_var(
+    instance: *const UndagInstance,
+    var: *const c_char,
+) -> *mut c_char {
+    let var = match CStr::from_ptr(var).to_str() {
+        Ok(var) => var,
+        Err(_) => return ptr::null_mut(),
+    };
+    match (*instance).instance.get(var) {
+        Ok(val) => to_c_string(val.to_string()),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// The error from the last failed `undag_step`, or null. The string belongs to
+/// the instance.
+///
+/// # Safety
+/// `instance` must be live.
+#[no_mangle]
+pub unsafe extern "C" fn undag_error(instance: *const UndagInstance) -> *const c_char {
+    (*instance)
+        .error
+        .as_ref()
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// # Safety
+/// `s` must have been returned by this library and not yet freed, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn undag_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// # Safety
+/// `instance` must have been returned by `undag_open` and not yet closed, or
+/// be null.
+#[no_mangle]
+pub unsafe extern "C" fn undag_close(instance: *mut UndagInstance) {
+    if !instance.is_null() {
+        drop(Box::from_raw(instance));
+    }
+}
@@ -0,0 +1,38 @@
+#ifndef UNDAG_H
+#define UNDAG_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct UndagInstance UndagInstance;
+
+/* Opens a repository or exported DAG file. Returns NULL on failure. */
+UndagInstance *undag_open(const char *path);
+
+/* Executes one commit: 1 while running, 0 once finished, 2 while waiting for
+ * a line of input, -1 on error. */
+int undag_step(UndagInstance *instance);
+
+/* Queues input for the program to read a line at a time. */
+void undag_write_input(UndagInstance *instance, const char *data);
+
+/* Takes the output printed since the last call. Free with undag_free_string. */
+char *undag_read_output(UndagInstance *instance);
+
+/* Formats a variable, or returns NULL if it is undefined. Free with
+ * undag_free_string. */
+char *undag_read_var(const UndagInstance *instance, const char *var);
+
+/* The error from the last failed step, or NULL. Owned by the instance. */
+const char *undag_error(const UndagInstance *instance);
+
+void undag_free_string(char *s);
+
+void undag_close(UndagInstance *instance);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use undag::Op;
+
+fuzz_target!(|message: &str| {
+    if let Ok(op) = message.parse::<Op>() {
+        let _ = op.to_string().parse::<Op>();
+    }
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use undag::{Instance, Val};
+
+// Each line is a path, written as a value, then as a reference to itself,
+// then entered as a table, in turn, and read back after each.
+fuzz_target!(|paths: &str| {
+    let mut instance = Instance::new();
+    for (i, path) in paths.lines().enumerate() {
+        let _ = match i % 3 {
+            0 => instance.set(path, Val::Int(i as i64)),
+            1 => instance.set(path, Val::Ref(path.into())),
+            _ => instance.enter_table(path),
+        };
+        let _ = instance.get(path);
+        let _ = instance.root_path(path);
+    }
+});
@@ -0,0 +1,246 @@
+use crate::dag::{Dag, Graph};
+use crate::interp::{Get, Op, Val};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+
+// Programs can be written as text, one op per line, where each op is followed
+// by the next unless an edge line names its children instead:
+//
+//     _start:
+//         set n #0
+//     loop:
+//         println $n
+//         add n $n #1
+//         gt end $n #10
+//         match path $end #0 loop #1 _end
+//         branch $path
+//         -> loop _end
+//     _end: nop
+//
+// A word ending in `:` labels the op after it, which may follow on the same
+// line, and becomes its tag. `nop` is the empty op, and lines starting with
+// `#` are comments. Without `_start` and `_end` labels, the program runs from
+// its first op to its last. Edges to an earlier op become replacements, the
+// same way loops are made with `git replace --graft`.
+pub fn assemble(text: &str) -> Result<Dag> {
+    let mut graph = Graph::default();
+    let mut nodes = Vec::<String>::new();
+    let mut labels = HashMap::<String, String>::new();
+    let mut pending = Vec::<String>::new();
+    let mut edges = HashMap::<String, (usize, Vec<String>)>::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(targets) = line.strip_prefix("->") {
+            let node = nodes
+                .last()
+                .filter(|_| pending.is_empty())
+                .with_context(|| format!("line {}: edges must follow an op", line_no))?;
+            let targets = targets.split_whitespace().map(str::to_owned).collect();
+            anyhow::ensure!(
+                edges.insert(node.clone(), (line_no, targets)).is_none(),
+                "line {}: the op's edges are already given",
+                line_no
+            );
+            continue;
+        }
+        let (line_labels, line) = split_labels(line);
+        for label in line_labels {
+            anyhow::ensure!(
+                !labels.contains_key(label) && !pending.iter().any(|l| l == label),
+                "line {}: label {} is already defined",
+                line_no,
+                label
+            );
+            pending.push(label.to_owned());
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let node = line_no.to_string();
+        graph.node(&node);
+        let op = if line == "nop" { "" } else { line };
+        graph.labels.insert(node.clone(), op.to_owned());
+        for label in pending.drain(..) {
+            graph.tags.get_mut(&node).unwrap().push(label.clone());
+            labels.insert(label, node.clone());
+        }
+        nodes.push(node);
+    }
+    if let Some(label) = pending.first() {
+        anyhow::bail!("label {} has no op after it", label);
+    }
+    anyhow::ensure!(!nodes.is_empty(), "program has no ops");
+
+    for (i, node) in nodes.iter().enumerate() {
+        let children = match edges.remove(node) {
+            Some((line_no, targets)) => targets
+                .iter()
+                .map(|target| {
+                    labels
+                        .get(target)
+                        .cloned()
+                        .with_context(|| format!("line {}: undefined label: {}", line_no, target))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => nodes.get(i + 1).cloned().into_iter().collect(),
+        };
+        graph.children.insert(node.clone(), children);
+    }
+    for (tag, node) in [("_start", &nodes[0]), ("_end", &nodes[nodes.len() - 1])] {
+        if !labels.contains_key(tag) {
+            graph.tags.get_mut(node).unwrap().push(tag.to_owned());
+        }
+    }
+    Ok(graph.to_dag())
+}
+
+// Splits the labels off the start of a trimmed line, leaving the op after them.
+pub(crate) fn split_labels(mut line: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    while let Some((label, rest)) = line
+        .split_once(char::is_whitespace)
+        .or(Some((line, "")))
+        .and_then(|(word, rest)| Some((word.strip_suffix(':')?, rest.trim_start())))
+    {
+        labels.push(label);
+        line = rest;
+    }
+    (labels, line)
+}
+
+// Writes the program between `start` and `end` as text that `assemble` reads
+// back. Ops are ordered so that every op comes before its children, except
+// along loops, and the first child of each op follows it wherever possible.
+// Tags become labels, and ops that are jumped to but untagged are labelled
+// `L1`, `L2` and so on. Literal `branch` targets are rewritten to the labels
+// of the ops they name.
+pub fn disassemble(source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<String> {
+    let replacements = Replacements::load(source)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(source, &replacements, start, end)?;
+    let children_of = |id: &Oid| -> Vec<Oid> {
+        children
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|next| next.id)
+            .collect()
+    };
+
+    // Reverse postorder of a depth-first search, visiting the first child
+    // last so that it ends up right after its parent.
+    let mut postorder = Vec::new();
+    let mut visited = std::iter::once(start).collect::<HashSet<_>>();
+    let mut stack = vec![(start, children_of(&start))];
+    while let Some((id, nexts)) = stack.last_mut() {
+        match nexts.pop() {
+            Some(next) => {
+                if visited.insert(next) {
+                    let nexts = children_of(&next);
+                    stack.push((next, nexts));
+                }
+            }
+            None => {
+                postorder.push(*id);
+                stack.pop();
+            }
+        }
+    }
+    let order = postorder.into_iter().rev().collect::<Vec<_>>();
+
+    let mut labels = HashMap::<Oid, Vec<String>>::new();
+    let mut names = source.tags()?;
+    names.sort();
+    for name in names {
+        if let Some(id) = source.tag(&name).map(|id| replacements.resolve(id)) {
+            if visited.contains(&id) {
+                labels.entry(id).or_default().push(name);
+            }
+        }
+    }
+    for (tag, id) in [("_start", start), ("_end", end)] {
+        if !labels.values().flatten().any(|label| label == tag) {
+            labels.entry(id).or_default().insert(0, tag.to_owned());
+        }
+    }
+    let mut generated = 0;
+    let mut label = |labels: &mut HashMap<Oid, Vec<String>>, id: Oid| -> String {
+        if let Some(label) = labels.get(&id).and_then(|labels| labels.first()) {
+            return label.clone();
+        }
+        let label = loop {
+            generated += 1;
+            let label = format!("L{}", generated);
+            if !labels.values().flatten().any(|taken| *taken == label) {
+                break label;
+            }
+        };
+        labels.insert(id, vec![label.clone()]);
+        label
+    };
+
+    // Every label must be known before any op is written.
+    let mut lines = Vec::new();
+    for (i, id) in order.iter().enumerate() {
+        let message = source.message(*id)?;
+        let mut op = message.trim().to_owned();
+        if let Ok(parsed) = message.parse::<Op>() {
+            if let Op::Branch(Get::Val(target)) = &parsed {
+                let target = target.to_string();
+                if let Some(to) = source.tag(&target).map(|to| replacements.resolve(to)) {
+                    let named = labels.get(&to).is_some_and(|l| l.contains(&target));
+                    if visited.contains(&to) && !named {
+                        let target = label(&mut labels, to);
+                        op = Op::Branch(Get::Val(Val::Str(target.into()))).to_string();
+                    }
+                }
+            }
+            if op.contains('\n') {
+                op = parsed.to_string();
+            }
+        }
+        anyhow::ensure!(
+            !op.contains('\n'),
+            "{}: op cannot be written on one line",
+            replacements.original(*id)
+        );
+        if op.is_empty() {
+            op = "nop".to_owned();
+        }
+        let nexts = children_of(id);
+        let edges = match order.get(i + 1) {
+            Some(next) if nexts == [*next] => None,
+            None if nexts.is_empty() => None,
+            _ => Some(
+                nexts
+                    .into_iter()
+                    .map(|next| label(&mut labels, next))
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        lines.push((*id, op, edges));
+    }
+
+    let mut text = String::new();
+    for (id, op, edges) in lines {
+        for label in labels.get(&id).into_iter().flatten() {
+            text += &format!("{}:\n", label);
+        }
+        text += &format!("    {}\n", op);
+        if let Some(edges) = edges {
+            text += "    ->";
+            for edge in edges {
+                text += &format!(" {}", edge);
+            }
+            text += "\n";
+        }
+    }
+    Ok(text)
+}
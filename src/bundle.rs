@@ -0,0 +1,101 @@
+use crate::{Oid, ProgramSource};
+use anyhow::{Context, Result};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+// A program distributed as a git bundle. libgit2 cannot read bundles, so the
+// bundle is cloned with git into a temporary repository that lasts as long as
+// this does. Remote repositories are cloned the same way.
+pub struct Bundle {
+    repo: git::Repository,
+    _dir: TempDir,
+}
+
+impl Bundle {
+    pub fn is_bundle(path: &Path) -> bool {
+        let mut header = [0; 16];
+        std::fs::File::open(path)
+            .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+            .is_ok()
+            && (header.starts_with(b"# v2 git bundle") || header.starts_with(b"# v3 git bundle"))
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::mirror(path.as_os_str())
+            .with_context(|| format!("failed to unpack bundle: {}", path.display()))
+    }
+
+    // Clones a remote repository the same way, for programs that are run or
+    // imported straight from a URL.
+    pub fn clone_url(url: &str) -> Result<Self> {
+        Self::mirror(url.as_ref()).with_context(|| format!("failed to clone {}", url))
+    }
+
+    fn mirror(source: &OsStr) -> Result<Self> {
+        let dir = TempDir::new()?;
+        // A mirror clone keeps every ref, including `refs/replace`.
+        let status = Command::new("git")
+            .args(["clone", "--quiet", "--mirror"])
+            .arg(source)
+            .arg(dir.path())
+            .status()
+            .context("failed to run git")?;
+        anyhow::ensure!(status.success(), "git clone failed");
+        Ok(Self {
+            repo: git::Repository::open_bare(dir.path())?,
+            _dir: dir,
+        })
+    }
+}
+
+impl ProgramSource for Bundle {
+    fn message(&self, id: Oid) -> Result<String> {
+        ProgramSource::message(&self.repo, id)
+    }
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+        ProgramSource::parents(&self.repo, id)
+    }
+
+    fn time(&self, id: Oid) -> Result<i64> {
+        ProgramSource::time(&self.repo, id)
+    }
+
+    fn author(&self, id: Oid) -> Result<(String, String)> {
+        ProgramSource::author(&self.repo, id)
+    }
+
+    fn tag(&self, name: &str) -> Option<Oid> {
+        ProgramSource::tag(&self.repo, name)
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        ProgramSource::tags(&self.repo)
+    }
+
+    fn verify_signature(&self, id: Oid, keyring: Option<&Path>) -> Result<()> {
+        ProgramSource::verify_signature(&self.repo, id, keyring)
+    }
+
+    fn create_commit(&self, message: &str, parents: &[Oid]) -> Result<Oid> {
+        ProgramSource::create_commit(&self.repo, message, parents)
+    }
+
+    fn create_tag(&self, name: &str, id: Oid) -> Result<()> {
+        ProgramSource::create_tag(&self.repo, name, id)
+    }
+
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+        ProgramSource::replacements(&self.repo)
+    }
+
+    fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+        ProgramSource::file(&self.repo, id, path)
+    }
+
+    fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+        ProgramSource::descendant_of(&self.repo, id, ancestor)
+    }
+}
@@ -0,0 +1,174 @@
+use crate::dag::{Dag, Graph};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+
+const MAGIC: &[u8; 4] = b"UDBC";
+const VERSION: u32 = 1;
+
+// Compiles the program between `start` and `end` into a compact binary form
+// that runs without a repository. Only the commits of the program are kept,
+// with replacements already applied, and its ops and tags are stored once
+// each in a table of strings that nodes refer to by index:
+//
+//     "UDBC" version
+//     strings: count, then each string's length and bytes
+//     nodes:   count, then each node's op and its children
+//     tags:    count, then each tag's name and node
+//
+// Every number is a little-endian `u32`. Nodes are ordered by commit time,
+// which is the order their children are taken in by the traversal modes that
+// sort by time.
+pub fn compile(source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<Vec<u8>> {
+    let replacements = Replacements::load(source)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(source, &replacements, start, end)?;
+    let mut nodes = tree::walk(&children, start);
+    if !nodes.contains(&end) {
+        nodes.push(end);
+    }
+    nodes.sort_by_cached_key(|&id| (source.time(id).unwrap_or_default(), id));
+    let index = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, &id)| (id, i as u32))
+        .collect::<HashMap<_, _>>();
+
+    let mut strings = Strings::default();
+    let mut node_bytes = Vec::new();
+    put(&mut node_bytes, nodes.len() as u32);
+    for id in &nodes {
+        let message = source.message(*id).unwrap_or_default();
+        put(&mut node_bytes, strings.intern(message.trim_end()));
+        let nexts = children.get(id).map_or(&[][..], Vec::as_slice);
+        put(&mut node_bytes, nexts.len() as u32);
+        for next in nexts {
+            put(&mut node_bytes, index[&next.id]);
+        }
+    }
+
+    let mut tags = Vec::new();
+    for name in source.tags()? {
+        if let Some(&i) = source
+            .tag(&name)
+            .and_then(|id| index.get(&replacements.resolve(id)))
+        {
+            tags.push((name, i));
+        }
+    }
+    for (tag, id) in [("_start", start), ("_end", end)] {
+        if !tags.iter().any(|(name, _)| name == tag) {
+            tags.push((tag.to_owned(), index[&id]));
+        }
+    }
+    tags.sort();
+    tags.dedup_by(|a, b| a.0 == b.0);
+    let mut tag_bytes = Vec::new();
+    put(&mut tag_bytes, tags.len() as u32);
+    for (name, i) in &tags {
+        put(&mut tag_bytes, strings.intern(name));
+        put(&mut tag_bytes, *i);
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    put(&mut bytes, VERSION);
+    put(&mut bytes, strings.list.len() as u32);
+    for string in &strings.list {
+        put(&mut bytes, string.len() as u32);
+        bytes.extend_from_slice(string.as_bytes());
+    }
+    bytes.extend(node_bytes);
+    bytes.extend(tag_bytes);
+    Ok(bytes)
+}
+
+// Reads a program compiled by `compile`.
+pub fn load(bytes: &[u8]) -> Result<Dag> {
+    let mut reader = Reader(bytes);
+    anyhow::ensure!(reader.take(MAGIC.len())? == MAGIC, "not a compiled program");
+    let version = reader.u32()?;
+    anyhow::ensure!(
+        version == VERSION,
+        "unsupported compiled program version: {}",
+        version
+    );
+
+    let mut strings = Vec::new();
+    for _ in 0..reader.u32()? {
+        let len = reader.u32()? as usize;
+        strings.push(std::str::from_utf8(reader.take(len)?).context("invalid string")?);
+    }
+    let string = |i: u32| {
+        strings
+            .get(i as usize)
+            .copied()
+            .with_context(|| format!("string {} not found", i))
+    };
+
+    let mut graph = Graph::default();
+    let count = reader.u32()?;
+    let node = |i: u32| {
+        anyhow::ensure!(i < count, "node {} not found", i);
+        Ok(i.to_string())
+    };
+    for i in 0..count {
+        let id = node(i)?;
+        graph.node(&id);
+        graph
+            .labels
+            .insert(id.clone(), string(reader.u32()?)?.to_owned());
+        let mut nexts = Vec::new();
+        for _ in 0..reader.u32()? {
+            nexts.push(node(reader.u32()?)?);
+        }
+        graph.children.insert(id, nexts);
+    }
+    let mut seen = HashSet::new();
+    for _ in 0..reader.u32()? {
+        let name = string(reader.u32()?)?;
+        let id = node(reader.u32()?)?;
+        anyhow::ensure!(seen.insert(name), "tag {} is defined twice", name);
+        graph.tags.get_mut(&id).unwrap().push(name.to_owned());
+    }
+    anyhow::ensure!(reader.0.is_empty(), "trailing bytes after program");
+    Ok(graph.to_dag())
+}
+
+// Strings in the order they are first interned.
+#[derive(Default)]
+struct Strings {
+    list: Vec<String>,
+    index: HashMap<String, u32>,
+}
+
+impl Strings {
+    fn intern(&mut self, string: &str) -> u32 {
+        if let Some(&i) = self.index.get(string) {
+            return i;
+        }
+        let i = self.list.len() as u32;
+        self.list.push(string.to_owned());
+        self.index.insert(string.to_owned(), i);
+        i
+    }
+}
+
+fn put(bytes: &mut Vec<u8>, n: u32) {
+    bytes.extend_from_slice(&n.to_le_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        anyhow::ensure!(len <= self.0.len(), "unexpected end of compiled program");
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,140 @@
+use crate::interp::{Get, Op};
+use crate::program::Program;
+use crate::tree::{self, Children};
+use crate::{Oid, ProgramSource, Replacements, UndagError};
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+use std::fmt;
+
+// What checking a program found: the commits whose ops fail to parse or
+// branch to tags that do not exist, and warnings about the shape of its graph.
+pub struct Report {
+    pub checked: usize,
+    pub errors: Vec<anyhow::Error>,
+    pub warnings: Vec<(Oid, String)>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in &self.errors {
+            writeln!(f, "{:#}", e)?;
+        }
+        for (id, warning) in &self.warnings {
+            writeln!(f, "warning: {}: {}", id, warning)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn check(source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<Report> {
+    let replacements = Replacements::load(source)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+
+    let program = Program::load(source, &replacements, start, end)?;
+    let order = tree::walk(&program.children, start);
+
+    let errors = order
+        .iter()
+        .filter_map(|&id| {
+            program
+                .op(id)
+                .and_then(|op| check_op(source, op).map_err(|e| e.context(id)))
+                .err()
+        })
+        .collect();
+    Ok(Report {
+        checked: order.len(),
+        errors,
+        warnings: lint(source, &replacements, &program.children, start, end),
+    })
+}
+
+fn check_op(source: &dyn ProgramSource, op: &Op) -> Result<()> {
+    // The command line registers no extern operations.
+    if let Op::Extern(name, _) = op {
+        anyhow::bail!(UndagError::InvalidOperation(name.clone()));
+    }
+    // Only literal targets can be verified without running the program.
+    if let Op::Branch(Get::Val(tag)) = op {
+        source
+            .tag(&tag.to_string())
+            .with_context(|| format!("branch: undefined tag: {}", tag))?;
+    }
+    Ok(())
+}
+
+pub fn lint(
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    children: &Children,
+    start: Oid,
+    end: Oid,
+) -> Vec<(Oid, String)> {
+    let mut parents = HashMap::<Oid, Vec<Oid>>::new();
+    for (&id, nexts) in children {
+        for next in nexts {
+            parents.entry(next.id).or_default().push(id);
+        }
+    }
+    let search = |from: Oid, edges: &HashMap<Oid, Vec<Oid>>| {
+        let mut found = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(id) = stack.pop() {
+            if found.insert(id) {
+                stack.extend(edges.get(&id).into_iter().flatten().copied());
+            }
+        }
+        found
+    };
+    let before_start = search(start, &parents);
+    let reaches_end = search(end, &parents);
+    let program = tree::walk(children, start);
+    let reachable = program.iter().copied().collect::<HashSet<_>>();
+
+    let mut warnings = Vec::new();
+
+    let mut unreachable = children
+        .keys()
+        .copied()
+        .filter(|id| !reachable.contains(id) && !before_start.contains(id))
+        .collect::<Vec<_>>();
+    unreachable.sort();
+    warnings.extend(
+        unreachable
+            .into_iter()
+            .map(|id| (id, "unreachable from _start".to_owned())),
+    );
+
+    for id in program {
+        if !reaches_end.contains(&id) {
+            warnings.push((id, "no path to _end".to_owned()));
+        }
+        let message = match source.message(id) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        let tokens = shellwords::split(&message).unwrap_or_default();
+        match tokens.first().map(String::as_str) {
+            Some("branch") => {
+                let nexts = children.get(&id).map(Vec::as_slice).unwrap_or_default();
+                if let Some(tag) = tokens.get(1).filter(|tag| !tag.starts_with('$')) {
+                    // Tags of modules are reached by running the module instead.
+                    let module = tag.contains("::") && source.tag(tag).is_some();
+                    if !module && tree::find_tag(source, replacements, tag, nexts).is_none() {
+                        warnings.push((id, format!("branch: no child leads to tag: {}", tag)));
+                    }
+                }
+            }
+            Some("match") if tokens.len() > 3 && (tokens.len() - 3) % 2 == 1 => {
+                warnings.push((
+                    id,
+                    format!("match: unpaired value ignored: {}", tokens.last().unwrap()),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    warnings
+}
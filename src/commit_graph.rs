@@ -0,0 +1,309 @@
+use crate::{Oid, ProgramSource};
+use anyhow::{Context, Result};
+use hashbrown::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+// Parent indices meaning that a commit has no parent, or that its parents
+// beyond the first are listed among the extra edges.
+const NO_PARENT: u32 = 0x7000_0000;
+const EXTRA_EDGES: u32 = 0x8000_0000;
+
+// Git's commit-graph file, written by `git commit-graph write` or `git gc`,
+// which lists the parents, commit time and generation of every commit it
+// covers so that history can be walked without loading commit objects. A graph
+// may be split into a chain of layers, each of which refers to the commits of
+// the layers below it by their position across the whole chain.
+pub struct CommitGraph {
+    layers: Vec<Layer>,
+}
+
+struct Layer {
+    data: Vec<u8>,
+    // The position of the layer's first commit across the chain.
+    base: u32,
+    count: u32,
+    fanout: usize,
+    lookup: usize,
+    commits: usize,
+    edges: Option<usize>,
+}
+
+impl CommitGraph {
+    // The graph in the object directory `objects`, if there is one.
+    pub fn open(objects: &Path) -> Result<Option<Self>> {
+        let info = objects.join("info");
+        let files = match std::fs::read_to_string(info.join("commit-graphs/commit-graph-chain")) {
+            Ok(chain) => chain
+                .lines()
+                .map(|hash| info.join(format!("commit-graphs/graph-{}.graph", hash.trim())))
+                .collect(),
+            Err(_) if info.join("commit-graph").is_file() => vec![info.join("commit-graph")],
+            Err(_) => return Ok(None),
+        };
+        let mut layers = Vec::<Layer>::new();
+        for file in files {
+            let data = std::fs::read(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let base = layers.last().map_or(0, |layer| layer.base + layer.count);
+            let layer = Layer::parse(data, base)
+                .with_context(|| format!("invalid commit-graph: {}", file.display()))?;
+            layers.push(layer);
+        }
+        Ok(Some(Self { layers }))
+    }
+
+    pub fn parents(&self, id: Oid) -> Option<Vec<Oid>> {
+        let (layer, entry) = self.entry(self.position(id)?)?;
+        let mut parents = Vec::new();
+        for parent in [layer.u32(entry + 20), layer.u32(entry + 24)] {
+            match parent {
+                NO_PARENT => {}
+                parent if parent & EXTRA_EDGES != 0 => {
+                    let mut edge = layer.edges? + (parent & !EXTRA_EDGES) as usize * 4;
+                    loop {
+                        let parent = layer.u32(edge);
+                        parents.push(self.id(parent & !EXTRA_EDGES)?);
+                        if parent & EXTRA_EDGES != 0 {
+                            break;
+                        }
+                        edge += 4;
+                    }
+                }
+                parent => parents.push(self.id(parent)?),
+            }
+        }
+        Some(parents)
+    }
+
+    pub fn time(&self, id: Oid) -> Option<i64> {
+        let (layer, entry) = self.entry(self.position(id)?)?;
+        let high = (layer.u32(entry + 28) & 0b11) as i64;
+        Some(high << 32 | layer.u32(entry + 32) as i64)
+    }
+
+    // The length of the longest path from the commit to a root, counting the
+    // root as 1, or 0 if the graph was written without generations.
+    fn generation(&self, id: Oid) -> Option<u32> {
+        let (layer, entry) = self.entry(self.position(id)?)?;
+        Some(layer.u32(entry + 28) >> 2)
+    }
+
+    fn position(&self, id: Oid) -> Option<u32> {
+        self.layers.iter().find_map(|layer| {
+            let first = id.as_bytes()[0] as usize;
+            let start = match first {
+                0 => 0,
+                _ => layer.u32(layer.fanout + (first - 1) * 4),
+            };
+            let end = layer.u32(layer.fanout + first * 4).min(layer.count);
+            let (mut low, mut high) = (start.min(end), end);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                match layer.id(mid).cmp(id.as_bytes()) {
+                    std::cmp::Ordering::Less => low = mid + 1,
+                    std::cmp::Ordering::Greater => high = mid,
+                    std::cmp::Ordering::Equal => return Some(layer.base + mid),
+                }
+            }
+            None
+        })
+    }
+
+    fn id(&self, position: u32) -> Option<Oid> {
+        let layer = self.layer(position)?;
+        let mut bytes = [0; 20];
+        bytes.copy_from_slice(layer.id(position - layer.base));
+        Some(Oid::from_bytes(bytes))
+    }
+
+    fn entry(&self, position: u32) -> Option<(&Layer, usize)> {
+        let layer = self.layer(position)?;
+        Some((layer, layer.commits + (position - layer.base) as usize * 36))
+    }
+
+    fn layer(&self, position: u32) -> Option<&Layer> {
+        self.layers
+            .iter()
+            .find(|layer| (layer.base..layer.base + layer.count).contains(&position))
+    }
+}
+
+impl Layer {
+    fn parse(data: Vec<u8>, base: u32) -> Result<Self> {
+        anyhow::ensure!(data.len() >= 8 && &data[..4] == b"CGPH", "bad signature");
+        anyhow::ensure!(data[4] == 1, "unsupported version: {}", data[4]);
+        anyhow::ensure!(data[5] == 1, "unsupported hash version: {}", data[5]);
+        let chunks = data[6] as usize;
+        anyhow::ensure!(data.len() >= 8 + (chunks + 1) * 12, "truncated chunk table");
+        let mut layer = Self {
+            data,
+            base,
+            count: 0,
+            fanout: 0,
+            lookup: 0,
+            commits: 0,
+            edges: None,
+        };
+        let mut found = [None; 4];
+        for i in 0..chunks {
+            let entry = 8 + i * 12;
+            let offset = u64::from_be_bytes(layer.data[entry + 4..entry + 12].try_into().unwrap());
+            let offset = usize::try_from(offset)?;
+            anyhow::ensure!(offset <= layer.data.len(), "chunk out of bounds");
+            let chunk = [b"OIDF", b"OIDL", b"CDAT", b"EDGE"]
+                .iter()
+                .position(|id| layer.data[entry..entry + 4] == id[..]);
+            if let Some(chunk) = chunk {
+                found[chunk] = Some(offset);
+            }
+        }
+        let [Some(fanout), Some(lookup), Some(commits), edges] = found else {
+            anyhow::bail!("missing required chunk");
+        };
+        anyhow::ensure!(fanout + 256 * 4 <= layer.data.len(), "truncated fanout");
+        layer.fanout = fanout;
+        layer.count = layer.u32(fanout + 255 * 4);
+        let count = layer.count as usize;
+        anyhow::ensure!(
+            lookup + count * 20 <= layer.data.len() && commits + count * 36 <= layer.data.len(),
+            "truncated commits"
+        );
+        layer.lookup = lookup;
+        layer.commits = commits;
+        layer.edges = edges;
+        Ok(layer)
+    }
+
+    fn id(&self, i: u32) -> &[u8] {
+        let start = self.lookup + i as usize * 20;
+        &self.data[start..start + 20]
+    }
+
+    // Reads past the end of the file as 0, which only a corrupt edge list can.
+    fn u32(&self, at: usize) -> u32 {
+        self.data
+            .get(at..at + 4)
+            .map_or(0, |bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+// A repository that reads parents and commit times from its commit-graph for
+// the commits it covers, and from the commit objects otherwise.
+pub struct Graphed {
+    repo: git::Repository,
+    // Shared with the forks of the repository.
+    graph: Option<Arc<CommitGraph>>,
+    // The boundary of a shallow clone, read once when the repository opens,
+    // and shared with its forks.
+    shallow: Arc<HashSet<Oid>>,
+}
+
+impl Graphed {
+    // The parents of commits at the boundary of a shallow clone are missing,
+    // which only the repository reports, so its graph is left unused. So is a
+    // graph that fails to read, as git ignores one.
+    pub fn new(repo: git::Repository) -> Self {
+        let shallow = crate::source::shallow_commits(repo.path());
+        let graph = match shallow.is_empty() {
+            true => CommitGraph::open(&repo.path().join("objects"))
+                .ok()
+                .flatten()
+                .map(Arc::new),
+            false => None,
+        };
+        Self {
+            repo,
+            graph,
+            shallow: Arc::new(shallow),
+        }
+    }
+}
+
+impl ProgramSource for Graphed {
+    fn message(&self, id: Oid) -> Result<String> {
+        ProgramSource::message(&self.repo, id)
+    }
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+        match self.graph.as_ref().and_then(|graph| graph.parents(id)) {
+            Some(parents) => Ok(parents),
+            None => crate::source::parents(&self.repo, id, &self.shallow),
+        }
+    }
+
+    fn time(&self, id: Oid) -> Result<i64> {
+        match self.graph.as_ref().and_then(|graph| graph.time(id)) {
+            Some(time) => Ok(time),
+            None => ProgramSource::time(&self.repo, id),
+        }
+    }
+
+    fn author(&self, id: Oid) -> Result<(String, String)> {
+        ProgramSource::author(&self.repo, id)
+    }
+
+    fn tag(&self, name: &str) -> Option<Oid> {
+        ProgramSource::tag(&self.repo, name)
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        ProgramSource::tags(&self.repo)
+    }
+
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+        ProgramSource::replacements(&self.repo)
+    }
+
+    fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+        ProgramSource::file(&self.repo, id, path)
+    }
+
+    fn create_commit(&self, message: &str, parents: &[Oid]) -> Result<Oid> {
+        ProgramSource::create_commit(&self.repo, message, parents)
+    }
+
+    fn create_tag(&self, name: &str, id: Oid) -> Result<()> {
+        ProgramSource::create_tag(&self.repo, name, id)
+    }
+
+    fn verify_signature(&self, id: Oid, keyring: Option<&Path>) -> Result<()> {
+        ProgramSource::verify_signature(&self.repo, id, keyring)
+    }
+
+    // Generations only decrease from child to parent, so the search skips any
+    // commit older than `ancestor` by generation.
+    fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+        let Some(graph) = &self.graph else {
+            return ProgramSource::descendant_of(&self.repo, id, ancestor);
+        };
+        let Some(target) = graph
+            .generation(ancestor)
+            .filter(|&generation| generation > 0)
+        else {
+            return ProgramSource::descendant_of(&self.repo, id, ancestor);
+        };
+        let mut checked = HashSet::new();
+        let mut stack = self.parents(id).unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if id == ancestor {
+                return true;
+            }
+            let below = graph
+                .generation(id)
+                .is_some_and(|generation| generation > 0 && generation <= target);
+            if checked.insert(id) && !below {
+                stack.extend(self.parents(id).unwrap_or_default());
+            }
+        }
+        false
+    }
+
+    fn fork(&self) -> Option<Box<dyn ProgramSource + Send>> {
+        Some(Box::new(Self {
+            repo: git::Repository::open(self.repo.path()).ok()?,
+            graph: self.graph.clone(),
+            shallow: self.shallow.clone(),
+        }))
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{Oid, ProgramSource};
+use hashbrown::HashSet;
+use std::fmt;
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    program: HashSet<Oid>,
+    executed: HashSet<Oid>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_program(&mut self, program: HashSet<Oid>) {
+        self.program = program;
+    }
+
+    pub fn record(&mut self, id: Oid) {
+        self.executed.insert(id);
+    }
+
+    pub fn unexecuted(&self) -> Vec<Oid> {
+        let mut unexecuted = self
+            .program
+            .difference(&self.executed)
+            .copied()
+            .collect::<Vec<_>>();
+        unexecuted.sort();
+        unexecuted
+    }
+
+    // The commits that were never executed, with the first line of each
+    // one's message.
+    pub fn report(&self, source: &dyn ProgramSource) -> Report {
+        let unexecuted = self
+            .unexecuted()
+            .into_iter()
+            .map(|id| {
+                let message = source.message(id).unwrap_or_default();
+                let summary = message.lines().next().unwrap_or_default().to_owned();
+                (id, summary)
+            })
+            .collect::<Vec<_>>();
+        Report {
+            executed: self.program.len() - unexecuted.len(),
+            total: self.program.len(),
+            unexecuted,
+        }
+    }
+}
+
+pub struct Report {
+    pub executed: usize,
+    pub total: usize,
+    pub unexecuted: Vec<(Oid, String)>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "coverage: {} of {} commits executed",
+            self.executed, self.total
+        )?;
+        for (id, summary) in &self.unexecuted {
+            writeln!(f, "never executed: {} {}", id, summary)?;
+        }
+        Ok(())
+    }
+}
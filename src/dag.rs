@@ -0,0 +1,436 @@
+use crate::source::{Oid, ProgramSource};
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+use serde_json::Value;
+
+// A program's commit graph held entirely in memory, for running programs
+// where no repository is available, such as in the browser, or building them
+// without creating commits. It is serialized as JSON, listing each commit as a
+// node along with the nodes that follow it:
+//
+//     {
+//         "nodes": [
+//             { "id": "start", "op": "set n #0", "children": ["end"] },
+//             { "id": "end", "op": "println $n" }
+//         ],
+//         "tags": { "_start": "start", "_end": "end" }
+//     }
+//
+// Ids are any strings unique among the nodes, and nodes are listed oldest
+// first, which is the order children are taken in by the traversal modes that
+// sort by time. Children that close a cycle loop the same way grafts do.
+#[derive(Debug, Default)]
+pub struct Dag {
+    commits: HashMap<Oid, DagCommit>,
+    tags: HashMap<String, Oid>,
+    replacements: HashMap<Oid, Oid>,
+}
+
+#[derive(Debug)]
+struct DagCommit {
+    message: String,
+    parents: Vec<Oid>,
+    time: i64,
+}
+
+impl Dag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a commit, returning its id. Ids are numbered in the order commits
+    // are added, as are their times, so children are taken in that order by
+    // the traversal modes that sort by time.
+    pub fn add_commit(&mut self, message: impl Into<String>, parents: &[Oid]) -> Oid {
+        let mut n = self.commits.len() as u64;
+        let id = loop {
+            let mut bytes = [0; 20];
+            bytes[12..].copy_from_slice(&n.to_be_bytes());
+            let id = Oid::from_bytes(bytes);
+            if !self.commits.contains_key(&id) {
+                break id;
+            }
+            n += 1;
+        };
+        self.commits.insert(
+            id,
+            DagCommit {
+                message: message.into(),
+                parents: parents.to_vec(),
+                time: n as i64,
+            },
+        );
+        id
+    }
+
+    pub fn add_tag(&mut self, name: impl Into<String>, id: Oid) {
+        self.tags.insert(name.into(), id);
+    }
+
+    // Makes `replace` stand in for `id`, as `git replace` does.
+    pub fn add_replacement(&mut self, id: Oid, replace: Oid) {
+        self.replacements.insert(id, replace);
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        fn id(json: &Value) -> Result<String> {
+            json.as_str()
+                .map(str::to_owned)
+                .with_context(|| format!("expected an id, found {}", json))
+        }
+
+        let json: Value = serde_json::from_str(json)?;
+        let mut graph = Graph::default();
+        let nodes = json["nodes"].as_array().context("missing nodes")?;
+        for node in nodes {
+            let node_id = id(&node["id"])?;
+            anyhow::ensure!(
+                !graph.labels.contains_key(&node_id),
+                "{}: duplicate node",
+                node_id
+            );
+            graph.node(&node_id);
+            let op = node["op"].as_str().unwrap_or_default();
+            graph.labels.insert(node_id, op.to_owned());
+        }
+        for node in nodes {
+            let node_id = id(&node["id"])?;
+            let children = match &node["children"] {
+                Value::Array(children) => children.iter().map(id).collect::<Result<Vec<_>>>()?,
+                Value::Null => Vec::new(),
+                children => anyhow::bail!("{}: invalid children: {}", node_id, children),
+            };
+            for child in &children {
+                anyhow::ensure!(
+                    graph.labels.contains_key(child),
+                    "{}: node not found",
+                    child
+                );
+            }
+            graph.children.entry(node_id).or_default().extend(children);
+        }
+        if let Some(tags) = json["tags"].as_object() {
+            for (name, target) in tags {
+                let target = id(target)?;
+                graph
+                    .tags
+                    .get_mut(&target)
+                    .with_context(|| format!("{}: node not found", target))?
+                    .push(name.clone());
+            }
+        }
+        Ok(graph.to_dag())
+    }
+
+    // Node ids are those of the commits, with each replaced commit standing in
+    // for its replacement.
+    pub fn to_json(&self) -> String {
+        let replaced = |mut id: Oid| {
+            while let Some(&replace) = self.replacements.get(&id) {
+                id = replace;
+            }
+            id
+        };
+        let replacing = self.replacements.values().collect::<HashSet<_>>();
+        let mut nodes = self
+            .commits
+            .keys()
+            .copied()
+            .filter(|id| !replacing.contains(id))
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|&id| (self.commits[&id].time, id.to_string()));
+
+        let mut children = HashMap::<Oid, Vec<Oid>>::new();
+        for &id in &nodes {
+            for &parent in &self.commits[&replaced(id)].parents {
+                children.entry(parent).or_default().push(id);
+            }
+        }
+        let nodes = nodes
+            .iter()
+            .map(|id| {
+                let children = children
+                    .get(id)
+                    .into_iter()
+                    .flatten()
+                    .map(Oid::to_string)
+                    .collect::<Vec<_>>();
+                serde_json::json!({
+                    "id": id.to_string(),
+                    "op": self.commits[&replaced(*id)].message.trim_end(),
+                    "children": children,
+                })
+            })
+            .collect::<Vec<_>>();
+        let tags = self
+            .tags
+            .iter()
+            .map(|(name, id)| (name.clone(), Value::from(id.to_string())))
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::json!({ "nodes": nodes, "tags": tags }).to_string()
+    }
+
+    // Writes the program into `repo` as commits of the empty tree, tagging
+    // them and grafting loops with replace refs.
+    #[cfg(feature = "git")]
+    pub fn write_to(&self, repo: &git::Repository) -> Result<()> {
+        let tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+        let mut written = HashMap::<Oid, git::Oid>::new();
+        let mut ids = self.commits.keys().copied().collect::<Vec<_>>();
+        ids.sort_by_key(|&id| (self.commits[&id].time, id.to_string()));
+        for id in ids {
+            // Parents are written before their children.
+            let mut stack = vec![id];
+            while let Some(&id) = stack.last() {
+                if written.contains_key(&id) {
+                    stack.pop();
+                    continue;
+                }
+                let commit = self.commit(id)?;
+                let pending = commit
+                    .parents
+                    .iter()
+                    .filter(|parent| !written.contains_key(*parent))
+                    .copied()
+                    .collect::<Vec<_>>();
+                if !pending.is_empty() {
+                    anyhow::ensure!(
+                        pending.iter().all(|parent| !stack.contains(parent)),
+                        "{}: commit is its own ancestor",
+                        id
+                    );
+                    stack.extend(pending);
+                    continue;
+                }
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(|parent| repo.find_commit(written[parent]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let signature =
+                    git::Signature::new("undag", "undag", &git::Time::new(commit.time, 0))?;
+                let message = match commit.message.as_str() {
+                    "" => String::new(),
+                    message => format!("{}\n", message.trim_end()),
+                };
+                let oid = repo.commit(
+                    None,
+                    &signature,
+                    &signature,
+                    &message,
+                    &tree,
+                    &parents.iter().collect::<Vec<_>>(),
+                )?;
+                written.insert(id, oid);
+                stack.pop();
+            }
+        }
+        for (name, id) in &self.tags {
+            repo.reference(&format!("refs/tags/{}", name), written[id], true, "undag")?;
+        }
+        for (id, replace) in &self.replacements {
+            let name = format!("refs/replace/{}", written[id]);
+            repo.reference(&name, written[replace], true, "undag")?;
+        }
+        Ok(())
+    }
+
+    // Copies every commit reachable from a tag or a replacement out of `source`.
+    pub fn export(source: &dyn ProgramSource, tags: &[String]) -> Result<Self> {
+        let mut dag = Self::default();
+        let mut stack = Vec::new();
+        for name in tags {
+            if let Some(id) = source.tag(name) {
+                dag.tags.insert(name.clone(), id);
+                stack.push(id);
+            }
+        }
+        for (id, replace) in source.replacements()? {
+            dag.replacements.insert(id, replace);
+            stack.extend([id, replace]);
+        }
+        while let Some(id) = stack.pop() {
+            if dag.commits.contains_key(&id) {
+                continue;
+            }
+            let commit = DagCommit {
+                message: source.message(id).unwrap_or_default(),
+                parents: source.parents(id)?,
+                time: source.time(id)?,
+            };
+            stack.extend(commit.parents.iter().copied());
+            dag.commits.insert(id, commit);
+        }
+        Ok(dag)
+    }
+}
+
+impl ProgramSource for Dag {
+    fn message(&self, id: Oid) -> Result<String> {
+        self.commit(id).map(|commit| commit.message.clone())
+    }
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+        self.commit(id).map(|commit| commit.parents.clone())
+    }
+
+    fn time(&self, id: Oid) -> Result<i64> {
+        self.commit(id).map(|commit| commit.time)
+    }
+
+    fn tag(&self, name: &str) -> Option<Oid> {
+        if let Some(&id) = self.tags.get(name) {
+            return Some(id);
+        }
+        if !crate::source::is_abbreviated_id(name) {
+            return None;
+        }
+        // The abbreviation must be unambiguous.
+        let name = name.to_ascii_lowercase();
+        let mut matches = self
+            .commits
+            .keys()
+            .filter(|id| id.to_string().starts_with(&name));
+        match (matches.next(), matches.next()) {
+            (Some(&id), None) => Some(id),
+            _ => None,
+        }
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        Ok(self.tags.keys().cloned().collect())
+    }
+
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+        Ok(self
+            .replacements
+            .iter()
+            .map(|(&id, &replace)| (id, replace))
+            .collect())
+    }
+}
+
+impl Dag {
+    fn commit(&self, id: Oid) -> Result<&DagCommit> {
+        self.commits
+            .get(&id)
+            .with_context(|| format!("{}: commit not found", id))
+    }
+}
+
+// A program as a graph of ops, in which edges may form cycles, read from the
+// formats that describe programs by their nodes' children.
+#[derive(Default)]
+pub(crate) struct Graph {
+    // Nodes in the order they first appear.
+    pub order: Vec<String>,
+    pub labels: HashMap<String, String>,
+    pub tags: HashMap<String, Vec<String>>,
+    pub children: HashMap<String, Vec<String>>,
+}
+
+impl Graph {
+    // Adds a node labelled with its id, unless it already exists.
+    pub fn node(&mut self, id: &str) {
+        if !self.labels.contains_key(id) {
+            self.order.push(id.to_owned());
+            self.labels.insert(id.to_owned(), id.to_owned());
+            self.tags.insert(id.to_owned(), Vec::new());
+        }
+    }
+
+    // Builds the commit graph, breaking cycles at the edges that close them.
+    pub fn to_dag(&self) -> Dag {
+        // Edges found to lead back to a node already on the depth-first search
+        // stack are the ones closing cycles. Searching from `_start` first keeps
+        // it the first commit of any loop through it.
+        let mut roots = self.order.clone();
+        if let Some(start) = self
+            .order
+            .iter()
+            .position(|node| self.tags[node].iter().any(|tag| tag == "_start"))
+        {
+            roots.rotate_left(start);
+        }
+        let mut back_edges = HashSet::new();
+        let mut visited = HashSet::new();
+        for root in &roots {
+            if !visited.insert(root) {
+                continue;
+            }
+            let mut on_stack = std::iter::once(root).collect::<HashSet<_>>();
+            let mut stack = vec![(root, 0)];
+            while let Some((node, i)) = stack.last_mut() {
+                let node = *node;
+                match self
+                    .children
+                    .get(node)
+                    .and_then(|children| children.get(*i))
+                {
+                    Some(child) => {
+                        *i += 1;
+                        if on_stack.contains(child) {
+                            back_edges.insert((node, child));
+                        } else if visited.insert(child) {
+                            on_stack.insert(child);
+                            stack.push((child, 0));
+                        }
+                    }
+                    None => {
+                        on_stack.remove(node);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        // Each node's parents, and those left once cycles are broken.
+        let mut parents = HashMap::<&String, Vec<&String>>::new();
+        let mut forward_parents = HashMap::<&String, Vec<&String>>::new();
+        for node in &self.order {
+            for child in self.children.get(node).into_iter().flatten() {
+                parents.entry(child).or_default().push(node);
+                if !back_edges.contains(&(node, child)) {
+                    forward_parents.entry(child).or_default().push(node);
+                }
+            }
+        }
+
+        // Commits are added parents first, otherwise in the order nodes appear,
+        // which is then also the order of their commit times.
+        let mut dag = Dag::new();
+        let mut ids = HashMap::<&String, Oid>::new();
+        while ids.len() < self.order.len() {
+            let (node, parent_ids) = self
+                .order
+                .iter()
+                .filter(|&node| !ids.contains_key(node))
+                .find_map(|node| {
+                    let parents = forward_parents.get(node).map_or(&[][..], Vec::as_slice);
+                    let parent_ids = parents
+                        .iter()
+                        .map(|&parent| ids.get(parent).copied())
+                        .collect::<Option<Vec<_>>>()?;
+                    Some((node, parent_ids))
+                })
+                .unwrap();
+            ids.insert(node, dag.add_commit(&self.labels[node], &parent_ids));
+        }
+        for node in &self.order {
+            if let Some(parents) = parents.get(node) {
+                if parents.len() != forward_parents.get(node).map_or(0, Vec::len) {
+                    let parent_ids = parents
+                        .iter()
+                        .map(|&parent| ids[parent])
+                        .collect::<Vec<_>>();
+                    let replace = dag.add_commit(&self.labels[node], &parent_ids);
+                    dag.add_replacement(ids[node], replace);
+                }
+            }
+            for tag in &self.tags[node] {
+                dag.add_tag(tag, ids[node]);
+            }
+        }
+        dag
+    }
+}
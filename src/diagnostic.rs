@@ -0,0 +1,48 @@
+use crate::{Oid, Val};
+use std::fmt;
+
+// What was known about a program when it failed, for tracking down the cause
+// in a large graph.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub commit: Oid,
+    pub message: String,
+    // The op as parsed, written in its canonical form as `undag fmt` writes
+    // it, or `None` if the message failed to parse.
+    pub op: Option<String>,
+    // The variables the op reads, with their values at the time.
+    pub vars: Vec<(String, Option<Val>)>,
+    // The most recently executed commits, oldest first, ending with `commit`.
+    pub recent: Vec<(Oid, String)>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "recent commits (oldest first):")?;
+        for (id, summary) in &self.recent {
+            writeln!(f, "    {} {}", id.short(), summary)?;
+        }
+        writeln!(f, "failed at commit {}:", self.commit)?;
+        for line in self.message.trim_end().lines() {
+            match line {
+                "" => writeln!(f)?,
+                line => writeln!(f, "    {}", line)?,
+            }
+        }
+        // Only an op written differently from the message says anything new.
+        if let Some(op) = self.op.as_ref().filter(|op| **op != self.message.trim()) {
+            writeln!(f, "parsed as {}", op)?;
+        }
+        if !self.vars.is_empty() {
+            writeln!(f, "variables:")?;
+            for (name, val) in &self.vars {
+                match val {
+                    Some(Val::Str(s)) => writeln!(f, "    {} = {:?}", name, s)?,
+                    Some(val) => writeln!(f, "    {} = {}", name, val)?,
+                    None => writeln!(f, "    {} is undefined", name)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
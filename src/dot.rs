@@ -0,0 +1,288 @@
+use crate::dag::{Dag, Graph};
+use crate::interp::{Get, Op};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+
+// Programs can be drawn as Graphviz digraphs rather than committed, with each
+// node's label holding its op and edges leading from parent to child:
+//
+//     digraph {
+//         a [label="set n #0", tag="_start"];
+//         b [label="println $n", tag="_end"];
+//         a -> b;
+//     }
+//
+// A node without a label uses its id as its op, and `tag` lists the node's
+// tags separated by spaces or commas. Edges that close a cycle become
+// replacements, the same way loops are made with `git replace --graft`.
+// Dashed edges are jumps drawn by `to_dot` and are not part of the program.
+pub fn from_dot(text: &str) -> Result<Dag> {
+    Ok(Parser::new(text)?.graph()?.to_dag())
+}
+
+// Draws the program between `start` and `end` as a digraph that `from_dot`
+// reads back. Nodes are named by short commit ids, or those of the commits
+// they replace, and labelled with their ops and the tags naming them, while a
+// `branch` to a literal tag is also drawn as a dashed edge to its target.
+pub fn to_dot(source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<String> {
+    let replacements = Replacements::load(source)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(source, &replacements, start, end)?;
+    let order = tree::walk(&children, start);
+    // Ids are shortened as far as they stay unique, as `git log` does.
+    let ids = order
+        .iter()
+        .map(|&id| (id, replacements.original(id).to_string()))
+        .collect::<HashMap<_, _>>();
+    let len = (7..40)
+        .find(|&len| {
+            let mut names = ids.values().map(|id| &id[..len]).collect::<Vec<_>>();
+            names.sort_unstable();
+            names.dedup();
+            names.len() == ids.len()
+        })
+        .unwrap_or(40);
+    let name = |id: Oid| ids[&id][..len].to_owned();
+
+    let mut node_tags = HashMap::<Oid, Vec<String>>::new();
+    let mut tag = |name: &str, id: Option<Oid>| {
+        if let Some(id) = id.map(|id| replacements.resolve(id)) {
+            let tags = node_tags.entry(id).or_default();
+            if !tags.iter().any(|tag| tag == name) {
+                tags.push(name.to_owned());
+            }
+        }
+    };
+    let mut names = source.tags()?;
+    names.sort();
+    for name in names {
+        tag(&name, source.tag(&name));
+    }
+    let mut jumps = Vec::new();
+    for &id in &order {
+        let message = source.message(id)?;
+        if let Ok(Op::Branch(Get::Val(target))) = message.parse() {
+            let target = target.to_string();
+            if let Some(to) = source.tag(&target) {
+                tag(&target, Some(to));
+                jumps.push((id, replacements.resolve(to)));
+            }
+        }
+    }
+
+    let mut dot = String::from("digraph {\n    node [shape=box];\n");
+    for &id in &order {
+        let message = source.message(id)?;
+        dot += &format!("    \"{}\" [label={}", name(id), quote(message.trim_end()));
+        if let Some(tags) = node_tags.get(&id) {
+            dot += &format!(", tag={}", quote(&tags.join(" ")));
+        }
+        dot += "];\n";
+    }
+    for &id in &order {
+        for next in children.get(&id).into_iter().flatten() {
+            dot += &format!("    \"{}\" -> \"{}\";\n", name(id), name(next.id));
+        }
+    }
+    for (from, to) in jumps {
+        if order.contains(&to) {
+            dot += &format!(
+                "    \"{}\" -> \"{}\" [style=dashed];\n",
+                name(from),
+                name(to)
+            );
+        }
+    }
+    dot += "}\n";
+    Ok(dot)
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Id(String),
+    Punct(&'static str),
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => {}
+                '#' => {
+                    chars.by_ref().find(|&c| c == '\n');
+                }
+                '/' if chars.peek() == Some(&'/') => {
+                    chars.by_ref().find(|&c| c == '\n');
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    let mut prev = ' ';
+                    for c in chars.by_ref() {
+                        if prev == '*' && c == '/' {
+                            break;
+                        }
+                        prev = c;
+                    }
+                }
+                '"' => {
+                    let mut s = String::new();
+                    loop {
+                        match chars.next().context("unterminated string")? {
+                            '"' => break,
+                            '\\' if matches!(chars.peek(), Some('"' | '\\')) => {
+                                s.push(chars.next().unwrap())
+                            }
+                            c => s.push(c),
+                        }
+                    }
+                    tokens.push(Token::Id(s));
+                }
+                '-' if chars.peek() == Some(&'>') => {
+                    chars.next();
+                    tokens.push(Token::Punct("->"));
+                }
+                '-' if chars.peek() == Some(&'-') => {
+                    anyhow::bail!("undirected edges are not supported")
+                }
+                '{' => tokens.push(Token::Punct("{")),
+                '}' => tokens.push(Token::Punct("}")),
+                '[' => tokens.push(Token::Punct("[")),
+                ']' => tokens.push(Token::Punct("]")),
+                ';' => tokens.push(Token::Punct(";")),
+                ',' => tokens.push(Token::Punct(",")),
+                '=' => tokens.push(Token::Punct("=")),
+                c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                    let mut s = c.to_string();
+                    while let Some(&c) = chars
+                        .peek()
+                        .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+                    {
+                        s.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Id(s));
+                }
+                c => anyhow::bail!("unexpected character: {:?}", c),
+            }
+        }
+        Ok(Self {
+            tokens: tokens.into_iter().peekable(),
+        })
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.tokens.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.tokens.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        anyhow::ensure!(
+            self.eat(punct),
+            "expected {:?}, found {:?}",
+            punct,
+            self.tokens.peek()
+        );
+        Ok(())
+    }
+
+    fn id(&mut self) -> Result<String> {
+        match self.tokens.next() {
+            Some(Token::Id(id)) => Ok(id),
+            token => anyhow::bail!("expected an id, found {:?}", token),
+        }
+    }
+
+    fn attrs(&mut self) -> Result<Vec<(String, String)>> {
+        let mut attrs = Vec::new();
+        while self.eat("[") {
+            while !self.eat("]") {
+                let key = self.id()?;
+                self.expect("=")?;
+                attrs.push((key, self.id()?));
+                let _ = self.eat(",") || self.eat(";");
+            }
+        }
+        Ok(attrs)
+    }
+
+    fn graph(&mut self) -> Result<Graph> {
+        if matches!(self.tokens.peek(), Some(Token::Id(id)) if id == "strict") {
+            self.tokens.next();
+        }
+        match self.id()?.as_str() {
+            "digraph" => {}
+            "graph" => anyhow::bail!("undirected graphs are not supported"),
+            kind => anyhow::bail!("expected digraph, found {:?}", kind),
+        }
+        if !self.eat("{") {
+            self.id()?;
+            self.expect("{")?;
+        }
+
+        let mut graph = Graph::default();
+        while !self.eat("}") {
+            let id = self.id()?;
+            match id.as_str() {
+                "graph" | "node" | "edge" => {
+                    self.attrs()?;
+                }
+                "subgraph" => anyhow::bail!("subgraphs are not supported"),
+                _ if self.eat("=") => {
+                    self.id()?;
+                }
+                _ => {
+                    let mut nodes = vec![id];
+                    while self.eat("->") {
+                        nodes.push(self.id()?);
+                    }
+                    let attrs = self.attrs()?;
+                    for node in &nodes {
+                        graph.node(node);
+                    }
+                    let dashed = attrs
+                        .iter()
+                        .any(|(key, value)| key == "style" && value == "dashed");
+                    for pair in nodes.windows(2).filter(|_| !dashed) {
+                        graph
+                            .children
+                            .entry(pair[0].clone())
+                            .or_default()
+                            .push(pair[1].clone());
+                    }
+                    // Other attributes of edges have no meaning to programs.
+                    if let [node] = nodes.as_slice() {
+                        for (key, value) in attrs {
+                            match key.as_str() {
+                                "label" => drop(graph.labels.insert(node.clone(), value)),
+                                "tag" => graph.tags.get_mut(node).unwrap().extend(
+                                    value
+                                        .split(|c: char| c == ',' || c.is_whitespace())
+                                        .filter(|tag| !tag.is_empty())
+                                        .map(str::to_owned),
+                                ),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            self.eat(";");
+        }
+        Ok(graph)
+    }
+}
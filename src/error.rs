@@ -0,0 +1,63 @@
+use crate::{Oid, Val};
+use thiserror::Error;
+
+// The errors a program can fail with. Library functions still return
+// `anyhow::Result`, so these are found with `anyhow::Error::downcast_ref`,
+// and errors raised while executing a commit carry its `Oid` as context.
+#[derive(Debug, Error)]
+pub enum UndagError {
+    #[error("undefined symbol: {0}")]
+    UndefinedSymbol(String),
+    #[error("tried to access non-table as table: {0}")]
+    NotATable(String),
+    #[error("table nesting too deep: {depth} exceeds the limit of {limit}")]
+    NestingTooDeep { depth: usize, limit: usize },
+    #[error("{op}: invalid args: {}", .args.iter().map(Val::to_string).collect::<Vec<_>>().join(" "))]
+    TypeMismatch { op: String, args: Vec<Val> },
+    #[error("{oid}: {message}")]
+    SyntaxError { oid: Oid, message: String },
+    #[error("invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("{var} is read-only")]
+    ReadOnly { var: String },
+    #[error("{op}: not running a program")]
+    NoProgram { op: String },
+    #[error("program is read-only")]
+    ReadOnlyProgram,
+    #[error("clock: no clock is available")]
+    NoClock,
+    #[error("meta: unknown field: {field}")]
+    UnknownField { field: String },
+    #[error("too many references: {var} passes through more than {limit}")]
+    RefDepth { var: String, limit: usize },
+    #[error("{op}: result would be longer than {limit} bytes")]
+    TooLong { op: String, limit: usize },
+    #[error("attempt to divide by zero")]
+    DivisionByZero,
+    #[error("{oid}: branch: failed to find target: {tag}")]
+    BranchNotFound { oid: Oid, tag: String },
+    #[error("{oid}: failed to find child to continue")]
+    NoChild { oid: Oid },
+    #[error("{oid}: commit is not part of the program")]
+    NotInProgram { oid: Oid },
+    #[error(
+        "{oid}: history is cut off by a shallow clone; fetch the rest with `git fetch --unshallow`"
+    )]
+    ShallowHistory { oid: Oid },
+    #[error("{oid}: refusing to execute commit without a valid signature: {reason}")]
+    BadSignature { oid: Oid, reason: String },
+    #[error("{oid}: executed {times} times without any input, output or variable changing")]
+    StuckLoop { oid: Oid, times: usize },
+    #[error("missing {tag} tag\n{help}")]
+    MissingTag { tag: String, help: String },
+    #[error("{end}: end of the program is not reached from its start, {start}: {reason}")]
+    EndNotReached {
+        start: Oid,
+        end: Oid,
+        reason: String,
+    },
+    #[error("no program is running")]
+    NotRunning,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use undag::asm;
+
+// The programs in the `examples` directory, built into the binary so that
+// they can be tried without a checkout.
+const EXAMPLES: &[(&str, &str)] = &[
+    ("hello-world", include_str!("../examples/hello-world.undag")),
+    (
+        "greet-by-name",
+        include_str!("../examples/greet-by-name.undag"),
+    ),
+    (
+        "greet-alice-or-bob",
+        include_str!("../examples/greet-alice-or-bob.undag"),
+    ),
+    (
+        "foo-bar-ping-pong",
+        include_str!("../examples/foo-bar-ping-pong.undag"),
+    ),
+    ("counter", include_str!("../examples/counter.undag")),
+    ("fibonacci", include_str!("../examples/fibonacci.undag")),
+    ("collection", include_str!("../examples/collection.undag")),
+];
+
+// Lists the examples with what each does, taken from the comment it starts
+// with.
+pub fn list() {
+    let width = EXAMPLES
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, text) in EXAMPLES {
+        println!("{:width$}  {}", name, description(text), width = width);
+    }
+}
+
+// Runs the example called `name` on stdin and stdout, or writes it into the
+// repository at `write` instead.
+pub fn run(name: &str, write: Option<&Path>) -> Result<()> {
+    let text = EXAMPLES
+        .iter()
+        .find(|(example, _)| *example == name)
+        .map(|(_, text)| text)
+        .with_context(|| {
+            let names = EXAMPLES.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            format!("no example named {}; try one of {}", name, names.join(", "))
+        })?;
+    let dag = asm::assemble(text).with_context(|| format!("invalid example: {}", name))?;
+    if let Some(repo) = write {
+        super::write_program(&dag, repo)?;
+        eprintln!("wrote {} to {}", name, repo.display());
+        return Ok(());
+    }
+    let (start, end) = undag::endpoints(&dag)?;
+    let mut instance = undag::Instance::new();
+    instance.set_seed(rand::random());
+    instance.run(
+        &dag,
+        start,
+        end,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    )
+}
+
+fn description(text: &str) -> String {
+    text.lines()
+        .map_while(|line| line.strip_prefix('#'))
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
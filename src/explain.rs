@@ -0,0 +1,131 @@
+use crate::interp::Hook;
+use crate::tree::{Ancestry, Child};
+use crate::Oid;
+use std::fmt;
+
+// How a `branch` chose where to go: the commit its tag resolved to, where the
+// search of the tag's history reached each child, and why the chosen one won.
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub from: Oid,
+    pub tag: String,
+    pub to: Oid,
+    // The commit the tag resolved to, or `None` if it does not exist.
+    pub resolved: Option<Oid>,
+    // Each child with the depth of the search when it reached the child and
+    // how many commits it had reached before, if it did.
+    pub children: Vec<(Oid, Option<(usize, usize)>)>,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    OnlyChild { depth: usize },
+    Nearest { depth: usize },
+    // Another child was found at the same depth, but later.
+    ReachedFirst { depth: usize },
+    // No child is in the tag's history, so the module is run from the tag.
+    Module { namespace: String },
+}
+
+impl Explanation {
+    pub fn new(
+        from: Oid,
+        tag: &str,
+        to: Oid,
+        ancestry: Option<&Ancestry>,
+        children: &[Child],
+    ) -> Self {
+        let reached = |id| ancestry.and_then(|ancestry| ancestry.reached(id));
+        let reason = match reached(to) {
+            Some((depth, _)) if children.len() == 1 => Reason::OnlyChild { depth },
+            Some((depth, _)) => {
+                let tied = children
+                    .iter()
+                    .filter(|child| child.id != to)
+                    .any(|child| reached(child.id).is_some_and(|(d, _)| d == depth));
+                match tied {
+                    false => Reason::Nearest { depth },
+                    true => Reason::ReachedFirst { depth },
+                }
+            }
+            None => Reason::Module {
+                namespace: tag
+                    .rsplit_once("::")
+                    .map_or(tag, |(namespace, _)| namespace)
+                    .to_owned(),
+            },
+        };
+        Self {
+            from,
+            tag: tag.to_owned(),
+            to,
+            resolved: ancestry.map(Ancestry::tag),
+            children: children
+                .iter()
+                .map(|child| (child.id, reached(child.id)))
+                .collect(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "branch {} at {}", self.tag, self.from)?;
+        match self.resolved {
+            Some(resolved) => writeln!(f, "  tag resolves to {}", resolved)?,
+            None => writeln!(f, "  tag does not exist")?,
+        }
+        for (id, reached) in &self.children {
+            match reached {
+                Some((depth, order)) => writeln!(
+                    f,
+                    "  child {}: depth {}, reached after {} others",
+                    id, depth, order
+                )?,
+                None => writeln!(f, "  child {}: not in the tag's history", id)?,
+            }
+        }
+        writeln!(f, "  chose {}: {}", self.to, self.reason)
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OnlyChild { depth } => write!(f, "the only child, at depth {}", depth),
+            Self::Nearest { depth } => write!(f, "nearest the tag, at depth {}", depth),
+            Self::ReachedFirst { depth } => {
+                write!(f, "reached first of the children at depth {}", depth)
+            }
+            Self::Module { namespace } => write!(
+                f,
+                "no child is in the tag's history, so module {} is run from the tag",
+                namespace
+            ),
+        }
+    }
+}
+
+// Hands `f` an explanation of each `branch` as it is taken.
+pub struct BranchExplainer<F>(F);
+
+impl<F: FnMut(Explanation)> BranchExplainer<F> {
+    pub fn new(f: F) -> Self {
+        Self(f)
+    }
+}
+
+impl<F: FnMut(Explanation)> Hook for BranchExplainer<F> {
+    fn explain_branch(
+        &mut self,
+        from: Oid,
+        tag: &str,
+        to: Oid,
+        ancestry: Option<&Ancestry>,
+        children: &[Child],
+    ) {
+        (self.0)(Explanation::new(from, tag, to, ancestry, children));
+    }
+}
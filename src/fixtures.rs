@@ -0,0 +1,80 @@
+use crate::{Instance, Oid, Order, ProgramSource};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+// A test case for a program: the output it should print when given `input`,
+// read from `<name>.out` and `<name>.in` in a test directory.
+pub struct Fixture {
+    pub name: String,
+    pub input: Vec<u8>,
+    pub expected: String,
+}
+
+// The fixtures in `dir`, sorted by name. Every `.out` file is one, and its
+// `.in` file is optional.
+pub fn discover(dir: &Path) -> Result<Vec<Fixture>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read tests: {}", dir.display()))?;
+    let mut fixtures = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "out") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .with_context(|| format!("invalid test name: {}", path.display()))?
+            .to_owned();
+        let expected = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let input = match std::fs::read(path.with_extension("in")) {
+            Ok(input) => input,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read input of {}", name)),
+        };
+        fixtures.push(Fixture {
+            name,
+            input,
+            expected,
+        });
+    }
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+impl Fixture {
+    // Runs the program on the fixture's input, failing with the first line it
+    // prints that differs from what is expected. Random choices of children
+    // are seeded the same way on every run.
+    pub fn run(
+        &self,
+        source: &dyn ProgramSource,
+        start: Oid,
+        end: Oid,
+        order: Order,
+    ) -> Result<()> {
+        let mut instance = Instance::new();
+        instance.set_order(order);
+        instance.set_seed(0);
+        let mut output = Vec::new();
+        instance.run(source, start, end, &mut &self.input[..], &mut output)?;
+        let actual = String::from_utf8_lossy(&output);
+
+        let mut expected_lines = self.expected.lines();
+        let mut actual_lines = actual.lines();
+        for line in 1.. {
+            match (expected_lines.next(), actual_lines.next()) {
+                (None, None) => break,
+                (expected, actual) if expected == actual => {}
+                (expected, actual) => anyhow::bail!(
+                    "line {}: expected {}, got {}",
+                    line,
+                    expected.map_or("end of output".to_owned(), |l| format!("{:?}", l)),
+                    actual.map_or("end of output".to_owned(), |l| format!("{:?}", l)),
+                ),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use crate::interp::Op;
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+
+// Rewrites the message of every commit between `start` and `end` into the
+// canonical form of its op. Each commit whose message changes is replaced,
+// with `git replace`, by a copy with the new message, so that history is left
+// as it was. Returns the commits whose messages changed along with their new
+// messages, without replacing anything if `dry_run` is set.
+pub fn format(
+    repo: &git::Repository,
+    start: Oid,
+    end: Oid,
+    dry_run: bool,
+) -> Result<Vec<(Oid, String)>> {
+    let replacements = Replacements::load(repo)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(repo, &replacements, start, end)?;
+
+    let mut changed = Vec::new();
+    for id in tree::walk(&children, start) {
+        let message = ProgramSource::message(repo, id)?;
+        // Commits that fail to parse are left for `check` to report.
+        let formatted = match message.parse::<Op>() {
+            Ok(op) => op.to_string(),
+            Err(_) => continue,
+        };
+        if formatted == message.trim_end() {
+            continue;
+        }
+        let original = replacements.original(id);
+        if !dry_run {
+            replace(repo, id, original, Some(&formatted), None, "undag fmt")
+                .with_context(|| format!("{}: failed to replace commit", original))?;
+        }
+        changed.push((original, formatted));
+    }
+    Ok(changed)
+}
+
+// Replaces `original` with a copy of `id`, which is either `original` or what
+// it is already replaced by, that has `message` as its message and `parents`
+// as its parents where they are given.
+pub(crate) fn replace(
+    repo: &git::Repository,
+    id: Oid,
+    original: Oid,
+    message: Option<&str>,
+    parents: Option<&[Oid]>,
+    log: &str,
+) -> Result<()> {
+    let commit = repo.find_commit(id.into())?;
+    let message = match message {
+        Some(message) => format!("{}\n", message),
+        None => String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+    };
+    let parents = match parents {
+        Some(parents) => parents
+            .iter()
+            .map(|&parent| repo.find_commit(parent.into()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => commit.parents().collect(),
+    };
+    let copy = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &message,
+        &commit.tree()?,
+        &parents.iter().collect::<Vec<_>>(),
+    )?;
+    repo.reference(&format!("refs/replace/{}", original), copy, true, log)?;
+    Ok(())
+}
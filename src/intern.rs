@@ -0,0 +1,30 @@
+use hashbrown::HashSet;
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+
+// Names that are no longer used anywhere else are dropped once the interner
+// has grown to twice the size it had after the last time they were.
+const MIN_PRUNE: usize = 1024;
+
+thread_local! {
+    static NAMES: RefCell<HashSet<Arc<str>>> = RefCell::new(HashSet::new());
+    static PRUNE_AT: Cell<usize> = const { Cell::new(MIN_PRUNE) };
+}
+
+// The shared copy of `name`, so that variable names and table keys that are
+// used over and over are only allocated the first time.
+pub fn intern(name: &str) -> Arc<str> {
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(name) = names.get(name) {
+            return name.clone();
+        }
+        if names.len() >= PRUNE_AT.get() {
+            names.retain(|name| Arc::strong_count(name) > 1);
+            PRUNE_AT.set((names.len() * 2).max(MIN_PRUNE));
+        }
+        let name = Arc::<str>::from(name);
+        names.insert(name.clone());
+        name
+    })
+}
@@ -1,26 +1,129 @@
-use crate::replace;
+use crate::coverage::Coverage;
+use crate::diagnostic::Diagnostic;
+use crate::intern::intern;
+use crate::program::Program;
+use crate::state::Checkpoint;
+use crate::tree;
+use crate::{Oid, ProgramSource, Replacements, UndagError};
 use anyhow::{Context, Result};
-use git::{Commit, Repository};
+use hashbrown::hash_map::Entry;
 use hashbrown::{HashMap, HashSet};
-use std::io::{Stdin, Stdout, Write};
+use num_bigint::{BigInt, Sign};
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 
-type Table = HashMap<String, Val>;
+// Keys are interned, so that the same few names are not allocated again on
+// every write.
+pub type Table = HashMap<Arc<str>, Val>;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub enum Val {
     Int(i64),
-    Str(String),
+    // Only integers too large for an `Int`, so that each integer has one
+    // representation.
+    BigInt(BigInt),
+    // Only fractions that are not whole, for the same reason.
+    Ratio(BigRational),
+    // Always finite, as ops whose results would not be fail instead.
+    Float(f64),
+    Str(Arc<str>),
     Table(Table),
+    // The path from the root table to another variable, which paths that
+    // pass through the reference continue from.
+    Ref(Arc<str>),
 }
 
 impl std::str::FromStr for Val {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let integer = |n: &str| {
+            let digits = n.strip_prefix(['+', '-']).unwrap_or(n);
+            match !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                true => n.parse::<BigInt>().ok(),
+                false => None,
+            }
+        };
         s.strip_prefix('#')
-            .map(str::parse::<i64>)
-            .map(|res| res.map(Self::Int))
-            .unwrap_or_else(|| Ok(Self::Str(s.to_owned())))
-            .map_err(From::from)
+            .map(|n| match n.parse::<i64>() {
+                Ok(n) => Ok(Self::Int(n)),
+                Err(e) => match n.split_once('/') {
+                    // Fractions are written like `#3/4`.
+                    Some((numer, denom)) => match (integer(numer), integer(denom)) {
+                        (Some(_), Some(denom)) if denom.sign() == Sign::NoSign => {
+                            anyhow::bail!("fraction with denominator 0: {}", n)
+                        }
+                        (Some(numer), Some(denom)) => {
+                            Ok(Self::from(BigRational::new(numer, denom)))
+                        }
+                        _ => Err(e.into()),
+                    },
+                    // Floats are written with a `.` or an exponent, like
+                    // `#0.5` or `#1e-3`.
+                    None => integer(n)
+                        .map(Self::from)
+                        .or_else(|| float(n).map(Self::Float))
+                        .ok_or_else(|| e.into()),
+                },
+            })
+            .unwrap_or_else(|| Ok(Self::Str(s.into())))
+    }
+}
+
+fn float(n: &str) -> Option<f64> {
+    match n
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+    {
+        true => n.parse::<f64>().ok().filter(|x| x.is_finite()),
+        false => None,
+    }
+}
+
+// Numbers are equal when their values are, so that a float equals the
+// integer or fraction it is exactly.
+impl PartialEq for Val {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::BigInt(a), Self::BigInt(b)) => a == b,
+            (Self::Ratio(a), Self::Ratio(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a == b,
+            (Self::Float(_), _) | (_, Self::Float(_)) => {
+                exact(self).is_some_and(|a| exact(other) == Some(a))
+            }
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Table(a), Self::Table(b)) => a == b,
+            (Self::Ref(a), Self::Ref(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// Floats are never NaN, so every value equals itself.
+impl Eq for Val {}
+
+impl From<BigInt> for Val {
+    fn from(n: BigInt) -> Self {
+        match i64::try_from(&n) {
+            Ok(n) => Self::Int(n),
+            Err(_) => Self::BigInt(n),
+        }
+    }
+}
+
+impl From<BigRational> for Val {
+    fn from(r: BigRational) -> Self {
+        match r.is_integer() {
+            true => Self::from(r.to_integer()),
+            false => Self::Ratio(r),
+        }
     }
 }
 
@@ -28,45 +131,329 @@ impl std::fmt::Display for Val {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Int(n) => write!(f, "{}", n),
+            Self::BigInt(n) => write!(f, "{}", n),
+            Self::Ratio(r) => write!(f, "{}", r),
+            // Always with a `.` or an exponent, so that it reads back as a
+            // float.
+            Self::Float(x) => write!(f, "{:?}", x),
             Self::Str(s) => write!(f, "{}", s),
             Self::Table(_table) => write!(f, "<table>"),
+            Self::Ref(path) => write!(f, "<ref {}>", path),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Get {
     Val(Val),
-    Var(String),
+    Var(Arc<str>),
 }
 
 impl Get {
-    fn val<'a>(&'a self, table: &'a Table) -> Result<&'a Val> {
+    fn val<'a>(&'a self, scope: Scope<'a>) -> Result<&'a Val> {
         match self {
             Self::Val(val) => Ok(val),
-            Self::Var(var) => {
-                let mut cur = table;
-                let mut subs = var.split('/');
-                let tail = subs.next_back().unwrap();
-                for sub in subs {
-                    cur = match cur.get(sub) {
-                        Some(Val::Table(table)) => table,
-                        Some(_) => anyhow::bail!("tried to access non-table as table: {}", var),
-                        None => anyhow::bail!("undefined symbol: {}", var),
-                    };
+            Self::Var(var) => lookup(scope, var),
+        }
+    }
+
+    // The argument as the name of a variable or table.
+    fn name(&self, scope: Scope) -> Result<Arc<str>> {
+        Ok(match self.val(scope)? {
+            Val::Str(s) => intern(s),
+            val => intern(&val.to_string()),
+        })
+    }
+}
+
+// Written as it is parsed, so that `#1` is an integer and `$a` a variable.
+impl std::fmt::Display for Get {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Val(val @ (Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_))) => {
+                write!(f, "#{}", val)
+            }
+            Self::Val(val) => write!(f, "{}", quote(&val.to_string())),
+            Self::Var(var) => write!(f, "{}", quote(&format!("${}", var))),
+        }
+    }
+}
+
+// Quotes a word for `shellwords::split` if it would not be read back as is.
+fn quote(word: &str) -> String {
+    let bare = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '\\' | '\'' | '"'));
+    match bare {
+        true => word.to_owned(),
+        false => format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+// The result of the arithmetic, comparison or bitwise op `op` on `a` and `b`,
+// if they are integers it applies to. Results too large for an `Int` become a
+// `BigInt` rather than overflowing, while rotations are only defined on 64
+// bits. The exact ops, and comparisons, also apply to fractions. Floats are
+// refused by the exact ops and turn the rest of the arithmetic into float
+// arithmetic, though they are still compared exactly.
+pub(crate) fn arith(op: &Op, a: &Val, b: &Val) -> Option<Val> {
+    let exact_op = matches!(
+        op,
+        Op::Rdiv(..) | Op::Radd(..) | Op::Rsub(..) | Op::Rmul(..)
+    );
+    if !exact_op && (matches!(a, Val::Float(_)) || matches!(b, Val::Float(_))) {
+        if let Op::Gt(..) = op {
+            return Some(Val::Int((exact(a)? > exact(b)?) as i64));
+        }
+        let (a, b) = (to_float(a)?, to_float(b)?);
+        let result = match op {
+            Op::Add(..) => a + b,
+            Op::Sub(..) => a - b,
+            Op::Mul(..) => a * b,
+            Op::Div(..) => a / b,
+            _ => return None,
+        };
+        return Some(Val::Float(result)).filter(|_| result.is_finite());
+    }
+    if exact_op || matches!(a, Val::Ratio(_)) || matches!(b, Val::Ratio(_)) {
+        let ratio = |val: &Val| match val {
+            Val::Float(_) => None,
+            val => exact(val),
+        };
+        let (a, b) = (ratio(a)?, ratio(b)?);
+        return Some(match op {
+            Op::Gt(..) => Val::Int((a > b) as i64),
+            Op::Radd(..) => Val::from(a + b),
+            Op::Rsub(..) => Val::from(a - b),
+            Op::Rmul(..) => Val::from(a * b),
+            Op::Rdiv(..) if b.numer().sign() == Sign::NoSign => return None,
+            Op::Rdiv(..) => Val::from(a / b),
+            _ => return None,
+        });
+    }
+    if let (&Val::Int(a), &Val::Int(b)) = (a, b) {
+        let result = match op {
+            Op::Gt(..) => Some((a > b) as i64),
+            Op::Add(..) => a.checked_add(b),
+            Op::Sub(..) => a.checked_sub(b),
+            Op::Mul(..) => a.checked_mul(b),
+            Op::Div(..) => a.checked_div(b),
+            Op::Mod(..) => a.checked_rem(b),
+            Op::And(..) => Some(a & b),
+            Op::Or(..) => Some(a | b),
+            Op::Xor(..) => Some(a ^ b),
+            Op::Rotl(..) => Some(a.rotate_left(b.rem_euclid(64) as u32)),
+            Op::Rotr(..) => Some(a.rotate_right(b.rem_euclid(64) as u32)),
+            _ => None,
+        };
+        if let Some(result) = result {
+            return Some(Val::Int(result));
+        }
+    }
+    let big = |val: &Val| match val {
+        &Val::Int(n) => Some(BigInt::from(n)),
+        Val::BigInt(n) => Some(n.clone()),
+        _ => None,
+    };
+    let (a, b) = (big(a)?, big(b)?);
+    Some(match op {
+        Op::Gt(..) => Val::Int((a > b) as i64),
+        Op::Add(..) => Val::from(a + b),
+        Op::Sub(..) => Val::from(a - b),
+        Op::Mul(..) => Val::from(a * b),
+        Op::Div(..) | Op::Mod(..) if b.sign() == Sign::NoSign => return None,
+        Op::Div(..) => Val::from(a / b),
+        Op::Mod(..) => Val::from(a % b),
+        Op::And(..) => Val::from(a & b),
+        Op::Or(..) => Val::from(a | b),
+        Op::Xor(..) => Val::from(a ^ b),
+        _ => return None,
+    })
+}
+
+// `val` as an exact fraction, if it is a number.
+fn exact(val: &Val) -> Option<BigRational> {
+    match val {
+        &Val::Int(n) => Some(BigRational::from_integer(n.into())),
+        Val::BigInt(n) => Some(BigRational::from_integer(n.clone())),
+        Val::Ratio(r) => Some(r.clone()),
+        &Val::Float(x) => BigRational::from_float(x),
+        _ => None,
+    }
+}
+
+// `val` rounded to the nearest float, if it is a number within their range.
+fn to_float(val: &Val) -> Option<f64> {
+    match val {
+        &Val::Int(n) => Some(n as f64),
+        Val::BigInt(n) => n.to_f64(),
+        Val::Ratio(r) => r.to_f64(),
+        &Val::Float(x) => Some(x),
+        _ => None,
+    }
+    .filter(|x| x.is_finite())
+}
+
+// The result of the math op `op` on `n`, if it is a number. `floor`, `ceil`
+// and `round` give integers, exactly for fractions, with `round` rounding
+// halves away from zero, while the rest give floats. Results that would not
+// be finite, like the square root of a negative number, are not defined.
+pub(crate) fn math(op: &Op, n: &Val) -> Option<Val> {
+    let whole = |x: f64| BigInt::from_f64(x).map(Val::from);
+    match (op, n) {
+        (Op::Floor(..) | Op::Ceil(..) | Op::Round(..), Val::Int(_) | Val::BigInt(_)) => {
+            Some(n.clone())
+        }
+        (Op::Floor(..), Val::Ratio(r)) => Some(Val::from(r.floor())),
+        (Op::Ceil(..), Val::Ratio(r)) => Some(Val::from(r.ceil())),
+        (Op::Round(..), Val::Ratio(r)) => Some(Val::from(r.round())),
+        (Op::Floor(..), &Val::Float(x)) => whole(x.floor()),
+        (Op::Ceil(..), &Val::Float(x)) => whole(x.ceil()),
+        (Op::Round(..), &Val::Float(x)) => whole(x.round()),
+        _ => {
+            let x = to_float(n)?;
+            let result = match op {
+                Op::Sqrt(..) => x.sqrt(),
+                Op::Sin(..) => x.sin(),
+                Op::Cos(..) => x.cos(),
+                Op::Ln(..) => x.ln(),
+                _ => return None,
+            };
+            Some(Val::Float(result)).filter(|_| result.is_finite())
+        }
+    }
+}
+
+// The result of `tostr` or `fromstr` for its two arguments, if they are valid.
+// Bases run from 2 to 36, with letters for the digits past 9.
+pub(crate) fn radix(op: &Op, val: &Val, base: &Val) -> Option<Val> {
+    let base = match base {
+        &Val::Int(base @ 2..=36) => base as u32,
+        _ => return None,
+    };
+    match (op, val) {
+        (Op::Tostr(..), &Val::Int(n)) => {
+            let mut digits = Vec::new();
+            let mut rest = n.unsigned_abs();
+            loop {
+                digits.push(char::from_digit((rest % base as u64) as u32, base)?);
+                rest /= base as u64;
+                if rest == 0 {
+                    break;
+                }
+            }
+            if n < 0 {
+                digits.push('-');
+            }
+            Some(Val::Str(
+                digits.into_iter().rev().collect::<String>().into(),
+            ))
+        }
+        (Op::Tostr(..), Val::BigInt(n)) => Some(Val::Str(n.to_str_radix(base).into())),
+        (Op::Fromstr(..), val) => {
+            let s = val.to_string();
+            let digits = s.strip_prefix(['+', '-']).unwrap_or(&s);
+            if digits.is_empty() || !digits.chars().all(|c| c.is_digit(base)) {
+                return None;
+            }
+            BigInt::parse_bytes(s.as_bytes(), base).map(Val::from)
+        }
+        _ => None,
+    }
+}
+
+// The longest string `strrep` makes, so that a large count fails rather than
+// exhausting memory.
+pub(crate) const MAX_REPEAT_LEN: usize = 1 << 28;
+
+// `string` repeated `count` times, if the result is no longer than `limit`
+// bytes.
+pub(crate) fn repeat(string: &str, count: i64, limit: usize) -> Result<String> {
+    let len = usize::try_from(count)
+        .ok()
+        .and_then(|count| string.len().checked_mul(count))
+        .filter(|&len| len <= limit);
+    match len {
+        Some(_) => Ok(string.repeat(count as usize)),
+        None => Err(UndagError::TooLong {
+            op: "strrep".to_owned(),
+            limit,
+        }
+        .into()),
+    }
+}
+
+// The tables that variable names are looked up from: the current table, and
+// the root table that references are followed from.
+#[derive(Clone, Copy)]
+pub struct Scope<'a> {
+    root: &'a Table,
+    table: &'a Table,
+}
+
+// The most references a path may pass through, so that ones referring to
+// themselves fail rather than being followed forever.
+const MAX_REFS: usize = 1024;
+
+// The path from the root table that `var` stands for if it passes through a
+// reference, each of which is replaced with the path it holds.
+fn deref(scope: Scope, var: &str) -> Result<Option<String>> {
+    let mut resolved = None::<String>;
+    for _ in 0..MAX_REFS {
+        let (mut cur, mut rest) = match &resolved {
+            Some(path) => (scope.root, path.as_str()),
+            None => (scope.table, var),
+        };
+        let mut next = None;
+        while let Some((sub, tail)) = rest.split_once('/') {
+            match cur.get(sub) {
+                Some(Val::Table(table)) => (cur, rest) = (table, tail),
+                Some(Val::Ref(path)) => {
+                    next = Some(format!("{}/{}", path, tail));
+                    break;
                 }
-                cur.get(tail)
-                    .with_context(|| format!("undefined symbol: {}", var))
+                _ => break,
             }
         }
+        match next {
+            Some(path) => resolved = Some(path),
+            None => return Ok(resolved),
+        }
+    }
+    anyhow::bail!(UndagError::RefDepth {
+        var: var.to_owned(),
+        limit: MAX_REFS,
+    })
+}
+
+fn lookup<'a>(scope: Scope<'a>, var: &str) -> Result<&'a Val> {
+    if let Some(path) = deref(scope, var)? {
+        return lookup_in(scope.root, &path);
+    }
+    lookup_in(scope.table, var)
+}
+
+fn lookup_in<'a>(table: &'a Table, var: &str) -> Result<&'a Val> {
+    let mut cur = table;
+    let mut subs = var.split('/');
+    let tail = subs.next_back().unwrap();
+    for sub in subs {
+        cur = match cur.get(sub) {
+            Some(Val::Table(table)) => table,
+            Some(_) => anyhow::bail!(UndagError::NotATable(var.to_owned())),
+            None => anyhow::bail!(UndagError::UndefinedSymbol(var.to_owned())),
+        };
     }
+    cur.get(tail)
+        .ok_or_else(|| UndagError::UndefinedSymbol(var.to_owned()).into())
 }
 
 impl std::str::FromStr for Get {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         s.strip_prefix('$')
-            .map(str::to_owned)
+            .map(intern)
             .map(Self::Var)
             .map(Ok)
             .unwrap_or_else(|| s.parse::<Val>().map(Self::Val))
@@ -78,8 +465,12 @@ pub enum Op {
     Nop,
     Set(Get, Get),
     Get(Get, Get),
+    Ref(Get, Get),
+    Deref(Get, Get),
     Del(Get),
     Exists(Get, Get),
+    Is(Get, Get, Get),
+    Next(Get, Get, Get),
     Branch(Get),
     Enter(Get),
     Exit,
@@ -87,9 +478,33 @@ pub enum Op {
     Print(Get),
     Println(Get),
     // String operations
-    Inpln(Get),
+    Inpln(Get, Option<Get>),
+    // Byte operations
+    Outb(Get),
+    Inb(Get),
+    Cls,
+    GotoXy(Get, Get),
+    Color(Get),
+    Rawmode(Get),
+    Key(Get),
+    Clock(Get),
     Concat(Get, Get, Get),
     Chars(Get, Get),
+    Graphemes(Get, Get),
+    Charat(Get, Get, Get),
+    Contains(Get, Get, Get),
+    Startswith(Get, Get, Get),
+    Endswith(Get, Get, Get),
+    Strrep(Get, Get, Get),
+    Strrev(Get, Get),
+    Tostr(Get, Get, Get),
+    Fromstr(Get, Get, Get),
+    Dump(Get, Get),
+    Load(Get, Get),
+    // Operations on the program's repository
+    Meta(Get, Get),
+    Mkcommit(Get, Get, Get),
+    Mktag(Get, Get),
     // Numerical binary operations
     Eq(Get, Get, Get),
     Gt(Get, Get, Get),
@@ -101,6 +516,24 @@ pub enum Op {
     And(Get, Get, Get),
     Or(Get, Get, Get),
     Xor(Get, Get, Get),
+    Rotl(Get, Get, Get),
+    Rotr(Get, Get, Get),
+    Rdiv(Get, Get, Get),
+    Radd(Get, Get, Get),
+    Rsub(Get, Get, Get),
+    Rmul(Get, Get, Get),
+    Numer(Get, Get),
+    Denom(Get, Get),
+    Sqrt(Get, Get),
+    Floor(Get, Get),
+    Ceil(Get, Get),
+    Round(Get, Get),
+    Sin(Get, Get),
+    Cos(Get, Get),
+    Ln(Get, Get),
+    // Operations registered by the embedder with `Instance::register`,
+    // named without the `@` they are called with
+    Extern(String, Vec<Get>),
 }
 
 impl std::str::FromStr for Op {
@@ -128,6 +561,14 @@ impl std::str::FromStr for Op {
                 parse_args!(op, tokens, var, src);
                 Ok(Self::Get(var, src))
             }
+            Some(op @ "ref") => {
+                parse_args!(op, tokens, var, src);
+                Ok(Self::Ref(var, src))
+            }
+            Some(op @ "deref") => {
+                parse_args!(op, tokens, var, src);
+                Ok(Self::Deref(var, src))
+            }
             Some(op @ "del") => {
                 parse_args!(op, tokens, var);
                 Ok(Self::Del(var))
@@ -136,6 +577,14 @@ impl std::str::FromStr for Op {
                 parse_args!(op, tokens, var, symbol);
                 Ok(Self::Exists(var, symbol))
             }
+            Some(op @ "is") => {
+                parse_args!(op, tokens, var, a, b);
+                Ok(Self::Is(var, a, b))
+            }
+            Some(op @ "next") => {
+                parse_args!(op, tokens, var, table, cursor);
+                Ok(Self::Next(var, table, cursor))
+            }
             Some(op @ "branch") => {
                 parse_args!(op, tokens, tag);
                 Ok(Self::Branch(tag))
@@ -171,7 +620,41 @@ impl std::str::FromStr for Op {
             }
             Some(op @ "inpln") => {
                 parse_args!(op, tokens, var);
-                Ok(Self::Inpln(var))
+                let prompt = tokens
+                    .next()
+                    .map(|prompt| prompt.parse())
+                    .transpose()
+                    .with_context(|| format!("{}: invalid prompt", op))?;
+                Ok(Self::Inpln(var, prompt))
+            }
+            Some(op @ "outb") => {
+                parse_args!(op, tokens, byte);
+                Ok(Self::Outb(byte))
+            }
+            Some(op @ "inb") => {
+                parse_args!(op, tokens, var);
+                Ok(Self::Inb(var))
+            }
+            Some("cls") => Ok(Self::Cls),
+            Some(op @ "goto_xy") => {
+                parse_args!(op, tokens, x, y);
+                Ok(Self::GotoXy(x, y))
+            }
+            Some(op @ "color") => {
+                parse_args!(op, tokens, n);
+                Ok(Self::Color(n))
+            }
+            Some(op @ "rawmode") => {
+                parse_args!(op, tokens, mode);
+                Ok(Self::Rawmode(mode))
+            }
+            Some(op @ "key") => {
+                parse_args!(op, tokens, var);
+                Ok(Self::Key(var))
+            }
+            Some(op @ "clock") => {
+                parse_args!(op, tokens, var);
+                Ok(Self::Clock(var))
             }
             Some(op @ "concat") => {
                 parse_args!(op, tokens, var, a, b);
@@ -181,8 +664,83 @@ impl std::str::FromStr for Op {
                 parse_args!(op, tokens, var, string);
                 Ok(Self::Chars(var, string))
             }
+            Some(op @ "graphemes") => {
+                parse_args!(op, tokens, var, string);
+                Ok(Self::Graphemes(var, string))
+            }
+            Some(op @ "charat") => {
+                parse_args!(op, tokens, var, string, index);
+                Ok(Self::Charat(var, string, index))
+            }
+            Some(op @ ("contains" | "startswith" | "endswith")) => {
+                parse_args!(op, tokens, var, haystack, needle);
+                Ok(match op {
+                    "contains" => Op::Contains,
+                    "startswith" => Op::Startswith,
+                    "endswith" => Op::Endswith,
+                    _ => unreachable!(),
+                }(var, haystack, needle))
+            }
+            Some(op @ "strrep") => {
+                parse_args!(op, tokens, var, string, count);
+                Ok(Self::Strrep(var, string, count))
+            }
+            Some(op @ "strrev") => {
+                parse_args!(op, tokens, var, string);
+                Ok(Self::Strrev(var, string))
+            }
+            Some(op @ "tostr") => {
+                parse_args!(op, tokens, var, n, base);
+                Ok(Self::Tostr(var, n, base))
+            }
+            Some(op @ "fromstr") => {
+                parse_args!(op, tokens, var, string, base);
+                Ok(Self::Fromstr(var, string, base))
+            }
+            Some(op @ "dump") => {
+                parse_args!(op, tokens, var, table);
+                Ok(Self::Dump(var, table))
+            }
+            Some(op @ "load") => {
+                parse_args!(op, tokens, var, string);
+                Ok(Self::Load(var, string))
+            }
+            Some(op @ "numer") => {
+                parse_args!(op, tokens, var, n);
+                Ok(Self::Numer(var, n))
+            }
+            Some(op @ "denom") => {
+                parse_args!(op, tokens, var, n);
+                Ok(Self::Denom(var, n))
+            }
+            Some(op @ ("sqrt" | "floor" | "ceil" | "round" | "sin" | "cos" | "ln")) => {
+                parse_args!(op, tokens, var, n);
+                Ok(match op {
+                    "sqrt" => Op::Sqrt,
+                    "floor" => Op::Floor,
+                    "ceil" => Op::Ceil,
+                    "round" => Op::Round,
+                    "sin" => Op::Sin,
+                    "cos" => Op::Cos,
+                    "ln" => Op::Ln,
+                    _ => unreachable!(),
+                }(var, n))
+            }
+            Some(op @ "meta") => {
+                parse_args!(op, tokens, var, field);
+                Ok(Self::Meta(var, field))
+            }
+            Some(op @ "mkcommit") => {
+                parse_args!(op, tokens, var, parent, message);
+                Ok(Self::Mkcommit(var, parent, message))
+            }
+            Some(op @ "mktag") => {
+                parse_args!(op, tokens, name, commit);
+                Ok(Self::Mktag(name, commit))
+            }
             Some(
-                op @ ("eq" | "gt" | "add" | "sub" | "mul" | "div" | "mod" | "and" | "or" | "xor"),
+                op @ ("eq" | "gt" | "add" | "sub" | "mul" | "div" | "mod" | "and" | "or" | "xor"
+                | "rotl" | "rotr" | "rdiv" | "radd" | "rsub" | "rmul"),
             ) => {
                 parse_args!(op, tokens, var, a, b);
                 Ok(match op {
@@ -196,12 +754,385 @@ impl std::str::FromStr for Op {
                     "and" => Op::And,
                     "or" => Op::Or,
                     "xor" => Op::Xor,
+                    "rotl" => Op::Rotl,
+                    "rotr" => Op::Rotr,
+                    "rdiv" => Op::Rdiv,
+                    "radd" => Op::Radd,
+                    "rsub" => Op::Rsub,
+                    "rmul" => Op::Rmul,
                     _ => unreachable!(),
                 }(var, a, b))
             }
-            _ => Err(anyhow::anyhow!("invalid operation: {:?}", s)),
+            // Registered operations are marked with `@`, so that a misspelled
+            // op is still an error rather than a call to one. They are looked
+            // up when executed.
+            Some(op) if op.len() > 1 && op.starts_with('@') => {
+                let name = &op[1..];
+                let args = tokens
+                    .map(|arg| arg.parse())
+                    .collect::<Result<_>>()
+                    .with_context(|| format!("{}: invalid args", name))?;
+                Ok(Self::Extern(name.to_owned(), args))
+            }
+            Some(op) => anyhow::bail!(UndagError::InvalidOperation(op.to_owned())),
+        }
+    }
+}
+
+// The canonical form of the op: its name and arguments separated by single
+// spaces, with arguments quoted only where needed.
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Self::Nop = self {
+            return Ok(());
+        }
+        if let Self::Extern(..) = self {
+            write!(f, "@")?;
+        }
+        write!(f, "{}", quote(self.name()))?;
+        for arg in self.args() {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Op {
+    // The name the op is invoked by, which is empty for `Nop`.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Nop => "",
+            Self::Set(..) => "set",
+            Self::Get(..) => "get",
+            Self::Ref(..) => "ref",
+            Self::Deref(..) => "deref",
+            Self::Del(_) => "del",
+            Self::Exists(..) => "exists",
+            Self::Is(..) => "is",
+            Self::Next(..) => "next",
+            Self::Branch(_) => "branch",
+            Self::Enter(_) => "enter",
+            Self::Exit => "exit",
+            Self::Match(..) => "match",
+            Self::Print(_) => "print",
+            Self::Println(_) => "println",
+            Self::Inpln(..) => "inpln",
+            Self::Outb(_) => "outb",
+            Self::Inb(_) => "inb",
+            Self::Cls => "cls",
+            Self::GotoXy(..) => "goto_xy",
+            Self::Color(_) => "color",
+            Self::Rawmode(_) => "rawmode",
+            Self::Key(_) => "key",
+            Self::Clock(_) => "clock",
+            Self::Concat(..) => "concat",
+            Self::Chars(..) => "chars",
+            Self::Graphemes(..) => "graphemes",
+            Self::Charat(..) => "charat",
+            Self::Contains(..) => "contains",
+            Self::Startswith(..) => "startswith",
+            Self::Endswith(..) => "endswith",
+            Self::Strrep(..) => "strrep",
+            Self::Strrev(..) => "strrev",
+            Self::Tostr(..) => "tostr",
+            Self::Fromstr(..) => "fromstr",
+            Self::Dump(..) => "dump",
+            Self::Load(..) => "load",
+            Self::Meta(..) => "meta",
+            Self::Mkcommit(..) => "mkcommit",
+            Self::Mktag(..) => "mktag",
+            Self::Eq(..) => "eq",
+            Self::Gt(..) => "gt",
+            Self::Add(..) => "add",
+            Self::Sub(..) => "sub",
+            Self::Mul(..) => "mul",
+            Self::Div(..) => "div",
+            Self::Mod(..) => "mod",
+            Self::And(..) => "and",
+            Self::Or(..) => "or",
+            Self::Xor(..) => "xor",
+            Self::Rotl(..) => "rotl",
+            Self::Rotr(..) => "rotr",
+            Self::Rdiv(..) => "rdiv",
+            Self::Radd(..) => "radd",
+            Self::Rsub(..) => "rsub",
+            Self::Rmul(..) => "rmul",
+            Self::Numer(..) => "numer",
+            Self::Denom(..) => "denom",
+            Self::Sqrt(..) => "sqrt",
+            Self::Floor(..) => "floor",
+            Self::Ceil(..) => "ceil",
+            Self::Round(..) => "round",
+            Self::Sin(..) => "sin",
+            Self::Cos(..) => "cos",
+            Self::Ln(..) => "ln",
+            Self::Extern(name, _) => name,
+        }
+    }
+
+    // Every argument of the op, in the order they are written.
+    pub fn args(&self) -> Vec<&Get> {
+        match self {
+            Self::Nop | Self::Exit | Self::Cls => Vec::new(),
+            Self::Del(a)
+            | Self::Branch(a)
+            | Self::Enter(a)
+            | Self::Print(a)
+            | Self::Println(a)
+            | Self::Inpln(a, None)
+            | Self::Outb(a)
+            | Self::Inb(a)
+            | Self::Color(a)
+            | Self::Rawmode(a)
+            | Self::Key(a)
+            | Self::Clock(a) => vec![a],
+            Self::Inpln(a, Some(b)) => vec![a, b],
+            Self::Set(a, b)
+            | Self::Get(a, b)
+            | Self::Ref(a, b)
+            | Self::Deref(a, b)
+            | Self::Exists(a, b)
+            | Self::Chars(a, b)
+            | Self::Graphemes(a, b)
+            | Self::Strrev(a, b)
+            | Self::Dump(a, b)
+            | Self::Load(a, b)
+            | Self::Numer(a, b)
+            | Self::Denom(a, b)
+            | Self::Sqrt(a, b)
+            | Self::Floor(a, b)
+            | Self::Ceil(a, b)
+            | Self::Round(a, b)
+            | Self::Sin(a, b)
+            | Self::Cos(a, b)
+            | Self::Ln(a, b)
+            | Self::GotoXy(a, b)
+            | Self::Meta(a, b)
+            | Self::Mktag(a, b) => vec![a, b],
+            Self::Match(var, src, branches) => [var, src]
+                .into_iter()
+                .chain(branches.iter().flat_map(|(val, branch)| [val, branch]))
+                .collect(),
+            Self::Concat(a, b, c)
+            | Self::Next(a, b, c)
+            | Self::Is(a, b, c)
+            | Self::Charat(a, b, c)
+            | Self::Contains(a, b, c)
+            | Self::Startswith(a, b, c)
+            | Self::Endswith(a, b, c)
+            | Self::Strrep(a, b, c)
+            | Self::Tostr(a, b, c)
+            | Self::Fromstr(a, b, c)
+            | Self::Mkcommit(a, b, c)
+            | Self::Eq(a, b, c)
+            | Self::Gt(a, b, c)
+            | Self::Add(a, b, c)
+            | Self::Sub(a, b, c)
+            | Self::Mul(a, b, c)
+            | Self::Div(a, b, c)
+            | Self::Mod(a, b, c)
+            | Self::And(a, b, c)
+            | Self::Or(a, b, c)
+            | Self::Xor(a, b, c)
+            | Self::Rotl(a, b, c)
+            | Self::Rotr(a, b, c)
+            | Self::Rdiv(a, b, c)
+            | Self::Radd(a, b, c)
+            | Self::Rsub(a, b, c)
+            | Self::Rmul(a, b, c) => vec![a, b, c],
+            Self::Extern(_, args) => args.iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    // Pick one of a commit's children at random.
+    Random,
+    // Take a commit's children in turn on successive visits, ordered by
+    // commit time and then by id.
+    Deterministic,
+    // Always prefer a commit's oldest child.
+    Chronological,
+    // Prefer the child that lists the commit earliest among its parents, so
+    // that first-parent chains fall through, then the oldest such child.
+    ParentIndex,
+}
+
+const DEFAULT_MAX_DEPTH: usize = 1024;
+
+// Where a program keeps its constants, and the table they are loaded into.
+pub const CONSTS_FILE: &str = ".undag/consts.json";
+const CONSTS: &str = "const";
+
+pub type ExternFn = dyn Fn(&mut Instance, &[Val]) -> Result<()>;
+
+// Observes a running program. Every method does nothing by default.
+pub trait Hook {
+    // Called before the op of each commit is executed.
+    fn before_op(&mut self, _commit: Oid, _op: &Op) {}
+
+    // Called once `branch` has chosen which child of `from` to go to.
+    fn after_branch(&mut self, _from: Oid, _tag: &str, _to: Oid) {}
+
+    // Called just before `after_branch` with what the choice was made from:
+    // the searched history of the tag, unless it does not exist, and the
+    // children of `from`.
+    fn explain_branch(
+        &mut self,
+        _from: Oid,
+        _tag: &str,
+        _to: Oid,
+        _ancestry: Option<&tree::Ancestry>,
+        _children: &[tree::Child],
+    ) {
+    }
+
+    // Called after a variable is written, with its path relative to the
+    // current table.
+    fn on_write(&mut self, _var: &str, _val: &Val) {}
+
+    // Called after a write to or within a watched variable, with its path
+    // from the root table, the commit whose op wrote it, and its value before
+    // and after, which are `None` while it is undefined.
+    fn on_watch(
+        &mut self,
+        _path: &str,
+        _commit: Option<Oid>,
+        _old: Option<&Val>,
+        _new: Option<&Val>,
+    ) {
+    }
+
+    // Called when `commit` has executed `times` times without any input,
+    // output or variable changing in between, unless loops are strict and
+    // the program fails instead.
+    fn on_stuck_loop(&mut self, _commit: Oid, _times: usize) {}
+}
+
+// Both hooks of a pair are called, the first first, so that more than one can
+// be set.
+impl<A: Hook, B: Hook> Hook for (A, B) {
+    fn before_op(&mut self, commit: Oid, op: &Op) {
+        self.0.before_op(commit, op);
+        self.1.before_op(commit, op);
+    }
+
+    fn after_branch(&mut self, from: Oid, tag: &str, to: Oid) {
+        self.0.after_branch(from, tag, to);
+        self.1.after_branch(from, tag, to);
+    }
+
+    fn explain_branch(
+        &mut self,
+        from: Oid,
+        tag: &str,
+        to: Oid,
+        ancestry: Option<&tree::Ancestry>,
+        children: &[tree::Child],
+    ) {
+        self.0.explain_branch(from, tag, to, ancestry, children);
+        self.1.explain_branch(from, tag, to, ancestry, children);
+    }
+
+    fn on_write(&mut self, var: &str, val: &Val) {
+        self.0.on_write(var, val);
+        self.1.on_write(var, val);
+    }
+
+    fn on_watch(&mut self, path: &str, commit: Option<Oid>, old: Option<&Val>, new: Option<&Val>) {
+        self.0.on_watch(path, commit, old, new);
+        self.1.on_watch(path, commit, old, new);
+    }
+
+    fn on_stuck_loop(&mut self, commit: Oid, times: usize) {
+        self.0.on_stuck_loop(commit, times);
+        self.1.on_stuck_loop(commit, times);
+    }
+}
+
+impl<H: Hook> Hook for Option<H> {
+    fn before_op(&mut self, commit: Oid, op: &Op) {
+        if let Some(hook) = self {
+            hook.before_op(commit, op);
+        }
+    }
+
+    fn after_branch(&mut self, from: Oid, tag: &str, to: Oid) {
+        if let Some(hook) = self {
+            hook.after_branch(from, tag, to);
+        }
+    }
+
+    fn explain_branch(
+        &mut self,
+        from: Oid,
+        tag: &str,
+        to: Oid,
+        ancestry: Option<&tree::Ancestry>,
+        children: &[tree::Child],
+    ) {
+        if let Some(hook) = self {
+            hook.explain_branch(from, tag, to, ancestry, children);
+        }
+    }
+
+    fn on_write(&mut self, var: &str, val: &Val) {
+        if let Some(hook) = self {
+            hook.on_write(var, val);
         }
     }
+
+    fn on_watch(&mut self, path: &str, commit: Option<Oid>, old: Option<&Val>, new: Option<&Val>) {
+        if let Some(hook) = self {
+            hook.on_watch(path, commit, old, new);
+        }
+    }
+
+    fn on_stuck_loop(&mut self, commit: Oid, times: usize) {
+        if let Some(hook) = self {
+            hook.on_stuck_loop(commit, times);
+        }
+    }
+}
+
+// The terminal that `rawmode` and `key` act on. Without one, `rawmode` does
+// nothing and `key` reads as no key being pressed.
+pub trait Terminal {
+    // Stops the terminal from waiting for whole lines and echoing keys as
+    // they are typed, or goes back to doing so.
+    fn set_raw(&mut self, raw: bool) -> Result<()>;
+
+    // The next key pressed, without waiting for one.
+    fn key(&mut self) -> Result<Option<String>>;
+}
+
+// Counts from when the instance is created.
+#[cfg(not(target_arch = "wasm32"))]
+fn default_clock() -> Option<Box<dyn FnMut() -> i64>> {
+    let created = std::time::Instant::now();
+    Some(Box::new(move || created.elapsed().as_millis() as i64))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_clock() -> Option<Box<dyn FnMut() -> i64>> {
+    None
+}
+
+fn entered_table_mut<'a>(mut table: &'a mut Table, entered: &[Arc<str>]) -> &'a mut Table {
+    for name in entered {
+        let val = table
+            .entry(name.clone())
+            .or_insert_with(|| Val::Table(Table::new()));
+        if !matches!(val, Val::Table(_)) {
+            *val = Val::Table(Table::new());
+        }
+        table = match val {
+            Val::Table(entered) => entered,
+            _ => unreachable!(),
+        };
+    }
+    table
 }
 
 // `Instance` is isolated into a module to prevent all but
@@ -212,7 +1143,47 @@ mod instance {
 
     pub struct Instance {
         table: Table,
-        entered: Vec<*mut Table>,
+        // The path from `table` to the current table, looked up on each
+        // access so that ops may freely restructure the tables around it.
+        entered: Vec<Arc<str>>,
+        coverage: Option<Coverage>,
+        order: Order,
+        checkpoint: Option<PathBuf>,
+        resume: Option<Oid>,
+        externs: HashMap<String, Rc<ExternFn>>,
+        execution: Option<Execution>,
+        rng: StdRng,
+        // Lines waiting to be read by `inpln`, and how many bytes of the
+        // first one `inb` has read.
+        input: VecDeque<Vec<u8>>,
+        input_read: usize,
+        hook: Option<Box<dyn Hook>>,
+        terminal: Option<Box<dyn Terminal>>,
+        // Milliseconds since some fixed point, for `clock`. Browsers have no
+        // clock that std can read, so there it has to be set.
+        clock: Option<Box<dyn FnMut() -> i64>>,
+        diagnostic: Option<Diagnostic>,
+        max_depth: usize,
+        verify_signatures: bool,
+        keyring: Option<PathBuf>,
+        prompts: bool,
+        ansi: bool,
+        // Whether the root table's `const` holds the program's constants,
+        // which no op may change.
+        consts: bool,
+        // Paths from the root table whose writes are logged, and the commit
+        // whose op is writing them.
+        watches: Vec<String>,
+        executing: Option<Oid>,
+        // How many times a commit may execute with nothing changing before
+        // the program is taken to be stuck, and whether that fails it.
+        loop_limit: Option<usize>,
+        strict_loops: bool,
+        // The tag the program ends at, which `mktag` moves the end with.
+        end_tag: String,
+        // Counts the input read, output written and variables changed, so
+        // that a commit executing again can tell whether anything happened.
+        progress: u64,
     }
 
     // The methods in this `impl` are the only ones allowed to access
@@ -222,170 +1193,1012 @@ mod instance {
             Self {
                 table: Table::new(),
                 entered: Vec::new(),
+                coverage: None,
+                order: Order::Random,
+                checkpoint: None,
+                resume: None,
+                externs: HashMap::new(),
+                execution: None,
+                rng: StdRng::from_entropy(),
+                input: VecDeque::new(),
+                input_read: 0,
+                hook: None,
+                terminal: None,
+                clock: default_clock(),
+                diagnostic: None,
+                max_depth: DEFAULT_MAX_DEPTH,
+                verify_signatures: false,
+                keyring: None,
+                prompts: false,
+                ansi: true,
+                consts: false,
+                watches: Vec::new(),
+                executing: None,
+                loop_limit: None,
+                strict_loops: false,
+                end_tag: "_end".to_owned(),
+                progress: 0,
             }
         }
 
-        pub fn table(&self) -> &Table {
-            // SAFETY: The pointers stored in `self.entered` will always point to data
-            //         contained within `self.table`. This data is not moved when
-            //         `self.table` is moved, so the pointers will not be
-            //         invalidated. Modifying `self.table` directly will invalidate
-            //         any pointers in `self.entered`, and as such should not happen.
-            self.entered
-                .last()
-                .map(|ptr| unsafe { &**ptr })
-                .unwrap_or(&self.table)
+        // Registers `name` as an operation, which programs call as `@name`.
+        pub fn register(
+            &mut self,
+            name: &str,
+            f: impl Fn(&mut Instance, &[Val]) -> Result<()> + 'static,
+        ) {
+            self.externs.insert(name.to_owned(), Rc::new(f));
         }
 
-        pub fn table_mut(&mut self) -> &mut Table {
-            // SAFETY: The pointers stored in `self.entered` will always point to data
-            //         contained within `self.table`. This data is not moved when
-            //         `self.table` is moved, so the pointers will not be
-            //         invalidated. Modifying `self.table` directly will invalidate
-            //         any pointers in `self.entered`, and as such should not happen.
-            //         This method cannot create multiple mutable references to
-            //         `self.table` because it always uses the top pointer of
-            //         `self.entered`, and the returned borrow's lifetime must end
-            //         before `self.entered` can be modified. It avoids creating
-            //         multiple simultaneous mutable references internally by
-            //         casting the `self.table` fallback to a raw pointer and
-            //         passing it to `unwrap_or`, then dereferencing and borrowing
-            //         the unwrapped pointer.
-            let ptr = self.entered.last().copied().unwrap_or(&mut self.table);
-            unsafe { &mut *ptr }
+        pub fn set_hook(&mut self, hook: impl Hook + 'static) {
+            self.hook = Some(Box::new(hook));
         }
 
-        pub fn enter_table(&mut self, table: &str) -> Result<()> {
-            for table in table.split('/') {
-                let table = match self
-                    .table_mut()
-                    .entry(table.to_owned())
-                    .or_insert_with(|| Val::Table(Table::new()))
-                {
-                    Val::Table(table) => table,
-                    _ => anyhow::bail!("tried to access non-table as table: {}", table),
-                } as *mut Table;
-                self.entered.push(table);
+        pub fn hook_mut(&mut self) -> Option<&mut (dyn Hook + 'static)> {
+            self.hook.as_deref_mut()
+        }
+
+        pub fn set_terminal(&mut self, terminal: impl Terminal + 'static) {
+            self.terminal = Some(Box::new(terminal));
+        }
+
+        pub fn terminal_mut(&mut self) -> Option<&mut (dyn Terminal + 'static)> {
+            self.terminal.as_deref_mut()
+        }
+
+        pub fn set_clock(&mut self, clock: impl FnMut() -> i64 + 'static) {
+            self.clock = Some(Box::new(clock));
+        }
+
+        pub fn clock(&mut self) -> Option<i64> {
+            self.clock.as_mut().map(|clock| clock())
+        }
+
+        pub fn extern_fn(&self, name: &str) -> Option<Rc<ExternFn>> {
+            self.externs.get(name).cloned()
+        }
+
+        pub fn set_checkpoint_path(&mut self, path: PathBuf) {
+            self.checkpoint = Some(path);
+        }
+
+        pub fn write_checkpoint(&self, commit: Oid) -> Result<()> {
+            match &self.checkpoint {
+                Some(path) => Checkpoint::write(path, commit, &self.entered, &self.table),
+                None => Ok(()),
+            }
+        }
+
+        pub fn resume(&mut self, checkpoint: Checkpoint) -> Result<()> {
+            self.set_root_table(checkpoint.table);
+            for name in &checkpoint.entered {
+                self.enter_table(name)?;
             }
+            self.resume = Some(checkpoint.commit);
             Ok(())
         }
 
-        pub fn exit_table(&mut self) -> bool {
-            self.entered.pop().is_some()
+        pub fn take_resume(&mut self) -> Option<Oid> {
+            self.resume.take()
         }
-    }
-}
 
-impl Instance {
-    pub fn run<'a>(
-        &mut self,
-        repo: &'a Repository,
-        start: Commit<'a>,
-        mut end: Commit<'a>,
-    ) -> Result<()> {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
+        pub(super) fn take_execution(&mut self) -> Option<Execution> {
+            self.execution.take()
+        }
 
-        let mut stdin = std::io::stdin();
-        let mut stdout = std::io::stdout();
+        pub(super) fn set_execution(&mut self, execution: Execution) {
+            self.execution = Some(execution);
+        }
 
-        replace(repo, &mut end);
+        // Details of the commit that the program last failed at.
+        pub fn diagnostic(&self) -> Option<&Diagnostic> {
+            self.diagnostic.as_ref()
+        }
 
-        let end_id = end.id();
-        let children = crate::tree::collect_children(repo, end);
+        pub fn set_diagnostic(&mut self, diagnostic: Option<Diagnostic>) {
+            self.diagnostic = diagnostic;
+        }
 
-        let mut cur = start;
-        loop {
-            replace(repo, &mut cur);
-            let op = cur
-                .message()
-                .context("syntax error")
-                .and_then(str::parse::<Op>)?;
-            if let Op::Branch(tag) = op {
-                let tag = tag.val(self.table())?.to_string();
-                let next = children
-                    .get(&cur.id())
-                    .and_then(|nexts| Self::find_tag(repo, &tag, nexts))
-                    .with_context(|| format!("{}: branch: failed to find target", cur.id()))?;
-                cur = next.clone();
-                continue;
-            }
-            if let Err(e) = self.exec(op, &mut stdin, &mut stdout) {
-                anyhow::bail!("{}: {}", cur.id(), e);
-            }
-
-            if cur.id() == end_id {
-                break Ok(());
-            }
-            if let Some(next) = children.get(&cur.id()).and_then(|set| {
-                let mut iter = set.iter().cloned();
-                iter.nth(rng.gen::<usize>() % iter.len())
-            }) {
-                cur = next;
-            } else {
-                break Err(anyhow::anyhow!(
-                    "{}: failed to find child to continue",
-                    cur.id()
-                ));
-            }
-        }
-    }
-
-    fn exec(&mut self, op: Op, stdin: &mut Stdin, stdout: &mut Stdout) -> Result<()> {
-        fn num_binop(
-            var: Get,
-            a: Get,
-            b: Get,
-            instance: &mut Instance,
-            op: impl Fn(i64, i64) -> i64,
-            opname: &str,
-        ) -> Result<()> {
-            let table = instance.table();
-            match (a.val(table)?, b.val(table)?) {
-                (&Val::Int(a), &Val::Int(b)) => {
-                    let val = var.val(table)?.clone();
-                    instance.set(&val.to_string(), Val::Int(op(a, b)))
-                }
-                (a, b) => Err(anyhow::anyhow!("{}: invalid args: {} {}", opname, a, b)),
+        // The commit that the next step will execute, if a program is running.
+        pub fn current_commit(&self) -> Option<Oid> {
+            self.execution.as_ref().map(|execution| execution.cur)
+        }
+
+        // The program or module that the current commit belongs to.
+        pub fn current_program(&self) -> Option<&Program> {
+            self.execution.as_ref().map(Execution::program)
+        }
+
+        // Queues a line, with or without its line ending, for `inpln` and
+        // `inb`. An empty line marks the end of the input.
+        pub fn push_input(&mut self, line: impl Into<Vec<u8>>) {
+            self.input.push_back(line.into());
+        }
+
+        pub fn has_input(&self) -> bool {
+            !self.input.is_empty()
+        }
+
+        // The rest of the first queued line.
+        pub fn pop_input(&mut self) -> Option<String> {
+            let line = self.input.pop_front()?;
+            self.progress += 1;
+            let read = std::mem::take(&mut self.input_read);
+            Some(String::from_utf8_lossy(&line[read..]).into_owned())
+        }
+
+        // The next byte of the first queued line, or `None` if the line marks
+        // the end of the input.
+        pub fn pop_input_byte(&mut self) -> Option<u8> {
+            let line = self.input.front()?;
+            self.progress += 1;
+            let byte = line.get(self.input_read).copied();
+            self.input_read += 1;
+            if self.input_read >= line.len() {
+                self.input.pop_front();
+                self.input_read = 0;
             }
+            byte
         }
 
-        match op {
+        // Writes the prompts of `inpln` before waiting on input, which is
+        // only wanted when a person is typing it.
+        pub fn set_prompts(&mut self, prompts: bool) {
+            self.prompts = prompts;
+        }
+
+        pub fn prompts(&self) -> bool {
+            self.prompts
+        }
+
+        // Has `cls`, `goto_xy` and `color` write nothing when disabled, for
+        // output that is not going to a terminal.
+        pub fn set_ansi(&mut self, ansi: bool) {
+            self.ansi = ansi;
+        }
+
+        pub fn ansi(&self) -> bool {
+            self.ansi
+        }
+
+        pub fn order(&self) -> Order {
+            self.order
+        }
+
+        pub fn set_order(&mut self, order: Order) {
+            self.order = order;
+        }
+
+        // Makes random choices of child repeat from run to run.
+        pub fn set_seed(&mut self, seed: u64) {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+
+        pub fn rng_mut(&mut self) -> &mut StdRng {
+            &mut self.rng
+        }
+
+        pub fn enable_coverage(&mut self) {
+            self.coverage.get_or_insert_with(Coverage::new);
+        }
+
+        pub fn coverage(&self) -> Option<&Coverage> {
+            self.coverage.as_ref()
+        }
+
+        pub fn coverage_mut(&mut self) -> Option<&mut Coverage> {
+            self.coverage.as_mut()
+        }
+
+        pub fn root_table(&self) -> &Table {
+            &self.table
+        }
+
+        pub fn set_root_table(&mut self, table: Table) {
+            self.entered.clear();
+            self.table = table;
+            self.consts = false;
+        }
+
+        // Loads the program's constants from the file `CONSTS_FILE` in the
+        // tree of its `start` commit, if there is one, into the root table's
+        // `const`.
+        pub fn load_consts(&mut self, source: &dyn ProgramSource, start: Oid) -> Result<()> {
+            let Some(text) = source.file(start, CONSTS_FILE)? else {
+                return Ok(());
+            };
+            let consts = serde_json::from_slice(&text)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| crate::state::from_json(&json))
+                .with_context(|| format!("{}: invalid {}", start, CONSTS_FILE))?;
+            self.table.insert(intern(CONSTS), Val::Table(consts));
+            self.consts = true;
+            Ok(())
+        }
+
+        // Fails if `path`, from the current table or else the root table, is
+        // among the constants.
+        pub fn check_writable(&self, path: &str, from_root: bool) -> Result<()> {
+            let root = match self.entered.first().filter(|_| !from_root) {
+                Some(name) => name,
+                None => path.split('/').next().unwrap(),
+            };
+            if self.consts && root == CONSTS {
+                anyhow::bail!(UndagError::ReadOnly {
+                    var: path.to_owned()
+                });
+            }
+            Ok(())
+        }
+
+        // An entered table that has since been removed or overwritten reads
+        // as empty and is created again on the next write.
+        pub fn table(&self) -> &Table {
+            static EMPTY: OnceLock<Table> = OnceLock::new();
+            let mut table = &self.table;
+            for name in &self.entered {
+                table = match table.get(name) {
+                    Some(Val::Table(entered)) => entered,
+                    _ => return EMPTY.get_or_init(Table::new),
+                };
+            }
+            table
+        }
+
+        // The path from the root table to `var`, from the current table.
+        pub fn root_path(&self, var: &str) -> Result<String> {
+            if let Some(path) = deref(self.scope(), var)? {
+                return Ok(path);
+            }
+            let mut path = self.entered.iter().map(|name| &**name).collect::<Vec<_>>();
+            path.push(var);
+            Ok(path.join("/"))
+        }
+
+        pub fn scope(&self) -> Scope<'_> {
+            Scope {
+                root: &self.table,
+                table: self.table(),
+            }
+        }
+
+        pub fn table_mut(&mut self) -> &mut Table {
+            entered_table_mut(&mut self.table, &self.entered)
+        }
+
+        // Borrows the current table, or the root table for paths followed
+        // through a reference, alongside the hook so that writes can be
+        // reported as they happen.
+        pub fn table_and_hook_mut(
+            &mut self,
+            from_root: bool,
+        ) -> (&mut Table, Option<&mut (dyn Hook + 'static)>) {
+            let table = match from_root {
+                true => &mut self.table,
+                false => entered_table_mut(&mut self.table, &self.entered),
+            };
+            (table, self.hook.as_deref_mut())
+        }
+
+        // Refuses to execute commits without a valid signature, checked
+        // against `keyring` if given and otherwise as `git verify-commit`
+        // would.
+        pub fn set_verify_signatures(&mut self, keyring: Option<PathBuf>) {
+            self.verify_signatures = true;
+            self.keyring = keyring;
+        }
+
+        pub fn verifies_signatures(&self) -> bool {
+            self.verify_signatures
+        }
+
+        pub fn keyring(&self) -> Option<&std::path::Path> {
+            self.keyring.as_deref()
+        }
+
+        // The deepest a table may be nested, counting from the root table,
+        // whether by `enter` or by creating tables along a path.
+        pub fn set_max_depth(&mut self, depth: usize) {
+            self.max_depth = depth;
+        }
+
+        // Reports every write to the variable at `path` from the root table,
+        // or into or over it, to the hook's `on_watch`.
+        pub fn watch(&mut self, path: impl Into<String>) {
+            self.watches.push(path.into());
+        }
+
+        pub fn watches(&self) -> &[String] {
+            &self.watches
+        }
+
+        pub(super) fn set_executing(&mut self, commit: Option<Oid>) {
+            self.executing = commit;
+        }
+
+        pub fn executing(&self) -> Option<Oid> {
+            self.executing
+        }
+
+        // Tells the hook, or fails if `strict`, once a commit executes more
+        // than `limit` times without any input being read, output written or
+        // variable changed in between.
+        pub fn set_loop_limit(&mut self, limit: usize, strict: bool) {
+            self.loop_limit = Some(limit);
+            self.strict_loops = strict;
+        }
+
+        pub fn loop_limit(&self) -> Option<(usize, bool)> {
+            self.loop_limit.map(|limit| (limit, self.strict_loops))
+        }
+
+        // The name of the tag the program ends at, for programs run from
+        // another tag than `_end`, so that moving it with `mktag` moves the
+        // end of the program.
+        pub fn set_end_tag(&mut self, name: impl Into<String>) {
+            self.end_tag = name.into();
+        }
+
+        pub fn end_tag(&self) -> &str {
+            &self.end_tag
+        }
+
+        pub(super) fn note_progress(&mut self) {
+            self.progress += 1;
+        }
+
+        pub fn progress(&self) -> u64 {
+            self.progress
+        }
+
+        // Fails if writing `path` from the current table, or else the root
+        // table, would create a table deeper than the limit.
+        pub fn check_depth(&self, path: &str, from_root: bool) -> Result<()> {
+            let entered = if from_root { 0 } else { self.entered.len() };
+            self.limit_depth(entered + path.matches('/').count())
+        }
+
+        fn limit_depth(&self, depth: usize) -> Result<()> {
+            if depth > self.max_depth {
+                anyhow::bail!(UndagError::NestingTooDeep {
+                    depth,
+                    limit: self.max_depth,
+                });
+            }
+            Ok(())
+        }
+
+        pub fn enter_table(&mut self, table: &str) -> Result<()> {
+            self.limit_depth(self.entered.len() + table.split('/').count())?;
+            for name in table.split('/') {
+                let name = intern(name);
+                if !self.table().contains_key(&name) {
+                    self.check_writable(&name, false)?;
+                }
+                match self
+                    .table_mut()
+                    .entry(name.clone())
+                    .or_insert_with(|| Val::Table(Table::new()))
+                {
+                    Val::Table(_) => {
+                        self.entered.push(name);
+                        self.progress += 1;
+                    }
+                    _ => anyhow::bail!(UndagError::NotATable(name.to_string())),
+                }
+            }
+            Ok(())
+        }
+
+        pub fn exit_table(&mut self) -> bool {
+            let exited = self.entered.pop().is_some();
+            self.progress += exited as u64;
+            exited
+        }
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+// Notes whether anything was written through it, which counts as progress
+// for a loop.
+struct Progress<'a, W> {
+    inner: &'a mut W,
+    wrote: bool,
+}
+
+impl<W: Write> Write for Progress<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.wrote |= !buf.is_empty();
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// How many of the most recently executed commits a `Diagnostic` lists.
+const RECENT_COMMITS: usize = 10;
+
+// The state of a running program that is kept between steps. Steps go from
+// `Oid` to `Oid` over the ops and children that `Program` read up front, so
+// the source is only read while running for signatures, `meta`, the ops that
+// write commits and tags, and tags branched to that were not walked yet.
+struct Execution {
+    program: Program,
+    replacements: Replacements,
+    start: Oid,
+    end: Oid,
+    cur: Oid,
+    steps: u64,
+    recent: VecDeque<Oid>,
+    visits: HashMap<Oid, usize>,
+    // The ancestry of each tag that `branch` may go to, walked when the
+    // program is loaded for the tags it names and otherwise the first time
+    // the tag is branched to.
+    ancestries: HashMap<Arc<str>, Option<tree::Ancestry>>,
+    // Commits whose signatures have been verified.
+    verified: HashSet<Oid>,
+    // How many times each commit has executed since anything last happened,
+    // going by `Instance::progress`, when looking out for stuck loops.
+    idle: HashMap<Oid, usize>,
+    progress: u64,
+    // Modules being run, innermost last, and the part of each module run from
+    // every commit it has been entered at.
+    calls: Vec<Call>,
+    modules: HashMap<Oid, Program>,
+}
+
+struct Call {
+    entry: Oid,
+    end: Oid,
+    // The commit that branched into the module, whose children are continued
+    // with once the module reaches its `_end`.
+    ret: Oid,
+    // Tags named within the module are looked up with this prefix.
+    namespace: String,
+}
+
+impl Execution {
+    // The program or module that the current commit belongs to.
+    fn program(&self) -> &Program {
+        match self.calls.last() {
+            Some(call) => &self.modules[&call.entry],
+            None => &self.program,
+        }
+    }
+}
+
+// Walks the ancestry of every tag that `program` branches to by name, which
+// within a module is prefixed with the module's namespace.
+fn load_ancestries(
+    ancestries: &mut HashMap<Arc<str>, Option<tree::Ancestry>>,
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    program: &Program,
+    namespace: Option<&str>,
+) {
+    for op in program.ops() {
+        if let Op::Branch(Get::Val(tag)) = op {
+            let tag = match namespace {
+                Some(namespace) => intern(&format!("{}::{}", namespace, tag)),
+                None => intern(&tag.to_string()),
+            };
+            ancestries
+                .entry(tag.clone())
+                .or_insert_with(|| tree::Ancestry::of(source, replacements, &tag));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StepResult {
+    Continued,
+    Halted,
+    // The current commit reads a line of input and none has been given with
+    // `push_input`. Stepping again retries the same commit.
+    NeedsInput,
+    // The program failed and can no longer be stepped.
+    Error(anyhow::Error),
+}
+
+impl Instance {
+    pub fn run(
+        &mut self,
+        source: &dyn ProgramSource,
+        start: Oid,
+        end: Oid,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        self.start(source, start, end)?;
+        loop {
+            match self.step(source, output) {
+                StepResult::Continued => {}
+                StepResult::Halted => break Ok(()),
+                StepResult::NeedsInput => {
+                    let mut line = Vec::new();
+                    input.read_until(b'\n', &mut line)?;
+                    self.push_input(line);
+                }
+                StepResult::Error(e) => break Err(e),
+            }
+        }
+    }
+
+    // Executes `op` on its own, outside of any program, as typed into a REPL.
+    // `branch` and the ops on the program's repository have no program to act
+    // on and fail, while `inpln` reads input given with `push_input`.
+    pub fn exec_op(&mut self, op: &Op, output: &mut impl Write) -> Result<()> {
+        match op {
+            Op::Branch(_) | Op::Meta(..) | Op::Mkcommit(..) | Op::Mktag(..) => {
+                Err(UndagError::NoProgram {
+                    op: op.name().to_owned(),
+                }
+                .into())
+            }
+            _ => self.exec(op, output),
+        }
+    }
+
+    // Loads the program from `start` to `end` so that it can be run with
+    // `step`. A resumed checkpoint continues from its saved commit instead.
+    pub fn start(&mut self, source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<()> {
+        let replacements = Replacements::load(source)?;
+        let start = replacements.resolve(start);
+        let end = replacements.resolve(end);
+
+        let program = Program::load(source, &replacements, start, end)?;
+        self.load_consts(source, start)?;
+        let mut ancestries = HashMap::new();
+        load_ancestries(&mut ancestries, source, &replacements, &program, None);
+
+        if let Some(coverage) = self.coverage_mut() {
+            coverage.set_program(tree::reachable(&program.children, start));
+        }
+
+        let cur = self.take_resume().unwrap_or(start);
+        self.set_diagnostic(None);
+        self.set_execution(Execution {
+            program,
+            replacements,
+            start,
+            end,
+            cur,
+            steps: 0,
+            recent: VecDeque::new(),
+            visits: HashMap::new(),
+            ancestries,
+            verified: HashSet::new(),
+            idle: HashMap::new(),
+            progress: self.progress(),
+            calls: Vec::new(),
+            modules: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    // Executes the current commit and moves on to the next. `source` must be
+    // the one the program was started from.
+    pub fn step(&mut self, source: &dyn ProgramSource, output: &mut impl Write) -> StepResult {
+        let mut execution = match self.take_execution() {
+            Some(execution) => execution,
+            None => return StepResult::Error(UndagError::NotRunning.into()),
+        };
+        let mut output = Progress {
+            inner: output,
+            wrote: false,
+        };
+        let result = self
+            .advance(&mut execution, source, &mut output)
+            .unwrap_or_else(StepResult::Error);
+        if output.wrote {
+            self.note_progress();
+        }
+        self.set_executing(None);
+        match result {
+            StepResult::Continued | StepResult::NeedsInput => self.set_execution(execution),
+            StepResult::Error(_) => {
+                let diagnostic = self.diagnose(&execution, source);
+                self.set_diagnostic(Some(diagnostic));
+            }
+            StepResult::Halted => {}
+        }
+        result
+    }
+
+    fn diagnose(&self, execution: &Execution, source: &dyn ProgramSource) -> Diagnostic {
+        let commit = execution.cur;
+        let message = source.message(commit).unwrap_or_default();
+        let op = execution.program().op(commit).ok();
+        let mut vars = Vec::<(String, Option<Val>)>::new();
+        for arg in op.map(Op::args).unwrap_or_default() {
+            if let Get::Var(name) = arg {
+                if vars.iter().all(|(var, _)| **var != **name) {
+                    vars.push((name.to_string(), self.get(name).ok().cloned()));
+                }
+            }
+        }
+        let mut recent = execution.recent.iter().copied().collect::<Vec<_>>();
+        if recent.last() != Some(&commit) {
+            recent.push(commit);
+        }
+        let recent = recent
+            .into_iter()
+            .map(|id| {
+                let message = source.message(id).unwrap_or_default();
+                (id, message.lines().next().unwrap_or_default().to_owned())
+            })
+            .collect();
+        Diagnostic {
+            commit,
+            message,
+            op: op.map(Op::to_string),
+            vars,
+            recent,
+        }
+    }
+
+    fn advance(
+        &mut self,
+        execution: &mut Execution,
+        source: &dyn ProgramSource,
+        output: &mut impl Write,
+    ) -> Result<StepResult> {
+        use rand::seq::SliceRandom;
+
+        let Execution {
+            program: main,
+            replacements,
+            start,
+            end,
+            cur,
+            steps,
+            recent,
+            visits,
+            ancestries,
+            verified,
+            idle,
+            progress,
+            calls,
+            modules,
+        } = execution;
+        let program = match calls.last() {
+            Some(call) => &modules[&call.entry],
+            None => &*main,
+        };
+        let children = &program.children;
+
+        *cur = replacements.resolve(*cur);
+        // A replacement carries the message of the commit it replaces, which
+        // is the one whose author is vouched for.
+        if self.verifies_signatures() && !verified.contains(cur) {
+            source.verify_signature(replacements.original(*cur), self.keyring())?;
+            verified.insert(*cur);
+        }
+        if let Some(coverage) = self.coverage_mut() {
+            coverage.record(*cur);
+        }
+        let op = program.op(*cur)?;
+        if matches!(op, Op::Inpln(..) | Op::Inb(_)) && !self.has_input() {
+            if self.prompts() {
+                if let Some(prompt) = self.prompt(op).map_err(|e| e.context(*cur))? {
+                    output
+                        .write_all(prompt.as_bytes())
+                        .map_err(UndagError::Io)?;
+                    output.flush().map_err(UndagError::Io)?;
+                }
+            }
+            // Checkpoints are also taken before waiting on input, since that
+            // is when interactive programs are most likely to be abandoned.
+            self.write_checkpoint(*cur)?;
+            return Ok(StepResult::NeedsInput);
+        }
+        *steps += 1;
+        if recent.len() == RECENT_COMMITS {
+            recent.pop_front();
+        }
+        recent.push_back(*cur);
+        if let Some((limit, strict)) = self.loop_limit() {
+            if *progress != self.progress() {
+                *progress = self.progress();
+                idle.clear();
+            }
+            let times = idle.entry(*cur).or_default();
+            *times += 1;
+            if *times > limit {
+                if strict {
+                    return Err(UndagError::StuckLoop {
+                        oid: *cur,
+                        times: *times,
+                    }
+                    .into());
+                }
+                let times = *times;
+                // Reported again only once the loop has gone on as long
+                // again.
+                idle.clear();
+                tracing::debug!(commit = %cur, times, "loop looks stuck");
+                if let Some(hook) = self.hook_mut() {
+                    hook.on_stuck_loop(*cur, times);
+                }
+            }
+        }
+        if steps.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.write_checkpoint(*cur)?;
+        }
+        if let Some(hook) = self.hook_mut() {
+            hook.before_op(*cur, op);
+        }
+        tracing::trace!(commit = %cur, %op, "step");
+        if let Op::Branch(tag) = op {
+            let mut tag = tag.name(self.scope())?;
+            if let Some(call) = calls.last() {
+                tag = intern(&format!("{}::{}", call.namespace, tag));
+            }
+            // Kept for the hook, since loading a module below gives up the
+            // borrow of the program's children.
+            let nexts = match self.hook_mut() {
+                Some(_) => children.get(cur).cloned().unwrap_or_default(),
+                None => Vec::new(),
+            };
+            let next = ancestries
+                .entry(tag.clone())
+                .or_insert_with(|| tree::Ancestry::of(source, replacements, &tag))
+                .as_ref()
+                .and_then(|ancestry| ancestry.nearest(children.get(cur)?))
+                .map(|next| next.id);
+            // A module's tag that no child leads to is branched to by running
+            // the module from there.
+            let next = match next {
+                Some(next) => next,
+                None => {
+                    let entry = tag.rsplit_once("::").and_then(|(namespace, _)| {
+                        let entry = source.tag(&tag)?;
+                        let end = source.tag(&format!("{}::_end", namespace))?;
+                        Some((
+                            namespace,
+                            replacements.resolve(entry),
+                            replacements.resolve(end),
+                        ))
+                    });
+                    let (namespace, entry, end) =
+                        entry.ok_or_else(|| UndagError::BranchNotFound {
+                            oid: *cur,
+                            tag: tag.to_string(),
+                        })?;
+                    if !modules.contains_key(&entry) {
+                        let module = Program::load(source, replacements, entry, end)?;
+                        load_ancestries(ancestries, source, replacements, &module, Some(namespace));
+                        modules.insert(entry, module);
+                    }
+                    calls.push(Call {
+                        entry,
+                        end,
+                        ret: *cur,
+                        namespace: namespace.to_owned(),
+                    });
+                    entry
+                }
+            };
+            tracing::debug!(commit = %cur, %tag, target = %next, "branched");
+            if let Some(hook) = self.hook_mut() {
+                let ancestry = ancestries.get(&tag).and_then(Option::as_ref);
+                hook.explain_branch(*cur, &tag, next, ancestry, &nexts);
+                hook.after_branch(*cur, &tag, next);
+            }
+            *cur = next;
+            return Ok(StepResult::Continued);
+        }
+        self.set_executing(Some(*cur));
+        let written = match op {
+            Op::Meta(var, field) => {
+                self.meta(source, var, field, replacements.original(*cur))
+                    .map_err(|e| e.context(*cur))?;
+                false
+            }
+            Op::Mkcommit(..) | Op::Mktag(..) => {
+                self.write(source, op, end).map_err(|e| e.context(*cur))?;
+                true
+            }
+            _ => {
+                self.exec(op, output).map_err(|e| e.context(*cur))?;
+                false
+            }
+        };
+        // The program may now have new commits, or a new `_end`.
+        if written {
+            *main = Program::load(source, replacements, *start, *end)?;
+            ancestries.clear();
+            load_ancestries(ancestries, source, replacements, main, None);
+        }
+
+        // Once a module reaches its `_end`, the commit that branched into it
+        // goes on to its children.
+        let mut from = *cur;
+        while from == calls.last().map_or(*end, |call| call.end) {
+            match calls.pop() {
+                Some(call) => from = call.ret,
+                None => return Ok(StepResult::Halted),
+            }
+        }
+        let children = match calls.last() {
+            Some(call) => &modules[&call.entry].children,
+            None => &main.children,
+        };
+        let order = self.order();
+        let next = children.get(&from).and_then(|nexts| match order {
+            Order::Random => nexts.choose(self.rng_mut()),
+            Order::Deterministic => {
+                let visit = visits.entry(from).or_default();
+                *visit += 1;
+                nexts.get((*visit - 1) % nexts.len())
+            }
+            Order::Chronological => nexts.first(),
+            Order::ParentIndex => nexts.iter().min_by_key(|next| next.parent),
+        });
+        *cur = next.ok_or(UndagError::NoChild { oid: from })?.id;
+        Ok(StepResult::Continued)
+    }
+
+    // The prompt that `op` shows before reading a line, if it is an `inpln`
+    // with one.
+    pub fn prompt(&self, op: &Op) -> Result<Option<String>> {
+        match op {
+            Op::Inpln(_, Some(prompt)) => Ok(Some(prompt.val(self.scope())?.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    // Sets `var` to a field of the commit `id`, which is the original commit
+    // if the current one is a replacement.
+    fn meta(&mut self, source: &dyn ProgramSource, var: &Get, field: &Get, id: Oid) -> Result<()> {
+        let var = var.name(self.scope())?;
+        let field = field.val(self.scope())?.to_string();
+        let val = match field.as_str() {
+            "author" => Val::Str(source.author(id)?.0.into()),
+            "email" => Val::Str(source.author(id)?.1.into()),
+            "time" => Val::Int(source.time(id)?),
+            "oid" => Val::Str(id.to_string().into()),
+            "summary" => Val::Str(
+                source
+                    .message(id)?
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .into(),
+            ),
+            _ => {
+                return Err(UndagError::UnknownField {
+                    field: field.to_string(),
+                }
+                .into())
+            }
+        };
+        self.set(&var, val)
+    }
+
+    // Executes the ops that write to the program's repository. Moving the tag
+    // the program ends at moves its end with it.
+    fn write(&mut self, source: &dyn ProgramSource, op: &Op, end: &mut Oid) -> Result<()> {
+        match op {
+            Op::Mkcommit(var, parent, message) => {
+                let var = var.name(self.scope())?;
+                let parent = parent.val(self.scope())?.to_string();
+                let parent = source.tag(&parent).ok_or_else(|| {
+                    UndagError::InvalidOperation(format!("mkcommit: unknown parent: {}", parent))
+                })?;
+                let message = message.val(self.scope())?.to_string();
+                let id = source.create_commit(&message, &[parent])?;
+                self.set(&var, Val::Str(id.to_string().into()))
+            }
+            Op::Mktag(name, commit) => {
+                let name = name.val(self.scope())?.to_string();
+                let commit = commit.val(self.scope())?.to_string();
+                let id = source.tag(&commit).ok_or_else(|| {
+                    UndagError::InvalidOperation(format!("mktag: unknown commit: {}", commit))
+                })?;
+                if name == self.end_tag() {
+                    *end = id;
+                }
+                source.create_tag(&name, id)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn exec(&mut self, op: &Op, output: &mut impl Write) -> Result<()> {
+        match op {
             Op::Nop => Ok(()),
             Op::Set(var, src) => {
-                let var = var.val(self.table())?.to_string();
-                let val = src.val(self.table())?.clone();
+                let var = var.name(self.scope())?;
+                let val = src.val(self.scope())?.clone();
                 self.set(&var, val)
             }
             Op::Get(var, src) => {
-                let var = var.val(self.table())?.to_string();
-                let val = Get::Var(src.val(self.table())?.to_string())
-                    .val(self.table())?
-                    .clone();
+                let var = var.name(self.scope())?;
+                let val = Get::Var(src.name(self.scope())?).val(self.scope())?.clone();
                 self.set(&var, val)
             }
+            Op::Ref(var, src) => {
+                let var = var.name(self.scope())?;
+                let path = self.root_path(&src.name(self.scope())?)?;
+                self.set(&var, Val::Ref(intern(&path)))
+            }
+            Op::Deref(var, src) => {
+                let var = var.name(self.scope())?;
+                let val = match src.val(self.scope())? {
+                    Val::Ref(path) => {
+                        let root = self.root_table();
+                        lookup(Scope { root, table: root }, path)?.clone()
+                    }
+                    val => {
+                        return Err(UndagError::TypeMismatch {
+                            op: "deref".to_owned(),
+                            args: vec![val.clone()],
+                        }
+                        .into())
+                    }
+                };
+                self.set(&var, val)
+            }
+            // Keys are visited in sorted order, each step finding the least key
+            // after the cursor, so that entries may be added or removed along
+            // the way. -1 stands before the first key and after the last.
+            Op::Next(var, table, cursor) => {
+                let var = var.name(self.scope())?;
+                let name = table.name(self.scope())?;
+                let after = match cursor.val(self.scope())? {
+                    Val::Int(-1) => None,
+                    cursor => Some(cursor.to_string()),
+                };
+                let next = match Get::Var(name.clone()).val(self.scope())? {
+                    Val::Table(table) => table
+                        .keys()
+                        .filter(|key| after.as_deref().is_none_or(|after| &***key > after))
+                        .min()
+                        .cloned(),
+                    _ => anyhow::bail!(UndagError::NotATable(name.to_string())),
+                };
+                self.set(&var, next.map_or(Val::Int(-1), Val::Str))
+            }
             Op::Del(var) => {
-                let var = var.val(self.table())?.to_string();
-                let mut cur = self.table_mut();
+                let var = var.name(self.scope())?;
+                let path = deref(self.scope(), &var)?;
+                let from_root = path.is_some();
+                let var = path.as_deref().unwrap_or(&var);
+                self.check_depth(var, from_root)?;
+                self.check_writable(var, from_root)?;
+                let mut cur = self.table_and_hook_mut(from_root).0;
                 let mut subs = var.split('/');
-                let tail = subs.next_back().unwrap().to_owned();
+                let tail = subs.next_back().unwrap();
                 for sub in subs {
                     cur = match cur
-                        .entry(sub.to_owned())
+                        .entry(intern(sub))
                         .or_insert_with(|| Val::Table(Table::new()))
                     {
                         Val::Table(table) => table,
-                        _ => anyhow::bail!("tried to access non-table as table: {}", var),
+                        _ => anyhow::bail!(UndagError::NotATable(var.to_string())),
                     };
                 }
-                cur.remove(&tail);
+                if cur.remove(tail).is_some() {
+                    self.note_progress();
+                }
                 Ok(())
             }
             Op::Exists(var, symbol) => {
-                let var = var.val(self.table())?.to_string();
-                let symbol = symbol.val(self.table())?.to_string();
-                let mut cur = self.table_mut();
-                let mut subs = symbol.split('/');
+                let var = var.name(self.scope())?;
+                let symbol = symbol.name(self.scope())?;
+                let path = deref(self.scope(), &symbol)?;
+                let mut cur = self.table_and_hook_mut(path.is_some()).0;
+                let mut subs = path.as_deref().unwrap_or(&symbol).split('/');
                 let tail = subs.next_back().unwrap();
                 let mut exists = true;
                 for sub in subs {
@@ -393,7 +2206,7 @@ impl Instance {
                     if cur.contains_key(sub) {
                         cur = match cur.get_mut(sub).unwrap() {
                             Val::Table(table) => table,
-                            _ => anyhow::bail!("tried to access non-table as table: {}", var),
+                            _ => anyhow::bail!(UndagError::NotATable(var.to_string())),
                         };
                     } else {
                         exists = false;
@@ -403,136 +2216,422 @@ impl Instance {
                 exists &= cur.contains_key(tail);
                 self.set(&var, Val::Int(exists as i64))
             }
-            Op::Enter(table) => self.enter_table(&table.val(self.table())?.to_string()),
+            Op::Is(var, a, b) => {
+                let var = var.name(self.scope())?;
+                let a = self.location(&a.name(self.scope())?)?;
+                let b = self.location(&b.name(self.scope())?)?;
+                self.set(&var, Val::Int((a == b) as i64))
+            }
+            Op::Enter(table) => self.enter_table(&table.name(self.scope())?),
             Op::Exit => {
                 self.exit_table();
                 Ok(())
             }
             Op::Match(var, src, branches) => {
-                let val = src.val(self.table())?;
+                let val = src.val(self.scope())?;
                 for branch in branches {
-                    if *branch.0.val(self.table())? == *val {
-                        let new_val = branch.1.val(self.table())?.clone();
-                        let var = var.val(self.table())?.to_string();
+                    if *branch.0.val(self.scope())? == *val {
+                        let new_val = branch.1.val(self.scope())?.clone();
+                        let var = var.name(self.scope())?;
                         self.set(&var, new_val)?;
                         break;
                     }
                 }
                 Ok(())
             }
-            Op::Print(arg) => arg
-                .val(self.table())
-                .and_then(|val| write!(stdout, "{}", val).map_err(From::from))
-                .and_then(|_| stdout.flush().map_err(From::from)),
-            Op::Println(arg) => arg
-                .val(self.table())
-                .and_then(|val| writeln!(stdout, "{}", val).map_err(From::from)),
-            Op::Inpln(var) => {
-                let var = var.val(self.table())?.to_string();
-                let mut s = String::new();
-                stdin.read_line(&mut s)?;
-                s.pop();
+            // Text is written all at once, so that writers see each print as a
+            // single write.
+            Op::Print(arg) => {
+                let text = arg.val(self.scope())?.to_string();
+                output.write_all(text.as_bytes()).map_err(UndagError::Io)?;
+                output.flush().map_err(UndagError::Io)?;
+                Ok(())
+            }
+            Op::Println(arg) => {
+                let line = format!("{}\n", arg.val(self.scope())?);
+                output.write_all(line.as_bytes()).map_err(UndagError::Io)?;
+                Ok(())
+            }
+            Op::Inpln(var, _) => {
+                let var = var.name(self.scope())?;
+                let mut s = self.pop_input().unwrap_or_default();
+                if s.ends_with('\n') {
+                    s.pop();
+                }
                 if s.ends_with('\r') {
                     s.pop();
                 }
-                self.set(&var, Val::Str(s))?;
+                self.set(&var, Val::Str(s.into()))?;
                 Ok(())
             }
+            Op::Outb(byte) => match byte.val(self.scope())? {
+                &Val::Int(n @ 0..=255) => {
+                    output.write_all(&[n as u8]).map_err(UndagError::Io)?;
+                    output.flush().map_err(UndagError::Io)?;
+                    Ok(())
+                }
+                val => Err(UndagError::TypeMismatch {
+                    op: "outb".to_owned(),
+                    args: vec![val.clone()],
+                }
+                .into()),
+            },
+            // The end of the input reads as -1.
+            Op::Inb(var) => {
+                let var = var.name(self.scope())?;
+                let byte = self.pop_input_byte().map_or(-1, i64::from);
+                self.set(&var, Val::Int(byte))
+            }
+            // Positions count from 0 at the top left, while the terminal's
+            // count from 1.
+            Op::Cls | Op::GotoXy(..) | Op::Color(_) => {
+                let args = op
+                    .args()
+                    .into_iter()
+                    .map(|arg| arg.val(self.scope()).cloned())
+                    .collect::<Result<Vec<_>>>()?;
+                let code = match (op, &args[..]) {
+                    (Op::Cls, []) => "\x1b[2J\x1b[H".to_owned(),
+                    (Op::GotoXy(..), &[Val::Int(x @ 0..), Val::Int(y @ 0..)]) => {
+                        format!("\x1b[{};{}H", y + 1, x + 1)
+                    }
+                    (Op::Color(_), &[Val::Int(n @ 0..=255)]) => format!("\x1b[{}m", n),
+                    _ => {
+                        return Err(UndagError::TypeMismatch {
+                            op: op.name().to_owned(),
+                            args,
+                        }
+                        .into())
+                    }
+                };
+                if self.ansi() {
+                    output.write_all(code.as_bytes()).map_err(UndagError::Io)?;
+                    output.flush().map_err(UndagError::Io)?;
+                }
+                Ok(())
+            }
+            Op::Rawmode(mode) => {
+                let raw = match mode.val(self.scope())? {
+                    Val::Str(mode) if &**mode == "on" => true,
+                    Val::Str(mode) if &**mode == "off" => false,
+                    val => {
+                        return Err(UndagError::TypeMismatch {
+                            op: "rawmode".to_owned(),
+                            args: vec![val.clone()],
+                        }
+                        .into())
+                    }
+                };
+                if let Some(terminal) = self.terminal_mut() {
+                    terminal.set_raw(raw)?;
+                }
+                Ok(())
+            }
+            // No key being pressed reads as an empty string.
+            Op::Key(var) => {
+                let var = var.name(self.scope())?;
+                let key = match self.terminal_mut() {
+                    Some(terminal) => terminal.key()?.unwrap_or_default(),
+                    None => String::new(),
+                };
+                self.set(&var, Val::Str(key.into()))
+            }
+            Op::Clock(var) => {
+                let var = var.name(self.scope())?;
+                let now = self.clock().ok_or(UndagError::NoClock)?;
+                self.set(&var, Val::Int(now))
+            }
             Op::Concat(var, a, b) => {
-                let var = var.val(self.table())?.to_string();
-                let concat = format!("{}{}", a.val(self.table())?, b.val(self.table())?);
-                self.set(&var, Val::Str(concat))?;
+                let var = var.name(self.scope())?;
+                let concat = format!("{}{}", a.val(self.scope())?, b.val(self.scope())?);
+                self.set(&var, Val::Str(concat.into()))?;
                 Ok(())
             }
-            Op::Chars(var, string) => {
-                let var = var.val(self.table())?.to_string();
-                let mut table = Table::new();
-                string
-                    .val(self.table())?
-                    .to_string()
-                    .chars()
+            Op::Chars(var, string) | Op::Graphemes(var, string) => {
+                use bstr::ByteSlice;
+                let var = var.name(self.scope())?;
+                let string = string.val(self.scope())?.to_string();
+                let pieces = match op {
+                    Op::Chars(..) => string
+                        .char_indices()
+                        .map(|(i, c)| &string[i..i + c.len_utf8()])
+                        .collect::<Vec<_>>(),
+                    _ => string.as_bytes().graphemes().collect(),
+                };
+                let mut table = pieces
+                    .into_iter()
                     .enumerate()
-                    .map(|(i, c)| (i.to_string(), Val::Str(c.to_string())))
-                    .for_each(|(i, c)| drop(table.insert(i, c)));
-                table.insert("len".to_owned(), Val::Int(table.len() as i64));
+                    .map(|(i, piece)| (intern(&i.to_string()), Val::Str(intern(piece))))
+                    .collect::<Table>();
+                table.insert(intern("len"), Val::Int(table.len() as i64));
+                self.set(&var, Val::Table(table))
+            }
+            // Counts characters like `chars`, without building its table.
+            Op::Charat(var, string, index) => {
+                let var = var.name(self.scope())?;
+                let string = string.val(self.scope())?;
+                let index = index.val(self.scope())?;
+                let c = match index {
+                    &Val::Int(i) => usize::try_from(i)
+                        .ok()
+                        .and_then(|i| string.to_string().chars().nth(i)),
+                    _ => None,
+                };
+                match c {
+                    Some(c) => self.set(&var, Val::Str(intern(c.encode_utf8(&mut [0; 4])))),
+                    None => Err(UndagError::TypeMismatch {
+                        op: "charat".to_owned(),
+                        args: vec![string.clone(), index.clone()],
+                    }
+                    .into()),
+                }
+            }
+            // Values are compared as the text they print as, like `concat`
+            // joins them.
+            Op::Contains(var, haystack, needle)
+            | Op::Startswith(var, haystack, needle)
+            | Op::Endswith(var, haystack, needle) => {
+                let var = var.name(self.scope())?;
+                let haystack = haystack.val(self.scope())?.to_string();
+                let needle = needle.val(self.scope())?.to_string();
+                let found = match op {
+                    Op::Contains(..) => haystack.contains(&needle),
+                    Op::Startswith(..) => haystack.starts_with(&needle),
+                    _ => haystack.ends_with(&needle),
+                };
+                self.set(&var, Val::Int(found as i64))
+            }
+            Op::Tostr(var, val, base) | Op::Fromstr(var, val, base) => {
+                let var = var.name(self.scope())?;
+                let val = val.val(self.scope())?;
+                let base = base.val(self.scope())?;
+                match radix(op, val, base) {
+                    Some(converted) => self.set(&var, converted),
+                    None => Err(UndagError::TypeMismatch {
+                        op: op.name().to_owned(),
+                        args: vec![val.clone(), base.clone()],
+                    }
+                    .into()),
+                }
+            }
+            // Integers are their own numerator, over 1.
+            Op::Numer(var, n) | Op::Denom(var, n) => {
+                let var = var.name(self.scope())?;
+                let part = match (op, n.val(self.scope())?) {
+                    (Op::Numer(..), Val::Ratio(r)) => Val::from(r.numer().clone()),
+                    (Op::Denom(..), Val::Ratio(r)) => Val::from(r.denom().clone()),
+                    (Op::Numer(..), n @ (Val::Int(_) | Val::BigInt(_))) => n.clone(),
+                    (Op::Denom(..), Val::Int(_) | Val::BigInt(_)) => Val::Int(1),
+                    (_, n) => {
+                        return Err(UndagError::TypeMismatch {
+                            op: op.name().to_owned(),
+                            args: vec![n.clone()],
+                        }
+                        .into())
+                    }
+                };
+                self.set(&var, part)
+            }
+            Op::Sqrt(var, n)
+            | Op::Floor(var, n)
+            | Op::Ceil(var, n)
+            | Op::Round(var, n)
+            | Op::Sin(var, n)
+            | Op::Cos(var, n)
+            | Op::Ln(var, n) => {
+                let var = var.name(self.scope())?;
+                let n = n.val(self.scope())?;
+                let result = math(op, n).ok_or_else(|| UndagError::TypeMismatch {
+                    op: op.name().to_owned(),
+                    args: vec![n.clone()],
+                })?;
+                self.set(&var, result)
+            }
+            // As JSON, whose objects serde_json keeps sorted by key, so that
+            // equal tables dump the same.
+            Op::Dump(var, table) => {
+                let var = var.name(self.scope())?;
+                let json = match table.val(self.scope())? {
+                    Val::Table(table) => crate::state::to_json(table).to_string(),
+                    val => {
+                        return Err(UndagError::TypeMismatch {
+                            op: "dump".to_owned(),
+                            args: vec![val.clone()],
+                        }
+                        .into())
+                    }
+                };
+                self.set(&var, Val::Str(json.into()))
+            }
+            // Reads JSON objects in general, like `--init-state` does.
+            Op::Load(var, string) => {
+                let var = var.name(self.scope())?;
+                let string = string.val(self.scope())?.to_string();
+                let table = serde_json::from_str(&string)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|json| crate::state::from_json(&json))
+                    .with_context(|| format!("load: invalid table: {}", string))?;
                 self.set(&var, Val::Table(table))
             }
+            Op::Strrev(var, string) => {
+                let var = var.name(self.scope())?;
+                let reversed = string
+                    .val(self.scope())?
+                    .to_string()
+                    .chars()
+                    .rev()
+                    .collect::<String>();
+                self.set(&var, Val::Str(reversed.into()))
+            }
+            Op::Strrep(var, string, count) => {
+                let var = var.name(self.scope())?;
+                let string = string.val(self.scope())?;
+                let count = count.val(self.scope())?;
+                match count {
+                    &Val::Int(n @ 0..) => {
+                        let repeated = repeat(&string.to_string(), n, MAX_REPEAT_LEN)?;
+                        self.set(&var, Val::Str(repeated.into()))
+                    }
+                    _ => Err(UndagError::TypeMismatch {
+                        op: "strrep".to_owned(),
+                        args: vec![string.clone(), count.clone()],
+                    }
+                    .into()),
+                }
+            }
+            // Tables are equal when they hold equal values under the same keys,
+            // whatever order they were written in, and references when they
+            // hold the same path.
             Op::Eq(var, a, b) => {
-                let a = a.val(self.table())?;
-                let b = b.val(self.table())?;
+                let a = a.val(self.scope())?;
+                let b = b.val(self.scope())?;
                 let eq = (a == b) as i64;
-                let var = var.val(self.table())?.to_string();
+                let var = var.name(self.scope())?;
                 self.set(&var, Val::Int(eq))?;
                 Ok(())
             }
-            Op::Gt(var, a, b) => num_binop(var, a, b, self, |a, b| (a > b) as i64, "gt"),
-            Op::Add(var, a, b) => num_binop(var, a, b, self, |a, b| a + b, "add"),
-            Op::Sub(var, a, b) => num_binop(var, a, b, self, |a, b| a - b, "sub"),
-            Op::Mul(var, a, b) => num_binop(var, a, b, self, |a, b| a * b, "mul"),
-            Op::Div(var, a, b) => num_binop(var, a, b, self, |a, b| a / b, "div"),
-            Op::Mod(var, a, b) => num_binop(var, a, b, self, |a, b| a % b, "mod"),
-            Op::And(var, a, b) => num_binop(var, a, b, self, |a, b| a & b, "and"),
-            Op::Or(var, a, b) => num_binop(var, a, b, self, |a, b| a | b, "or"),
-            Op::Xor(var, a, b) => num_binop(var, a, b, self, |a, b| a ^ b, "xor"),
-            Op::Branch(_) => unreachable!(),
+            Op::Gt(var, a, b)
+            | Op::Add(var, a, b)
+            | Op::Sub(var, a, b)
+            | Op::Mul(var, a, b)
+            | Op::Div(var, a, b)
+            | Op::Mod(var, a, b)
+            | Op::And(var, a, b)
+            | Op::Or(var, a, b)
+            | Op::Xor(var, a, b)
+            | Op::Rotl(var, a, b)
+            | Op::Rotr(var, a, b)
+            | Op::Rdiv(var, a, b)
+            | Op::Radd(var, a, b)
+            | Op::Rsub(var, a, b)
+            | Op::Rmul(var, a, b) => {
+                let a = a.val(self.scope())?;
+                let b = b.val(self.scope())?;
+                if matches!(op, Op::Div(..) | Op::Mod(..) | Op::Rdiv(..)) && *b == Val::Int(0) {
+                    anyhow::bail!(UndagError::DivisionByZero);
+                }
+                let result = arith(op, a, b).ok_or_else(|| UndagError::TypeMismatch {
+                    op: op.name().to_owned(),
+                    args: vec![a.clone(), b.clone()],
+                })?;
+                let var = var.name(self.scope())?;
+                self.set(&var, result)
+            }
+            Op::Extern(name, args) => {
+                let f = self
+                    .extern_fn(name)
+                    .ok_or_else(|| UndagError::InvalidOperation(name.clone()))?;
+                let args = args
+                    .iter()
+                    .map(|arg| arg.val(self.scope()).cloned())
+                    .collect::<Result<Vec<_>>>()?;
+                f(self, &args)
+            }
+            Op::Branch(_) | Op::Meta(..) | Op::Mkcommit(..) | Op::Mktag(..) => unreachable!(),
         }
     }
 
-    fn set(&mut self, var: &str, val: Val) -> Result<()> {
-        let mut cur = self.table_mut();
-        let mut subs = var.split('/');
-        let tail = subs.next_back().unwrap().to_owned();
+    // The path from the root table to the variable `var` stands for, following
+    // any references along it and then any it holds, so that two names are of
+    // the same variable exactly when their locations are equal.
+    fn location(&self, var: &str) -> Result<String> {
+        let mut path = self.root_path(var)?;
+        let root = self.root_table();
+        for _ in 0..MAX_REFS {
+            match lookup(Scope { root, table: root }, &path) {
+                Ok(Val::Ref(target)) => path = target.to_string(),
+                _ => return Ok(path),
+            }
+        }
+        anyhow::bail!(UndagError::RefDepth {
+            var: var.to_owned(),
+            limit: MAX_REFS,
+        })
+    }
+
+    pub fn get(&self, var: &str) -> Result<&Val> {
+        lookup(self.scope(), var)
+    }
+
+    // Writes through any reference along `var` into the variable it refers
+    // to, though hooks are told of the write to `var` as written.
+    pub fn set(&mut self, var: &str, val: Val) -> Result<()> {
+        let watched = match self.watches().is_empty() {
+            true => None,
+            false => Some(self.root_path(var)?).filter(|path| {
+                self.watches().iter().any(|watch| {
+                    let within = |outer: &str, inner: &str| {
+                        inner
+                            .strip_prefix(outer)
+                            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                    };
+                    within(watch, path) || within(path, watch)
+                })
+            }),
+        };
+        let old = watched
+            .as_ref()
+            .map(|path| lookup_in(self.root_table(), path).ok().cloned());
+        let path = deref(self.scope(), var)?;
+        let from_root = path.is_some();
+        let target = path.as_deref().unwrap_or(var);
+        self.check_depth(target, from_root)?;
+        self.check_writable(target, from_root)?;
+        // Values are only compared when looking out for stuck loops.
+        let detecting = self.loop_limit().is_some();
+        let (mut cur, hook) = self.table_and_hook_mut(from_root);
+        let mut subs = target.split('/');
+        let tail = intern(subs.next_back().unwrap());
         for sub in subs {
             cur = match cur
-                .entry(sub.to_owned())
+                .entry(intern(sub))
                 .or_insert_with(|| Val::Table(Table::new()))
             {
                 Val::Table(table) => table,
-                _ => anyhow::bail!("tried to access non-table as table: {}", var),
+                _ => anyhow::bail!(UndagError::NotATable(var.to_owned())),
             };
         }
-        cur.insert(tail, val);
-        Ok(())
-    }
-
-    fn find_tag<'a, 'b>(
-        repo: &'a Repository,
-        tag: &str,
-        commits: &'b [Commit<'a>],
-    ) -> Option<&'b Commit<'a>> {
-        let mut found = Vec::new();
-
-        let mut tag = repo
-            .find_reference(&format!("refs/tags/{}", tag))
-            .and_then(|tag| tag.peel_to_commit())
-            .ok()?;
-        replace(repo, &mut tag);
-
-        let mut checked = HashSet::new();
-        let mut stack = vec![(tag, 0)];
-        let mut dist = 1;
-        while let Some((cur, i)) = stack.last_mut() {
-            if let Some(commit) = commits.iter().find(|commit| commit.id() == cur.id()) {
-                found.push((commit, dist));
-            }
-            if let Ok(mut parent) = cur.parent(*i) {
-                replace(repo, &mut parent);
-                if checked.insert(parent.id()) {
-                    stack.push((parent, 0));
-                    dist += 1;
-                } else {
-                    *i += 1;
-                    continue;
-                }
-            } else {
-                stack.pop();
-                dist -= 1;
+        let (val, changed) = match cur.entry(tail) {
+            Entry::Occupied(mut entry) => {
+                let changed = detecting && *entry.get() != val;
+                entry.insert(val);
+                (entry.into_mut(), changed)
             }
+            Entry::Vacant(entry) => (entry.insert(val), detecting),
+        };
+        if let Some(hook) = hook {
+            hook.on_write(var, val);
         }
-
-        found
-            .into_iter()
-            .min_by_key(|(_, dist)| *dist)
-            .map(|(commit, _)| commit)
+        if changed {
+            self.note_progress();
+        }
+        if let (Some(path), Some(old)) = (watched, old) {
+            let commit = self.executing();
+            if let (root, Some(hook)) = self.table_and_hook_mut(true) {
+                let new = lookup_in(root, &path).ok();
+                hook.on_watch(&path, commit, old.as_ref(), new);
+            }
+        }
+        Ok(())
     }
 }
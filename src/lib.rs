@@ -0,0 +1,231 @@
+pub mod asm;
+#[cfg(feature = "git")]
+pub mod bundle;
+pub mod bytecode;
+pub mod check;
+#[cfg(feature = "git")]
+pub mod commit_graph;
+pub mod coverage;
+pub mod dag;
+pub mod diagnostic;
+pub mod dot;
+pub mod error;
+pub mod explain;
+pub mod fixtures;
+#[cfg(feature = "git")]
+pub mod fmt;
+mod intern;
+pub mod interp;
+pub mod lsp;
+pub mod module;
+#[cfg(feature = "git")]
+pub mod optimize;
+pub mod program;
+#[cfg(feature = "python")]
+mod python;
+pub mod source;
+pub mod state;
+pub mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod trace;
+pub mod transpile;
+pub mod tree;
+
+pub use diagnostic::Diagnostic;
+pub use error::UndagError;
+#[cfg(feature = "git")]
+pub use git;
+#[cfg(feature = "gitoxide")]
+pub use gix;
+pub use interp::{Get, Hook, Instance, Op, Order, StepResult, Table, Val};
+pub use source::{Oid, ProgramSource};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+
+/// Runs the program in the repository at `path` with default settings.
+#[cfg(feature = "git")]
+pub fn run(path: impl AsRef<std::path::Path>) -> Result<()> {
+    let source = open(path)?;
+    let (start, end) = endpoints(&*source)?;
+    Instance::new().run(
+        &*source,
+        start,
+        end,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    )
+}
+
+/// Opens the repository at `path`, linking its submodules as modules. A file
+/// is either a git bundle, a Graphviz `.dot` graph or a program exported with
+/// `undag export-dag`.
+#[cfg(feature = "git")]
+#[tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))]
+pub fn open(path: impl AsRef<std::path::Path>) -> Result<Box<dyn ProgramSource>> {
+    let path = path.as_ref();
+    Ok(if bundle::Bundle::is_bundle(path) {
+        Box::new(bundle::Bundle::open(path)?)
+    } else if path.is_file() {
+        Box::new(open_file(path)?)
+    } else {
+        Box::new(module::with_submodules(open_repository(path)?)?)
+    })
+}
+
+/// Opens a program like `open`, or clones it first if `location` is a URL
+/// such as `https://host/repo.git` or `git@host:repo.git`.
+#[cfg(feature = "git")]
+pub fn open_location(location: &str) -> Result<Box<dyn ProgramSource>> {
+    let scp_like = location
+        .split_once(':')
+        .is_some_and(|(host, _)| host.contains('@') && !host.contains('/'));
+    if location.contains("://") || scp_like {
+        Ok(Box::new(bundle::Bundle::clone_url(location)?))
+    } else {
+        open(location)
+    }
+}
+
+/// Opens a program file, either a Graphviz `.dot` graph, an `.undag` text
+/// file, a `.udbc` compiled program or JSON.
+pub fn open_file(path: impl AsRef<std::path::Path>) -> Result<dag::Dag> {
+    use anyhow::Context;
+    let path = path.as_ref();
+    if matches!(path.extension(), Some(ext) if ext == "udbc") {
+        return bytecode::load(&std::fs::read(path)?)
+            .with_context(|| format!("invalid compiled program: {}", path.display()));
+    }
+    let text = std::fs::read_to_string(path)?;
+    if matches!(path.extension(), Some(ext) if ext == "dot" || ext == "gv") {
+        dot::from_dot(&text).with_context(|| format!("invalid graph: {}", path.display()))
+    } else if matches!(path.extension(), Some(ext) if ext == "undag") {
+        asm::assemble(&text).with_context(|| format!("invalid program: {}", path.display()))
+    } else {
+        dag::Dag::from_json(&text)
+            .with_context(|| format!("invalid program file: {}", path.display()))
+    }
+}
+
+/// Opens the repository containing `path`, which may be anywhere within its
+/// worktree or a bare repository.
+#[cfg(feature = "git")]
+pub fn open_repository(path: impl AsRef<std::path::Path>) -> Result<git::Repository> {
+    use anyhow::Context;
+    let path = path.as_ref();
+    git::Repository::discover(path)
+        .with_context(|| format!("no repository found at {}", path.display()))
+}
+
+/// Opens the repository containing `path` with gitoxide rather than libgit2.
+#[cfg(feature = "gitoxide")]
+#[tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))]
+pub fn open_gitoxide(path: impl AsRef<std::path::Path>) -> Result<source::Gitoxide> {
+    use anyhow::Context;
+    let path = path.as_ref();
+    let repo = gix::discover(path)
+        .with_context(|| format!("no repository found at {}", path.display()))?;
+    Ok(source::Gitoxide::new(repo))
+}
+
+/// Finds the commits tagged `_start` and `_end`.
+pub fn endpoints(source: &dyn ProgramSource) -> Result<(Oid, Oid)> {
+    tagged_endpoints(source, "_start", "_end")
+}
+
+/// Finds the commits tagged `start` and `end`, for repositories holding more
+/// than one program.
+pub fn tagged_endpoints(source: &dyn ProgramSource, start: &str, end: &str) -> Result<(Oid, Oid)> {
+    let find = |name: &str| {
+        source.tag(name).ok_or_else(|| UndagError::MissingTag {
+            tag: name.to_owned(),
+            help: missing_tag_help(source, name, start, end),
+        })
+    };
+    Ok((find(start)?, find(end)?))
+}
+
+// How many of a repository's tags are listed when one is missing.
+const LISTED_TAGS: usize = 20;
+
+// Suggests the tags whose names are close to the missing `name`, lists the
+// rest, and explains how a program's start and end are laid out.
+fn missing_tag_help(source: &dyn ProgramSource, name: &str, start: &str, end: &str) -> String {
+    let mut tags = source.tags().unwrap_or_default();
+    tags.sort();
+    let mut close = tags
+        .iter()
+        .map(|tag| (strsim::jaro(&name.to_lowercase(), &tag.to_lowercase()), tag))
+        .filter(|(similarity, _)| *similarity > 0.7)
+        .collect::<Vec<_>>();
+    close.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut help = String::new();
+    if !close.is_empty() {
+        let close = close.iter().take(3).map(|(_, tag)| tag.as_str());
+        help += &format!(
+            "  did you mean {}?\n",
+            close.collect::<Vec<_>>().join(" or ")
+        );
+    }
+    match tags.len() {
+        0 => help += "  the repository has no tags or branches\n",
+        n => {
+            help += &format!(
+                "  tags and branches: {}",
+                tags[..n.min(LISTED_TAGS)].join(", ")
+            );
+            if n > LISTED_TAGS {
+                help += &format!(" and {} more", n - LISTED_TAGS);
+            }
+            help.push('\n');
+        }
+    }
+    help += &format!(
+        "  a program runs from the commit tagged {} through its descendants to the commit \
+         tagged {}; tag them with `git tag {} <commit>` and `git tag {} <commit>`",
+        start, end, start, end
+    );
+    help
+}
+
+// The commits replaced with `git replace`, by the commit replacing them, and
+// the other way around.
+pub struct Replacements {
+    replaced: HashMap<Oid, Oid>,
+    originals: HashMap<Oid, Oid>,
+}
+
+impl Replacements {
+    fn load(source: &dyn ProgramSource) -> Result<Self> {
+        let replacements = source.replacements()?;
+        let mut originals = HashMap::new();
+        for &(old, new) in &replacements {
+            originals.entry(new).or_insert(old);
+        }
+        Ok(Self {
+            replaced: replacements.into_iter().collect(),
+            originals,
+        })
+    }
+
+    // The commit that `id` is ultimately replaced by, or `id` itself.
+    fn resolve(&self, mut id: Oid) -> Oid {
+        while let Some(&new) = self.replaced.get(&id) {
+            id = new;
+        }
+        id
+    }
+
+    fn is_empty(&self) -> bool {
+        self.replaced.is_empty()
+    }
+
+    // The commit that `id` ultimately replaces, or `id` itself.
+    fn original(&self, mut id: Oid) -> Oid {
+        while let Some(&old) = self.originals.get(&id) {
+            id = old;
+        }
+        id
+    }
+}
@@ -0,0 +1,287 @@
+use crate::asm;
+use crate::interp::{Get, Op};
+use crate::UndagError;
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+// Serves the Language Server Protocol for programs written as text, reading
+// messages from `input` and writing them to `output` until the client exits.
+// Documents are checked as they change, reporting ops that fail to parse and
+// labels that are never defined, labels can be followed to their definition,
+// and hovering over an op shows its entry in the README's instruction list.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut documents = HashMap::<String, String>::new();
+    while let Some(message) = read_message(&mut input)? {
+        // Responses to requests from the server have no method.
+        let Some(method) = message["method"].as_str() else {
+            continue;
+        };
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "undag" },
+            })),
+            "shutdown" => Some(Value::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str()),
+                };
+                let text = text.unwrap_or_default().to_owned();
+                publish(&mut output, &uri, diagnostics(&text))?;
+                documents.insert(uri, text);
+                None
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&mut output, &uri, Vec::new())?;
+                None
+            }
+            "textDocument/definition" | "textDocument/hover" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+                let column = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+                Some(match method {
+                    "textDocument/definition" => definition(text, &uri, line, column),
+                    _ => hover(text, line, column),
+                })
+            }
+            _ => None,
+        };
+        if let Some(id) = message.get("id") {
+            let response = match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("unknown method: {}", method) },
+                }),
+            };
+            write_message(&mut output, &response)?;
+        }
+    }
+    Ok(())
+}
+
+fn diagnostics(text: &str) -> Vec<Value> {
+    let mut diagnostics = Vec::new();
+    let mut labels = HashSet::new();
+    // Labels named by edges, which must be defined, and by `branch`, which
+    // may also name tags outside the file.
+    let mut references = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(targets) = trimmed.strip_prefix("->") {
+            references.extend(targets.split_whitespace().map(|target| (i, target, true)));
+            continue;
+        }
+        let (line_labels, op) = asm::split_labels(trimmed);
+        for label in line_labels {
+            if !labels.insert(label) {
+                let message = format!("label {} is already defined", label);
+                diagnostics.push(diagnostic(
+                    word_range(line, i, &format!("{}:", label)),
+                    1,
+                    message,
+                ));
+            }
+        }
+        if op.is_empty() || op == "nop" {
+            continue;
+        }
+        match op.parse::<Op>() {
+            Ok(Op::Branch(Get::Val(target))) => {
+                let target = target.to_string();
+                if let Some(target) = op.split_whitespace().find(|word| *word == target) {
+                    references.push((i, target, false));
+                }
+            }
+            // The command line registers no extern operations.
+            Ok(Op::Extern(name, _)) => {
+                let message = UndagError::InvalidOperation(name).to_string();
+                diagnostics.push(diagnostic(op_range(line, i, op), 1, message));
+            }
+            Ok(_) => {}
+            Err(e) => diagnostics.push(diagnostic(op_range(line, i, op), 1, format!("{:#}", e))),
+        }
+    }
+    for (i, target, edge) in references {
+        if labels.contains(target) {
+            continue;
+        }
+        let range = word_range(text.lines().nth(i).unwrap(), i, target);
+        if edge {
+            diagnostics.push(diagnostic(range, 1, format!("undefined label: {}", target)));
+        } else if !target.contains("::") {
+            let message = format!(
+                "no label named {}; it must be a tag outside the file",
+                target
+            );
+            diagnostics.push(diagnostic(range, 2, message));
+        }
+    }
+
+    // Anything else the assembler rejects, such as a label with no op after it.
+    if diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic["severity"] != 1)
+    {
+        if let Err(e) = asm::assemble(text) {
+            let message = format!("{:#}", e);
+            let (i, message) = message
+                .strip_prefix("line ")
+                .and_then(|rest| rest.split_once(": "))
+                .and_then(|(n, message)| Some((n.parse::<usize>().ok()? - 1, message.to_owned())))
+                .unwrap_or((0, message));
+            let line = text.lines().nth(i).unwrap_or_default();
+            diagnostics.push(diagnostic(op_range(line, i, line.trim()), 1, message));
+        }
+    }
+    diagnostics
+}
+
+fn definition(text: &str, uri: &str, line: usize, column: usize) -> Value {
+    let Some((word, _)) = word_at(text, line, column) else {
+        return Value::Null;
+    };
+    let word = word.strip_suffix(':').unwrap_or(word);
+    for (i, line) in text.lines().enumerate() {
+        let (labels, _) = asm::split_labels(line.trim());
+        if labels.contains(&word) {
+            return json!({ "uri": uri, "range": word_range(line, i, &format!("{}:", word)) });
+        }
+    }
+    Value::Null
+}
+
+fn hover(text: &str, line: usize, column: usize) -> Value {
+    let Some((word, start)) = word_at(text, line, column) else {
+        return Value::Null;
+    };
+    let line = text.lines().nth(line).unwrap_or_default();
+    let (_, op) = asm::split_labels(line.trim());
+    if op.is_empty() || offset(line, op) != start {
+        return Value::Null;
+    }
+    match docs(word) {
+        Some(docs) => json!({ "contents": { "kind": "markdown", "value": docs } }),
+        None => Value::Null,
+    }
+}
+
+// The entry for the op `name` in the README's instruction list.
+fn docs(name: &str) -> Option<String> {
+    include_str!("../README.md").lines().find_map(|row| {
+        let (invocation, description) = row.strip_prefix("| `")?.split_once("` | ")?;
+        let description = description.trim_end_matches('|').trim_end();
+        (invocation.split_whitespace().next()? == name)
+            .then(|| format!("```\n{}\n```\n{}", invocation, description))
+    })
+}
+
+// The word around `column` of line `line`, along with the byte offset it
+// starts at.
+fn word_at(text: &str, line: usize, column: usize) -> Option<(&str, usize)> {
+    let line = text.lines().nth(line)?;
+    let at = line
+        .char_indices()
+        .nth(column)
+        .map_or(line.len(), |(i, _)| i);
+    let start = line[..at]
+        .rfind(char::is_whitespace)
+        .map_or(0, |i| i + line[i..].chars().next().unwrap().len_utf8());
+    let end = line[at..]
+        .find(char::is_whitespace)
+        .map_or(line.len(), |i| at + i);
+    let word = &line[start..end];
+    (!word.is_empty()).then_some((word, start))
+}
+
+// The byte offset of `part`, a slice of `line`, within it.
+fn offset(line: &str, part: &str) -> usize {
+    part.as_ptr() as usize - line.as_ptr() as usize
+}
+
+// Positions count characters rather than the UTF-16 code units of the
+// protocol, which only differ outside the Basic Multilingual Plane.
+fn position(line: &str, i: usize, byte: usize) -> Value {
+    json!({ "line": i, "character": line[..byte].chars().count() })
+}
+
+fn op_range(line: &str, i: usize, op: &str) -> Value {
+    let start = line.find(op).unwrap_or_default();
+    json!({ "start": position(line, i, start), "end": position(line, i, line.len()) })
+}
+
+fn word_range(line: &str, i: usize, word: &str) -> Value {
+    let start = line
+        .split_whitespace()
+        .find(|w| *w == word)
+        .map_or(0, |w| offset(line, w));
+    json!({
+        "start": position(line, i, start),
+        "end": position(line, i, start + word.len()),
+    })
+}
+
+fn diagnostic(range: Value, severity: u8, message: String) -> Value {
+    json!({ "range": range, "severity": severity, "source": "undag", "message": message })
+}
+
+fn publish(output: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> Result<()> {
+    write_message(
+        output,
+        &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }),
+    )
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let mut body = vec![0; length.context("message without a Content-Length header")?];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
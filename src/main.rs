@@ -1,36 +1,1406 @@
-mod interp;
-mod tree;
-
 use anyhow::{Context, Result};
-use git::Repository;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use undag::dag::Dag;
+use undag::explain::BranchExplainer;
+use undag::module::Linked;
+use undag::stats::{RunStats, Stats};
+use undag::trace::{KeyRecorder, Recorder, ReplayedKeys, Timeline, Trace};
+use undag::{
+    asm, bytecode, check, dot, fixtures, fmt, optimize, state, transpile, Instance, Oid, Op, Order,
+    ProgramSource, StepResult, Val,
+};
+
+mod examples;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "tui")]
+mod tui;
+
+fn main() {
+    // Errors raised while executing a commit carry the commit as context, so
+    // the whole chain is printed on one line.
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[derive(Parser)]
+#[command(version, about = "Runs Git repositories as programs")]
+// A path without a subcommand is run, as `undag <repo>`.
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Log what the interpreter does to stderr: -v for loading programs and
+    /// resolving branches, -vv for every step as well
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program
+    Run(RunArgs),
+    /// Run a program in a terminal interface showing the commits around the
+    /// current one, the variables and the output
+    #[cfg(feature = "tui")]
+    Tui {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        order: OrderArgs,
+        /// Start running at this many steps per second instead of paused
+        #[arg(long, value_name = "STEPS")]
+        speed: Option<f64>,
+    },
+    /// Run a program while serving a web page that draws its commits and
+    /// follows the run live
+    #[cfg(feature = "serve")]
+    Serve {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        order: OrderArgs,
+        /// Address to serve the page on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// Steps to take per second
+        #[arg(long, value_name = "STEPS", default_value_t = 5.0)]
+        speed: f64,
+    },
+    /// Run a program again exactly as it ran when its trace was recorded
+    Replay {
+        /// Trace recorded with `--trace`
+        #[arg(value_name = "TRACE")]
+        file: PathBuf,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Type ops and run each one immediately
+    Repl {
+        /// Append each op that succeeds as a commit to this repository,
+        /// creating it if needed, after the commit tagged `_end`
+        #[arg(long, value_name = "REPO")]
+        commit_to: Option<PathBuf>,
+    },
+    /// List the example programs, or run one
+    Examples {
+        /// Example to run
+        name: Option<String>,
+        /// Write the example into this repository, creating it if needed,
+        /// instead of running it
+        #[arg(long, value_name = "REPO", requires = "name")]
+        write: Option<PathBuf>,
+    },
+    /// Run a program on each test case in a directory and compare its output
+    Test {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        order: OrderArgs,
+        /// Directory of NAME.out files with the expected output and optional
+        /// NAME.in files with the input; defaults to `tests` in the repository
+        /// or beside the program file
+        #[arg(long, value_name = "DIR")]
+        tests: Option<PathBuf>,
+    },
+    /// Time repeated runs of a program
+    Bench {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        order: OrderArgs,
+        /// Number of runs
+        #[arg(short = 'n', long, default_value_t = 10)]
+        runs: u32,
+        /// File to read the program's input from on every run
+        #[arg(long, value_name = "FILE")]
+        input: Option<PathBuf>,
+    },
+    /// Report errors and structural problems without running the program
+    Check(SourceArgs),
+    /// Report figures describing the shape of a program
+    Stats(SourceArgs),
+    /// Draw a program as a Graphviz digraph on stdout
+    Graph(SourceArgs),
+    /// Write a program as text that `build` assembles back
+    Disasm(SourceArgs),
+    /// Serve the Language Server Protocol for `.undag` files over stdio
+    Lsp,
+    /// Compile a program into a file that runs without a repository
+    Compile {
+        #[command(flatten)]
+        source: SourceArgs,
+        /// File to write the compiled program to
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Write a program as a standalone program in another language
+    Transpile {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        order: OrderArgs,
+        /// Language to write the program in
+        #[arg(long, value_enum, default_value_t = Target::Python)]
+        target: Target,
+    },
+    /// Rewrite every commit message into the canonical form of its op
+    Fmt {
+        /// Repository to format
+        #[arg(default_value = ".")]
+        repo: PathBuf,
+        /// Tag the program starts at
+        #[arg(long = "start", value_name = "TAG", default_value = "_start")]
+        start_tag: String,
+        /// Tag the program ends at
+        #[arg(long = "end", value_name = "TAG", default_value = "_end")]
+        end_tag: String,
+        /// List the commits that would change and fail if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Fold constant ops and remove ones that do nothing
+    Optimize {
+        /// Repository to optimize
+        #[arg(default_value = ".")]
+        repo: PathBuf,
+        /// Tag the program starts at
+        #[arg(long = "start", value_name = "TAG", default_value = "_start")]
+        start_tag: String,
+        /// Tag the program ends at
+        #[arg(long = "end", value_name = "TAG", default_value = "_end")]
+        end_tag: String,
+        /// List the commits that would change without replacing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write a repository's program as JSON to stdout
+    ExportDag {
+        /// Repository to export
+        #[arg(default_value = ".")]
+        repo: PathBuf,
+    },
+    /// Assemble a program from text into a repository, creating it if needed
+    Build {
+        /// Text file to assemble
+        file: PathBuf,
+        /// Repository to write to
+        repo: PathBuf,
+    },
+    /// Write a JSON or DOT program file into a repository, creating it if needed
+    ImportDag {
+        /// Repository to write to
+        repo: PathBuf,
+        /// Program file to import
+        file: PathBuf,
+    },
+}
 
-fn main() -> Result<()> {
-    let path = std::env::args().nth(1).context("path required")?;
+// Where a program is and which part of it runs.
+#[derive(Args)]
+struct SourceArgs {
+    /// Repository, bundle or program file
+    #[arg(default_value = ".")]
+    path: String,
+    /// Tag the program starts at
+    #[arg(long = "start", value_name = "TAG", default_value = "_start")]
+    start_tag: String,
+    /// Tag the program ends at
+    #[arg(long = "end", value_name = "TAG", default_value = "_end")]
+    end_tag: String,
+    /// Link a repository, bundle or program file as a module
+    #[arg(long = "import", value_name = "NAME=LOCATION", value_parser = parse_import)]
+    imports: Vec<(String, String)>,
+    /// Read repositories with gitoxide
+    #[arg(long)]
+    gitoxide: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    Python,
+}
+
+// Which child is taken when nothing chooses, at random by default.
+#[derive(Args)]
+struct OrderArgs {
+    /// Take children in turn on successive visits
+    #[arg(long, group = "order")]
+    deterministic: bool,
+    /// Prefer the oldest child
+    #[arg(long, group = "order")]
+    chronological: bool,
+    /// Prefer the child that lists the current commit earliest among its parents
+    #[arg(long, group = "order")]
+    first_parent: bool,
+}
 
-    let repo = Repository::open(path)?;
+impl OrderArgs {
+    fn order(&self) -> Order {
+        if self.deterministic {
+            Order::Deterministic
+        } else if self.chronological {
+            Order::Chronological
+        } else if self.first_parent {
+            Order::ParentIndex
+        } else {
+            Order::Random
+        }
+    }
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    /// Report the commits that were never executed
+    #[arg(long)]
+    coverage: bool,
+    /// Report the steps taken, the ops and branches executed, the most
+    /// variables held at once and the time taken when the program finishes
+    #[arg(long)]
+    stats: bool,
+    /// Explain each branch: the commit its tag resolves to, how near the tag
+    /// each child is, and why the chosen one won
+    #[arg(long)]
+    explain_branch: bool,
+    /// Log every write to the variable at PATH, with the commit that wrote it
+    /// and the value before and after
+    #[arg(long, value_name = "PATH")]
+    watch: Vec<String>,
+    /// Warn when a commit executes more than N times, 10000 if not given,
+    /// without any input, output or variable changing in between
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+    detect_loops: Option<Option<usize>>,
+    /// Fail instead of warning when a loop looks stuck, detecting them even
+    /// without --detect-loops
+    #[arg(long)]
+    strict_loops: bool,
+    #[command(flatten)]
+    order: OrderArgs,
+    /// Only execute signed commits, checked against KEYRING if given
+    #[arg(long, value_name = "KEYRING", num_args = 0..=1, require_equals = true)]
+    verify_signatures: Option<Option<PathBuf>>,
+    /// Periodically save the program's state to FILE
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+    /// Continue from a checkpoint
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+    /// Read the program's input from FILE instead of stdin, failing if the
+    /// program reads past its end
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+    /// Write what the program prints to FILE instead of stdout
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Write each print as a JSON line {"out": TEXT}, and read each line of
+    /// input as {"in": TEXT}
+    #[arg(long)]
+    json_io: bool,
+    /// Have cls, goto_xy and color print nothing instead of ANSI escape
+    /// sequences
+    #[arg(long)]
+    no_ansi: bool,
+    /// Load the global table from a JSON file before starting
+    #[arg(long, value_name = "FILE")]
+    init_state: Option<PathBuf>,
+    /// Write the global table as JSON to file descriptor N or a file on exit
+    #[arg(long, value_name = "fd:N|FILE")]
+    dump_state: Option<String>,
+    /// Limit how deeply tables may be nested
+    #[arg(long, value_name = "DEPTH")]
+    max_depth: Option<usize>,
+    /// Record the seed, starting state, input, executed commits and output to
+    /// FILE for `replay`
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Write how long each commit took to execute to FILE as a Chrome trace,
+    /// for Perfetto or chrome://tracing
+    #[arg(long, value_name = "FILE")]
+    trace_json: Option<PathBuf>,
+    /// Commit the program's output to REF
+    #[arg(long = "output-ref", value_name = "REF")]
+    output_ref: Option<String>,
+}
 
-    let start = repo
-        .find_reference("refs/tags/_start")
-        .and_then(|r| r.peel_to_commit())
-        .context("missing _start tag")?;
-    let end = repo
-        .find_reference("refs/tags/_end")
-        .and_then(|r| r.peel_to_commit())
-        .context("missing _end tag")?;
+// How many times a commit may execute with nothing changing before a loop is
+// taken to be stuck, unless `--detect-loops` says otherwise.
+const DEFAULT_LOOP_LIMIT: usize = 10000;
 
-    let mut instance = interp::Instance::new();
-    instance.run(&repo, start, end)
+fn parse_import(import: &str) -> Result<(String, String), String> {
+    let (name, location) = import.split_once('=').ok_or("expected NAME=LOCATION")?;
+    Ok((name.to_owned(), location.to_owned()))
 }
 
-fn replace<'a>(repo: &'a Repository, commit: &mut git::Commit<'a>) -> Option<git::Oid> {
-    let id = commit.id();
-    let mut replaced = false;
-    while let Ok(replace) = repo
-        .find_reference(&format!("refs/replace/{}", commit.id()))
-        .and_then(|r| r.peel_to_commit())
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+    init_logging(cli.verbose);
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run_program(args, None),
+        Command::Replay { file, run } => run_program(run, Some(Trace::read(&file)?)),
+        #[cfg(feature = "tui")]
+        Command::Tui {
+            source: args,
+            order,
+            speed,
+        } => {
+            if let Some(speed) = speed {
+                anyhow::ensure!(speed > 0.0, "--speed: must be more than 0");
+            }
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            tui::run(&source, start, end, &args.end_tag, order.order(), speed)
+        }
+        #[cfg(feature = "serve")]
+        Command::Serve {
+            source: args,
+            order,
+            addr,
+            speed,
+        } => {
+            anyhow::ensure!(speed > 0.0, "--speed: must be more than 0");
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            serve::serve(
+                &source,
+                start,
+                end,
+                &args.end_tag,
+                order.order(),
+                &addr,
+                speed,
+            )
+        }
+        Command::Repl { commit_to } => repl(commit_to.as_deref()),
+        Command::Examples { name, write } => match name {
+            Some(name) => examples::run(&name, write.as_deref()),
+            None => {
+                examples::list();
+                Ok(())
+            }
+        },
+        Command::Test {
+            source: args,
+            order,
+            tests,
+        } => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            let dir = match tests {
+                Some(dir) => dir,
+                None => tests_dir(Path::new(&args.path))?,
+            };
+            let fixtures = fixtures::discover(&dir)?;
+            let mut failed = 0;
+            for fixture in &fixtures {
+                match fixture.run(&source, start, end, order.order()) {
+                    Ok(()) => println!("ok {}", fixture.name),
+                    Err(e) => {
+                        println!("FAILED {}: {:#}", fixture.name, e);
+                        failed += 1;
+                    }
+                }
+            }
+            println!("{} passed, {} failed", fixtures.len() - failed, failed);
+            if failed > 0 {
+                anyhow::bail!("{} of {} tests failed", failed, fixtures.len());
+            }
+            Ok(())
+        }
+        Command::Bench {
+            source: args,
+            order,
+            runs,
+            input,
+        } => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            let input = match input {
+                Some(path) => std::fs::read(&path)
+                    .with_context(|| format!("--input: failed to read {}", path.display()))?,
+                None => Vec::new(),
+            };
+            bench(
+                &source,
+                start,
+                end,
+                &args.end_tag,
+                order.order(),
+                runs,
+                &input,
+            )
+        }
+        Command::Check(args) => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            let report = check::check(&source, start, end)?;
+            eprint!("{}", report);
+            anyhow::ensure!(
+                report.errors.is_empty(),
+                "{} of {} commits failed to check",
+                report.errors.len(),
+                report.checked
+            );
+            eprintln!("checked {} commits", report.checked);
+            Ok(())
+        }
+        Command::Stats(args) => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            print!("{}", Stats::collect(&source, start, end)?);
+            Ok(())
+        }
+        Command::Graph(args) => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            print!("{}", dot::to_dot(&source, start, end)?);
+            Ok(())
+        }
+        Command::Disasm(args) => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            print!("{}", asm::disassemble(&source, start, end)?);
+            Ok(())
+        }
+        Command::Lsp => undag::lsp::serve(std::io::stdin().lock(), std::io::stdout().lock()),
+        Command::Compile {
+            source: args,
+            output,
+        } => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            std::fs::write(&output, bytecode::compile(&source, start, end)?)
+                .with_context(|| format!("failed to write {}", output.display()))
+        }
+        Command::Transpile {
+            source: args,
+            order,
+            target,
+        } => {
+            let source = open_source(&args)?;
+            let (start, end) = undag::tagged_endpoints(&source, &args.start_tag, &args.end_tag)?;
+            match target {
+                Target::Python => print!(
+                    "{}",
+                    transpile::to_python(&source, start, end, order.order())?
+                ),
+            }
+            Ok(())
+        }
+        Command::Fmt {
+            repo,
+            start_tag,
+            end_tag,
+            check,
+        } => {
+            let repo = undag::open_repository(repo)?;
+            let (start, end) = undag::tagged_endpoints(&repo, &start_tag, &end_tag)?;
+            let changed = fmt::format(&repo, start, end, check)?;
+            for (id, message) in &changed {
+                eprintln!("{}: {}", id, message);
+            }
+            if check && !changed.is_empty() {
+                anyhow::bail!("{} commits are not formatted", changed.len());
+            }
+            Ok(())
+        }
+        Command::Optimize {
+            repo,
+            start_tag,
+            end_tag,
+            dry_run,
+        } => {
+            let repo = undag::open_repository(repo)?;
+            let (start, end) = undag::tagged_endpoints(&repo, &start_tag, &end_tag)?;
+            for (id, change) in optimize::optimize(&repo, start, end, dry_run)? {
+                eprintln!("{}: {}", id, change);
+            }
+            Ok(())
+        }
+        Command::ExportDag { repo } => {
+            let repo = undag::open_repository(repo)?;
+            let tags = ProgramSource::tags(&repo)?;
+            println!("{}", Dag::export(&repo, &tags)?.to_json());
+            Ok(())
+        }
+        Command::Build { file, repo } => {
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let dag = asm::assemble(&text)
+                .with_context(|| format!("invalid program: {}", file.display()))?;
+            write_program(&dag, &repo)
+        }
+        Command::ImportDag { repo, file } => write_program(&undag::open_file(file)?, &repo),
+    }
+}
+
+fn write_program(dag: &Dag, repo: &Path) -> Result<()> {
+    let repo = match undag::git::Repository::open(repo) {
+        Ok(repo) => repo,
+        Err(_) => undag::git::Repository::init(repo)?,
+    };
+    dag.write_to(&repo)
+}
+
+// The `tests` directory at the top of a repository's worktree, or beside a
+// program file.
+fn tests_dir(path: &Path) -> Result<PathBuf> {
+    if path.is_file() {
+        return Ok(path.with_file_name("tests"));
+    }
+    let repo = undag::open_repository(path)?;
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    Ok(root.join("tests"))
+}
+
+fn open_source(args: &SourceArgs) -> Result<Linked> {
+    let source = match args.gitoxide {
+        true => open_gitoxide(&args.path)?,
+        false => undag::open(&args.path)?,
+    };
+    let mut source = Linked::new(source);
+    for (name, location) in &args.imports {
+        let module = undag::open_location(location)
+            .with_context(|| format!("--import: failed to open {}", location))?;
+        source.add_module(name, module);
+    }
+    Ok(source)
+}
+
+// Logs with the time since starting, and how long each span took as it
+// closes, so that whatever is slow stands out.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => return,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_timer(tracing_subscriber::fmt::time::Uptime::default())
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .init();
+}
+
+// Runs a program, or replays `replay` with the seed, order and input it
+// recorded.
+fn run_program(args: RunArgs, replay: Option<Trace>) -> Result<()> {
+    let path = &args.source.path;
+    // Output is committed to the repository the program is in.
+    let output_repo = match &args.output_ref {
+        Some(_) => Some(
+            undag::open_repository(path).context("--output-ref: program must be a repository")?,
+        ),
+        None => None,
+    };
+    let source = &open_source(&args.source)?;
+    let (start, end) =
+        undag::tagged_endpoints(source, &args.source.start_tag, &args.source.end_tag)?;
+
+    let mut instance = Instance::new();
+    instance.set_end_tag(&args.source.end_tag);
+    if args.coverage {
+        instance.enable_coverage();
+    }
+    let order = replay
+        .as_ref()
+        .map_or_else(|| args.order.order(), |trace| trace.order);
+    instance.set_order(order);
+    let seed = replay
+        .as_ref()
+        .map_or_else(rand::random, |trace| trace.seed);
+    instance.set_seed(seed);
+    // Executed commits are kept to record a trace or to check a replay.
+    let recorder = (args.trace.is_some() || replay.is_some()).then(Recorder::default);
+    let timeline = args.trace_json.as_ref().map(|_| Timeline::default());
+    let run_stats = args.stats.then(RunStats::default);
+    let explainer = args
+        .explain_branch
+        .then(|| BranchExplainer::new(|explanation| eprint!("{}", explanation)));
+    let log = (!args.watch.is_empty() || args.detect_loops.is_some()).then_some(Log);
+    if recorder.is_some()
+        || timeline.is_some()
+        || run_stats.is_some()
+        || explainer.is_some()
+        || log.is_some()
+    {
+        instance.set_hook((
+            ((recorder.clone(), timeline.clone()), run_stats.clone()),
+            (explainer, log),
+        ));
+    }
+    if let Some(depth) = args.max_depth {
+        instance.set_max_depth(depth);
+    }
+    for path in &args.watch {
+        instance.watch(path);
+    }
+    if args.detect_loops.is_some() || args.strict_loops {
+        let limit = args.detect_loops.flatten().unwrap_or(DEFAULT_LOOP_LIMIT);
+        instance.set_loop_limit(limit, args.strict_loops);
+    }
+    // Prompts are only written for someone typing the input.
+    instance.set_prompts(match &replay {
+        Some(trace) => trace.prompts,
+        None => args.input.is_none() && std::io::stdin().is_terminal(),
+    });
+    instance.set_ansi(match &replay {
+        Some(trace) => trace.ansi,
+        None => !args.no_ansi,
+    });
+    // Keys are read straight from stdin, so not when input comes from
+    // elsewhere or is JSON.
+    #[cfg(unix)]
+    let terminal =
+        (replay.is_none() && args.input.is_none() && !args.json_io).then(StdinTerminal::default);
+    let keys = KeyRecorder::default();
+    match &replay {
+        Some(trace) => instance.set_terminal(ReplayedKeys::new(&trace.keys)),
+        #[cfg(unix)]
+        None => match (&terminal, args.trace.is_some()) {
+            (Some(terminal), true) => instance.set_terminal(keys.wrap(terminal.clone())),
+            (Some(terminal), false) => instance.set_terminal(terminal.clone()),
+            (None, _) => {}
+        },
+        #[cfg(not(unix))]
+        None => {}
+    }
+    let clock = Rc::<RefCell<Vec<i64>>>::default();
+    match &replay {
+        Some(trace) => {
+            let mut readings = trace.clock.clone().into_iter();
+            instance.set_clock(move || readings.next().unwrap_or_default());
+        }
+        None if args.trace.is_some() => {
+            let (readings, started) = (clock.clone(), std::time::Instant::now());
+            instance.set_clock(move || {
+                let now = started.elapsed().as_millis() as i64;
+                readings.borrow_mut().push(now);
+                now
+            });
+        }
+        None => {}
+    }
+    if let Some(keyring) = args.verify_signatures {
+        instance.set_verify_signatures(keyring);
+    }
+    if let Some(checkpoint) = args.checkpoint {
+        instance.set_checkpoint_path(checkpoint);
+    }
+    if let Some(trace) = &replay {
+        anyhow::ensure!(
+            args.init_state.is_none() && args.resume.is_none(),
+            "replay: the trace holds the state the program starts with"
+        );
+        anyhow::ensure!(
+            args.input.is_none(),
+            "replay: the trace holds the program's input"
+        );
+        instance.set_root_table(trace.state.clone());
+    }
+    if let Some(init_state) = &args.init_state {
+        instance.set_root_table(state::load(init_state)?);
+    }
+    let initial_state = args.trace.as_ref().map(|_| instance.root_table().clone());
+    if let Some(resume) = &args.resume {
+        instance.resume(state::Checkpoint::read(resume)?)?;
+    }
+    // Like stdout, the file is flushed at the end of each line, so nothing is
+    // lost if the run is interrupted.
+    let inner: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::io::LineWriter::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("--output: failed to create {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdout()),
+    };
+    let inner = match args.json_io {
+        true => Box::new(JsonLines::new(inner)),
+        false => inner,
+    };
+    let mut output = Tee {
+        inner,
+        captured: (args.output_ref.is_some() || recorder.is_some()).then(Vec::new),
+    };
+    let interrupt = Arc::new(Interrupt::default());
     {
-        *commit = replace;
-        replaced = true;
+        let interrupt = interrupt.clone();
+        ctrlc::set_handler(move || {
+            // A program waiting for input would never reach the next step.
+            if let Some(summary) = &*interrupt.waiting.lock().unwrap() {
+                restore_terminal();
+                eprint!("{}", summary);
+                std::process::exit(130);
+            }
+            interrupt.requested.store(true, Ordering::SeqCst);
+        })?;
+    }
+    let mut replayed_input = replay
+        .as_ref()
+        .map(|trace| trace.input.iter().cloned().collect::<VecDeque<_>>());
+    let mut input = Vec::new();
+    let mut reader: Box<dyn BufRead> = match &args.input {
+        Some(path) => Box::new(std::io::BufReader::new(
+            std::fs::File::open(path)
+                .with_context(|| format!("--input: failed to open {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let mut read_line = || -> Result<Vec<u8>> {
+        let line = match &mut replayed_input {
+            Some(lines) => lines
+                .pop_front()
+                .context("replay: trace has no more input")?
+                .into_bytes(),
+            None => {
+                let mut line = Vec::new();
+                // Lines are read from the same input as keys when there are
+                // any, so that neither skips what the other read ahead.
+                #[cfg(unix)]
+                let read = match &terminal {
+                    Some(terminal) => terminal.read_line(&mut line)?,
+                    None => reader.read_until(b'\n', &mut line)?,
+                };
+                #[cfg(not(unix))]
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    if let Some(path) = &args.input {
+                        anyhow::bail!("--input: no more input in {}", path.display());
+                    }
+                } else if args.json_io {
+                    line = serde_json::from_slice::<serde_json::Value>(&line)
+                        .ok()
+                        .and_then(|json| Some(json["in"].as_str()?.as_bytes().to_vec()))
+                        .with_context(|| {
+                            format!(
+                                "--json-io: expected {{\"in\": TEXT}}, found {}",
+                                String::from_utf8_lossy(&line).trim_end()
+                            )
+                        })?;
+                }
+                line
+            }
+        };
+        input.push(String::from_utf8_lossy(&line).into_owned());
+        Ok(line)
+    };
+    let result = instance.start(source, start, end).and_then(|()| {
+        execute(
+            &mut instance,
+            source,
+            &mut output,
+            &mut read_line,
+            &interrupt,
+            run_stats.as_ref(),
+        )
+    });
+    let flushed = output.flush();
+    if let Some(coverage) = instance.coverage() {
+        eprint!("{}", coverage.report(source));
+    }
+    if let Some(run_stats) = &run_stats {
+        eprint!("{}", run_stats);
+    }
+    if let Some(diagnostic) = instance.diagnostic() {
+        eprint!("{}", diagnostic);
+    }
+    let dumped = match args.dump_state {
+        Some(target) => {
+            dump_target(&target).and_then(|file| state::dump(file, instance.root_table()))
+        }
+        None => Ok(()),
+    };
+    let captured = output.captured.unwrap_or_default();
+    let committed = match (output_repo, args.output_ref) {
+        (Some(repo), Some(name)) => commit_output(&repo, &name, &captured),
+        _ => Ok(()),
+    };
+    let commits = recorder.map(|recorder| recorder.commits());
+    let traced = match (args.trace, initial_state, &commits) {
+        (Some(path), Some(state), Some(commits)) => Trace {
+            seed,
+            order,
+            state,
+            input,
+            prompts: instance.prompts(),
+            ansi: instance.ansi(),
+            keys: keys.keys(),
+            clock: clock.take(),
+            commits: commits.clone(),
+            output: Some(String::from_utf8_lossy(&captured).into_owned()),
+        }
+        .write(&path),
+        _ => Ok(()),
+    };
+    let timed = match (&args.trace_json, &timeline) {
+        (Some(path), Some(timeline)) => timeline.write(path),
+        _ => Ok(()),
+    };
+    if let (Some(trace), Some(commits)) = (&replay, &commits) {
+        trace.compare(commits)?;
+        trace.compare_output(&captured)?;
+    }
+    let interrupted = result?;
+    flushed?;
+    committed?;
+    dumped?;
+    traced?;
+    timed?;
+    if interrupted {
+        std::process::exit(130);
+    }
+    Ok(())
+}
+
+// Runs the program `runs` times, each with the same seed and input, and
+// reports the steps taken and how long the runs took.
+fn bench(
+    source: &dyn ProgramSource,
+    start: undag::Oid,
+    end: undag::Oid,
+    end_tag: &str,
+    order: Order,
+    runs: u32,
+    input: &[u8],
+) -> Result<()> {
+    anyhow::ensure!(runs > 0, "--runs: at least one run is needed");
+    let mut times = Vec::new();
+    let mut counts = Vec::new();
+    for _ in 0..runs {
+        let mut instance = Instance::new();
+        instance.set_end_tag(end_tag);
+        instance.set_order(order);
+        instance.set_seed(0);
+        let mut input = input;
+        let mut steps = 0u64;
+        let started = std::time::Instant::now();
+        instance.start(source, start, end)?;
+        loop {
+            match instance.step(source, &mut std::io::sink()) {
+                StepResult::Continued => steps += 1,
+                StepResult::Halted => break steps += 1,
+                StepResult::NeedsInput => {
+                    let mut line = Vec::new();
+                    input.read_until(b'\n', &mut line)?;
+                    instance.push_input(line);
+                }
+                StepResult::Error(e) => return Err(e),
+            }
+        }
+        times.push(started.elapsed());
+        counts.push(steps);
+    }
+    let total = times.iter().sum::<std::time::Duration>();
+    let mean = total / runs;
+    let steps = counts.iter().sum::<u64>();
+    println!("runs:  {}", runs);
+    // Runs can still take different paths, as when the program reads the
+    // clock, and so different numbers of steps.
+    let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+    match min == max {
+        true => println!("steps: {} per run", min),
+        false => println!(
+            "steps: {:.1} mean, {} min, {} max",
+            steps as f64 / runs as f64,
+            min,
+            max
+        ),
+    }
+    println!(
+        "time:  {:?} mean, {:?} min, {:?} max",
+        mean,
+        times.iter().min().unwrap(),
+        times.iter().max().unwrap()
+    );
+    println!("speed: {:.0} ops/s", steps as f64 / total.as_secs_f64());
+    Ok(())
+}
+
+const REPL_HELP: &str = "\
+Type an op to run it, or one of:
+    :vars    list the variables
+    :help    show this message
+    :quit    exit
+";
+
+fn repl(commit_to: Option<&Path>) -> Result<()> {
+    let repo = match commit_to {
+        Some(path) => Some(match undag::git::Repository::open(path) {
+            Ok(repo) => repo,
+            Err(_) => undag::git::Repository::init(path)?,
+        }),
+        None => None,
+    };
+    let mut instance = Instance::new();
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout();
+    let mut read_line = |prompt: &str| -> Result<Option<String>> {
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        let mut line = String::new();
+        Ok((stdin.read_line(&mut line)? > 0).then_some(line))
+    };
+    eprint!("{}", REPL_HELP);
+    while let Some(line) = read_line("> ")? {
+        let line = line.trim();
+        match line {
+            ":vars" => {
+                eprint!("{}", variables(&instance));
+                continue;
+            }
+            ":help" => {
+                eprint!("{}", REPL_HELP);
+                continue;
+            }
+            ":quit" => break,
+            _ if line.starts_with(':') => {
+                eprintln!("unknown command: {}", line);
+                continue;
+            }
+            _ => {}
+        }
+        let op = match line.parse::<Op>() {
+            Ok(Op::Nop) => continue,
+            Ok(op) => op,
+            Err(e) => {
+                eprintln!("error: {:#}", e);
+                continue;
+            }
+        };
+        if let Op::Inpln(..) = op {
+            let prompt = match instance.prompt(&op) {
+                Ok(prompt) => prompt.unwrap_or_default(),
+                Err(e) => {
+                    eprintln!("error: {:#}", e);
+                    continue;
+                }
+            };
+            match read_line(&prompt)? {
+                Some(input) => instance.push_input(input),
+                None => break,
+            }
+        }
+        if let Err(e) = instance.exec_op(&op, &mut std::io::stdout()) {
+            eprintln!("error: {:#}", e);
+            continue;
+        }
+        if let Some(repo) = &repo {
+            append_op(repo, line)?;
+        }
+    }
+    Ok(())
+}
+
+// Commits `op` after the commit tagged `_end`, moving `_end` to it.
+fn append_op(repo: &undag::git::Repository, op: &str) -> Result<()> {
+    let end = ProgramSource::tag(repo, "_end");
+    let id = repo.create_commit(&format!("{}\n", op), &end.into_iter().collect::<Vec<_>>())?;
+    if end.is_none() {
+        repo.create_tag("_start", id)?;
+    }
+    repo.create_tag("_end", id)
+}
+
+#[cfg(feature = "gitoxide")]
+fn open_gitoxide(path: &str) -> Result<Box<dyn ProgramSource>> {
+    Ok(Box::new(undag::open_gitoxide(path)?))
+}
+
+#[cfg(not(feature = "gitoxide"))]
+fn open_gitoxide(_: &str) -> Result<Box<dyn ProgramSource>> {
+    anyhow::bail!("--gitoxide: undag was built without the gitoxide feature")
+}
+
+// Set by Ctrl-C, upon which the program stops before its next step.
+#[derive(Default)]
+struct Interrupt {
+    requested: AtomicBool,
+    // What to print if interrupted while waiting for input.
+    waiting: Mutex<Option<String>>,
+}
+
+// Prints what the run reports as it goes to stderr: writes to watched
+// variables and loops that look stuck.
+struct Log;
+
+impl undag::Hook for Log {
+    fn on_watch(&mut self, path: &str, commit: Option<Oid>, old: Option<&Val>, new: Option<&Val>) {
+        let at = commit.map_or_else(String::new, |commit| format!(" at {}", commit));
+        eprintln!(
+            "watch: {} set{} from {} to {}",
+            path,
+            at,
+            describe(old),
+            describe(new)
+        );
+    }
+
+    fn on_stuck_loop(&mut self, commit: Oid, times: usize) {
+        eprintln!(
+            "warning: {}; the program looks stuck in a loop",
+            undag::UndagError::StuckLoop { oid: commit, times }
+        );
+    }
+}
+
+// A value as logged for a watch, with strings quoted.
+fn describe(val: Option<&Val>) -> String {
+    match val {
+        Some(Val::Str(s)) => format!("{:?}", s),
+        Some(val) => val.to_string(),
+        None => "undefined".to_owned(),
+    }
+}
+
+// Runs the program until it halts, returning whether it was interrupted.
+fn execute(
+    instance: &mut Instance,
+    source: &dyn ProgramSource,
+    output: &mut impl Write,
+    read_line: &mut impl FnMut() -> Result<Vec<u8>>,
+    interrupt: &Interrupt,
+    run_stats: Option<&RunStats>,
+) -> Result<bool> {
+    loop {
+        if interrupt.requested.load(Ordering::SeqCst) {
+            if let Some(commit) = instance.current_commit() {
+                instance.write_checkpoint(commit)?;
+            }
+            eprint!("{}", summary(instance));
+            return Ok(true);
+        }
+        let result = instance.step(source, output);
+        if let Some(run_stats) = run_stats {
+            run_stats.sample(instance.root_table());
+        }
+        match result {
+            StepResult::Continued => {}
+            StepResult::Halted => return Ok(false),
+            StepResult::NeedsInput => {
+                *interrupt.waiting.lock().unwrap() = Some(summary(instance));
+                let line = read_line()?;
+                *interrupt.waiting.lock().unwrap() = None;
+                instance.push_input(line);
+            }
+            StepResult::Error(e) => return Err(e),
+        }
+    }
+}
+
+// Where the program was interrupted and the variables it had set.
+fn summary(instance: &Instance) -> String {
+    let mut summary = String::new();
+    if let Some(commit) = instance.current_commit() {
+        summary += &format!("interrupted at {}\n", commit);
+    }
+    summary + &variables(instance)
+}
+
+// The variables of the global table, one per line.
+fn variables(instance: &Instance) -> String {
+    let mut vars = instance.root_table().iter().collect::<Vec<_>>();
+    vars.sort_by_key(|(name, _)| *name);
+    let mut lines = String::new();
+    for (name, val) in vars {
+        lines += &match val {
+            Val::Table(table) => format!("    {}: table of {} entries\n", name, table.len()),
+            Val::Str(s) => format!("    {} = {:?}\n", name, s),
+            Val::Ref(path) => format!("    {}: reference to {}\n", name, path),
+            Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_) => {
+                format!("    {} = {}\n", name, val)
+            }
+        };
+    }
+    lines
+}
+
+// Writes through to `inner`, keeping a copy of everything written if
+// `captured` is set.
+struct Tee<W> {
+    inner: W,
+    captured: Option<Vec<u8>>,
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if let Some(captured) = &mut self.captured {
+            captured.extend_from_slice(&buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Writes what is written to `inner` as lines of JSON, `{"out": TEXT}`, one for
+// each write ending in a newline and one for what was written before each
+// flush. Programs flush after each print, so every print becomes one line. A
+// character cut off by a flush, as when `outb` writes one byte of it at a time,
+// is held back until the rest of it is written.
+struct JsonLines<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> JsonLines<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self, len: usize) -> std::io::Result<()> {
+        let text = self.pending.drain(..len).collect::<Vec<_>>();
+        let out = serde_json::json!({ "out": String::from_utf8_lossy(&text) });
+        writeln!(self.inner, "{}", out)
+    }
+}
+
+impl<W: Write> Write for JsonLines<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if buf.ends_with(b"\n") {
+            self.write_line(self.pending.len())?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let len = complete_len(&self.pending);
+        if len > 0 {
+            self.write_line(len)?;
+        }
+        self.inner.flush()
+    }
+}
+
+// Whatever is still held back when the run ends is written as it is.
+impl<W: Write> Drop for JsonLines<W> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.write_line(self.pending.len());
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+// The length of `bytes` without a character that is cut off at the end.
+// Invalid bytes before it count as complete.
+fn complete_len(bytes: &[u8]) -> usize {
+    let mut start = 0;
+    loop {
+        match std::str::from_utf8(&bytes[start..]) {
+            Ok(_) => return bytes.len(),
+            Err(e) => match e.error_len() {
+                Some(len) => start += e.valid_up_to() + len,
+                None => return start + e.valid_up_to(),
+            },
+        }
+    }
+}
+
+// The terminal on stdin. `rawmode on` puts it in cbreak mode, where keys are
+// read as soon as they are typed and not echoed, but Ctrl-C still interrupts
+// and output is still translated as usual. Lines for `inpln` are read through
+// it too, since stdin's own buffer would keep what it read ahead from keys.
+#[cfg(unix)]
+#[derive(Clone, Default)]
+struct StdinTerminal {
+    // Bytes read but not yet taken as keys or lines, shared by every copy.
+    pending: Rc<RefCell<Vec<u8>>>,
+}
+
+#[cfg(unix)]
+impl StdinTerminal {
+    // Reads up to and including the next newline, waiting for one, and
+    // returns how many bytes were read, which is 0 at the end of input.
+    fn read_line(&self, line: &mut Vec<u8>) -> std::io::Result<usize> {
+        let mut pending = self.pending.borrow_mut();
+        loop {
+            if let Some(newline) = pending.iter().position(|&byte| byte == b'\n') {
+                line.extend(pending.drain(..=newline));
+                return Ok(newline + 1);
+            }
+            let mut buf = [0; 4096];
+            let len = read_stdin(&mut buf)?;
+            if len == 0 {
+                let len = pending.len();
+                line.append(&mut pending);
+                return Ok(len);
+            }
+            pending.extend_from_slice(&buf[..len]);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_stdin(buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let len = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if len >= 0 {
+            return Ok(len as usize);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+// Where `--dump-state` writes: a copy of the descriptor N given as `fd:N`,
+// which the caller keeps open, or else a file created at the path.
+fn dump_target(target: &str) -> Result<std::fs::File> {
+    let Some(fd) = target.strip_prefix("fd:") else {
+        return std::fs::File::create(target)
+            .with_context(|| format!("--dump-state: failed to create {}", target));
+    };
+    let fd = fd
+        .parse()
+        .with_context(|| format!("--dump-state: invalid file descriptor: {}", fd))?;
+    dup_fd(fd).with_context(|| format!("--dump-state: file descriptor {} is not open", fd))
+}
+
+#[cfg(unix)]
+fn dup_fd(fd: i32) -> Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+    // Only a descriptor that is open is copied, and the copy is ours alone,
+    // so closing it leaves the caller's open.
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup == -1 {
+        return Err(std::io::Error::last_os_error().into());
     }
-    replaced.then(|| id)
+    // SAFETY: `dup` was just created by `fcntl` and nothing else owns it.
+    Ok(unsafe { std::fs::File::from_raw_fd(dup) })
+}
+
+#[cfg(not(unix))]
+fn dup_fd(_: i32) -> Result<std::fs::File> {
+    anyhow::bail!("file descriptors are only supported on Unix")
+}
+
+// The settings the terminal had before `rawmode on`, which are put back when
+// the run ends, even if it ends with Ctrl-C.
+#[cfg(unix)]
+static SAVED_TERMINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+#[cfg(unix)]
+fn restore_terminal() {
+    if let Some(saved) = SAVED_TERMINAL.lock().unwrap().take() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &saved) };
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_terminal() {}
+
+#[cfg(unix)]
+impl undag::interp::Terminal for StdinTerminal {
+    // Input that is not a terminal has no modes to set.
+    fn set_raw(&mut self, raw: bool) -> Result<()> {
+        let mut saved = SAVED_TERMINAL.lock().unwrap();
+        if !raw {
+            drop(saved);
+            restore_terminal();
+            return Ok(());
+        }
+        if saved.is_some() || !std::io::stdin().is_terminal() {
+            return Ok(());
+        }
+        let mut settings = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut settings) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("rawmode");
+        }
+        let original = settings;
+        settings.c_lflag &= !(libc::ICANON | libc::ECHO);
+        settings.c_cc[libc::VMIN] = 1;
+        settings.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &settings) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("rawmode");
+        }
+        *saved = Some(original);
+        Ok(())
+    }
+
+    // Arrow keys, which send escape sequences, are named by their direction.
+    fn key(&mut self) -> Result<Option<String>> {
+        let mut poll = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let mut pending = self.pending.borrow_mut();
+        if unsafe { libc::poll(&mut poll, 1, 0) } > 0 {
+            let mut buf = [0; 64];
+            let len = read_stdin(&mut buf)?;
+            pending.extend_from_slice(&buf[..len]);
+        }
+        if pending.is_empty() {
+            return Ok(None);
+        }
+        let (key, len) = match &pending[..] {
+            [0x1b, b'[', b'A', ..] => ("up".to_owned(), 3),
+            [0x1b, b'[', b'B', ..] => ("down".to_owned(), 3),
+            [0x1b, b'[', b'C', ..] => ("right".to_owned(), 3),
+            [0x1b, b'[', b'D', ..] => ("left".to_owned(), 3),
+            pending => match bstr::decode_utf8(pending) {
+                (Some(c), len) => (c.to_string(), len),
+                (None, len) => (char::REPLACEMENT_CHARACTER.to_string(), len.max(1)),
+            },
+        };
+        pending.drain(..len);
+        Ok(Some(key))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for StdinTerminal {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+// Commits the output of a run as the file `output` on top of the ref `name`,
+// creating the ref if needed.
+fn commit_output(repo: &undag::git::Repository, name: &str, output: &[u8]) -> Result<()> {
+    let context = || format!("--output-ref: failed to commit output to {}", name);
+    let mut tree = repo.treebuilder(None).with_context(context)?;
+    let blob = repo.blob(output).with_context(context)?;
+    tree.insert("output", blob, 0o100644)
+        .with_context(context)?;
+    let tree = repo.find_tree(tree.write()?).with_context(context)?;
+    let parent = repo
+        .find_reference(name)
+        .and_then(|reference| reference.peel_to_commit())
+        .ok();
+    let signature = repo
+        .signature()
+        .or_else(|_| undag::git::Signature::now("undag", "undag"))?;
+    repo.commit(
+        Some(name),
+        &signature,
+        &signature,
+        "Program output",
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )
+    .with_context(context)?;
+    Ok(())
 }
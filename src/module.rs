@@ -0,0 +1,155 @@
+use crate::source::{Oid, ProgramSource};
+use anyhow::Result;
+
+// A program together with the modules it uses, whose tags are named with the
+// module's name as a prefix, as in `lib::sort`. Branching to a module's tag
+// runs the module from that commit until its `_end`, then continues from the
+// commit that branched.
+pub struct Linked {
+    main: Box<dyn ProgramSource>,
+    modules: Vec<(String, Box<dyn ProgramSource>)>,
+}
+
+impl Linked {
+    pub fn new(main: Box<dyn ProgramSource>) -> Self {
+        Self {
+            main,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn add_module(&mut self, name: impl Into<String>, module: Box<dyn ProgramSource>) {
+        self.modules.push((name.into(), module));
+    }
+
+    // Commits of separate repositories never share ids, so each commit is read
+    // from whichever source has it.
+    fn owner(&self, id: Oid) -> &dyn ProgramSource {
+        if self.modules.is_empty() || self.main.time(id).is_ok() {
+            return &*self.main;
+        }
+        self.modules
+            .iter()
+            .map(|(_, module)| &**module)
+            .find(|module| module.time(id).is_ok())
+            .unwrap_or(&*self.main)
+    }
+}
+
+impl ProgramSource for Linked {
+    fn message(&self, id: Oid) -> Result<String> {
+        self.owner(id).message(id)
+    }
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+        self.owner(id).parents(id)
+    }
+
+    fn time(&self, id: Oid) -> Result<i64> {
+        self.owner(id).time(id)
+    }
+
+    fn author(&self, id: Oid) -> Result<(String, String)> {
+        self.owner(id).author(id)
+    }
+
+    fn tag(&self, name: &str) -> Option<Oid> {
+        if let Some((prefix, tag)) = name.split_once("::") {
+            if let Some((_, module)) = self.modules.iter().find(|(name, _)| name == prefix) {
+                return module.tag(tag);
+            }
+        }
+        self.main.tag(name)
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        let mut tags = self.main.tags()?;
+        for (name, module) in &self.modules {
+            tags.extend(
+                module
+                    .tags()?
+                    .into_iter()
+                    .map(|tag| format!("{}::{}", name, tag)),
+            );
+        }
+        Ok(tags)
+    }
+
+    fn verify_signature(&self, id: Oid, keyring: Option<&std::path::Path>) -> Result<()> {
+        self.owner(id).verify_signature(id, keyring)
+    }
+
+    // Only a program without modules is forked, as it is then only its own
+    // source.
+    fn fork(&self) -> Option<Box<dyn ProgramSource + Send>> {
+        match self.modules.is_empty() {
+            true => self.main.fork(),
+            false => None,
+        }
+    }
+
+    fn create_commit(&self, message: &str, parents: &[Oid]) -> Result<Oid> {
+        match parents.first() {
+            Some(&parent) => self.owner(parent).create_commit(message, parents),
+            None => self.main.create_commit(message, parents),
+        }
+    }
+
+    fn create_tag(&self, name: &str, id: Oid) -> Result<()> {
+        if let Some((prefix, tag)) = name.split_once("::") {
+            if let Some((_, module)) = self.modules.iter().find(|(name, _)| name == prefix) {
+                return module.create_tag(tag, id);
+            }
+        }
+        self.main.create_tag(name, id)
+    }
+
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+        let mut replacements = self.main.replacements()?;
+        for (_, module) in &self.modules {
+            replacements.extend(module.replacements()?);
+        }
+        Ok(replacements)
+    }
+
+    fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+        self.owner(id).file(id, path)
+    }
+
+    fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+        self.owner(id).descendant_of(id, ancestor)
+    }
+}
+
+// Links each of the repository's submodules as a module named after it, along
+// with their own submodules in turn. Bare repositories have no submodules
+// checked out, and submodules that are not checked out are left unlinked, so
+// that programs which never branch into them still run.
+#[cfg(feature = "git")]
+pub fn with_submodules(repo: git::Repository) -> Result<Linked> {
+    let mut modules = Vec::new();
+    let submodules = match repo.is_bare() {
+        true => Vec::new(),
+        false => repo.submodules().unwrap_or_else(|e| {
+            tracing::debug!(error = %e, "failed to read submodules");
+            Vec::new()
+        }),
+    };
+    for submodule in submodules {
+        let Some(name) = submodule.name() else {
+            tracing::debug!("skipped submodule whose name is not valid UTF-8");
+            continue;
+        };
+        match submodule.open() {
+            Ok(module) => modules.push((name.to_owned(), with_submodules(module)?)),
+            Err(e) => {
+                tracing::debug!(submodule = name, error = %e, "skipped submodule that is not checked out")
+            }
+        }
+    }
+    let mut linked = Linked::new(Box::new(crate::commit_graph::Graphed::new(repo)));
+    for (name, module) in modules {
+        linked.add_module(name, Box::new(module));
+    }
+    Ok(linked)
+}
@@ -0,0 +1,338 @@
+use crate::fmt::replace;
+use crate::interp::{arith, math, radix, repeat, Get, Op, Val};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+
+// The values variables are known to hold at some point of a program.
+type Known = HashMap<String, Val>;
+
+// The longest string a `strrep` is folded into, since the result is written
+// into a commit message.
+const MAX_FOLDED_LEN: usize = 256;
+
+// Optimizes the program between `start` and `end`:
+//
+// - Ops whose arguments are all known, from literals or from variables set
+//   earlier along a run of commits that can only be entered one way, are
+//   folded into a `set` of their result.
+// - Empty ops are removed, and so are `branch` instructions that can only go
+//   to the one child their commit has, straightening the runs they were in.
+//
+// Like `fmt`, commits are replaced with `git replace` rather than rewritten,
+// so that history is left as it was. Tagged commits are kept since they may
+// be branched to, and so are commits whose parents have other children, which
+// would otherwise be reordered among them. Returns the commits folded or
+// removed along with what became of them, without replacing anything if
+// `dry_run` is set.
+pub fn optimize(
+    repo: &git::Repository,
+    start: Oid,
+    end: Oid,
+    dry_run: bool,
+) -> Result<Vec<(Oid, String)>> {
+    let replacements = Replacements::load(repo)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(repo, &replacements, start, end)?;
+    let order = tree::walk(&children, start);
+
+    let mut parents = HashMap::<Oid, Vec<Oid>>::new();
+    for (&id, nexts) in &children {
+        for next in nexts {
+            parents.entry(next.id).or_default().push(id);
+        }
+    }
+    let mut tagged = [start, end].into_iter().collect::<HashSet<_>>();
+    for name in ProgramSource::tags(repo)? {
+        if let Some(id) = ProgramSource::tag(repo, &name) {
+            tagged.insert(replacements.resolve(id));
+        }
+    }
+    let ops = order
+        .iter()
+        .map(|&id| Ok((id, ProgramSource::message(repo, id)?.parse::<Op>().ok())))
+        .collect::<Result<HashMap<_, _>>>()?;
+    if let Some((&id, _)) = ops
+        .iter()
+        .find(|(_, op)| matches!(op, Some(Op::Mkcommit(..) | Op::Mktag(..))))
+    {
+        anyhow::bail!(
+            "{}: programs that create commits or tags cannot be optimized",
+            replacements.original(id)
+        );
+    }
+
+    let mut changed = Vec::new();
+    let mut messages = HashMap::<Oid, String>::new();
+    let mut known_after = HashMap::<Oid, Known>::new();
+    let mut removed = HashMap::<Oid, Oid>::new();
+    for &id in &order {
+        // A commit with one parent is only entered right after it, unless it
+        // is tagged and so may be entered from another program.
+        let mut known = match parents.get(&id).map(Vec::as_slice) {
+            Some([parent]) if !tagged.contains(&id) => {
+                known_after.get(parent).cloned().unwrap_or_default()
+            }
+            _ => Known::new(),
+        };
+        let original = replacements.original(id);
+        match &ops[&id] {
+            Some(op) => {
+                if let Some(folded) = fold(op, &mut known) {
+                    let folded = folded.to_string();
+                    changed.push((original, folded.clone()));
+                    messages.insert(id, folded);
+                } else if let Some(reason) = removable(repo, &replacements, &children, id, op) {
+                    let commit_parents = ProgramSource::parents(repo, id)?;
+                    let only_child = |parent: &Oid| {
+                        let parent = replacements.resolve(*parent);
+                        children.get(&parent).is_some_and(|nexts| nexts.len() == 1)
+                    };
+                    if let [parent] = commit_parents[..] {
+                        if !tagged.contains(&id) && only_child(&parent) {
+                            changed.push((original, reason.to_owned()));
+                            removed.insert(id, parent);
+                        }
+                    }
+                }
+            }
+            None => known.clear(),
+        }
+        known_after.insert(id, known);
+    }
+
+    // Children of removed commits take their parents in their place.
+    let mut relinked = HashMap::<Oid, Vec<Oid>>::new();
+    for &id in &order {
+        if removed.contains_key(&id) {
+            continue;
+        }
+        let commit_parents = match ProgramSource::parents(repo, id) {
+            Ok(parents) => parents,
+            Err(_) if id == start => continue,
+            Err(e) => return Err(e),
+        };
+        let mut relinks = false;
+        let new_parents = commit_parents
+            .into_iter()
+            .map(|mut parent| {
+                while let Some(&grandparent) = removed.get(&replacements.resolve(parent)) {
+                    parent = grandparent;
+                    relinks = true;
+                }
+                parent
+            })
+            .collect::<Vec<_>>();
+        if relinks {
+            relinked.insert(id, new_parents);
+        }
+    }
+
+    if !dry_run {
+        for &id in &order {
+            if removed.contains_key(&id)
+                || !(messages.contains_key(&id) || relinked.contains_key(&id))
+            {
+                continue;
+            }
+            let original = replacements.original(id);
+            replace(
+                repo,
+                id,
+                original,
+                messages.get(&id).map(String::as_str),
+                relinked.get(&id).map(Vec::as_slice),
+                "undag optimize",
+            )
+            .with_context(|| format!("{}: failed to replace commit", original))?;
+        }
+    }
+    Ok(changed)
+}
+
+// Folds `op` into a `set` of its result if its arguments are known, and
+// updates what is known to what holds after it runs.
+fn fold(op: &Op, known: &mut Known) -> Option<Op> {
+    let (var, val) = match op {
+        Op::Nop
+        | Op::Print(_)
+        | Op::Println(_)
+        | Op::Outb(_)
+        | Op::Cls
+        | Op::GotoXy(..)
+        | Op::Color(_) => return None,
+        // Branching to another module runs it, which may change anything.
+        Op::Branch(Get::Val(tag)) if !tag.to_string().contains("::") => return None,
+        Op::Set(var, src) => (var, value(src, known)),
+        Op::Concat(var, a, b) => (
+            var,
+            value(a, known)
+                .zip(value(b, known))
+                .map(|(a, b)| Val::Str(format!("{}{}", a, b).into())),
+        ),
+        Op::Charat(var, string, index) => (
+            var,
+            match (value(string, known), value(index, known)) {
+                (Some(string), Some(Val::Int(i))) => usize::try_from(i)
+                    .ok()
+                    .and_then(|i| string.to_string().chars().nth(i))
+                    .map(|c| Val::Str(c.to_string().into())),
+                _ => None,
+            },
+        ),
+        Op::Contains(var, a, b) | Op::Startswith(var, a, b) | Op::Endswith(var, a, b) => (
+            var,
+            value(a, known).zip(value(b, known)).map(|(a, b)| {
+                let (a, b) = (a.to_string(), b.to_string());
+                let found = match op {
+                    Op::Contains(..) => a.contains(&b),
+                    Op::Startswith(..) => a.starts_with(&b),
+                    _ => a.ends_with(&b),
+                };
+                Val::Int(found as i64)
+            }),
+        ),
+        Op::Tostr(var, a, b) | Op::Fromstr(var, a, b) => (
+            var,
+            value(a, known)
+                .zip(value(b, known))
+                .and_then(|(a, b)| radix(op, &a, &b)),
+        ),
+        Op::Strrev(var, string) => (
+            var,
+            value(string, known).map(|string| {
+                Val::Str(string.to_string().chars().rev().collect::<String>().into())
+            }),
+        ),
+        Op::Strrep(var, string, count) => (
+            var,
+            match (value(string, known), value(count, known)) {
+                (Some(string), Some(Val::Int(n))) => repeat(&string.to_string(), n, MAX_FOLDED_LEN)
+                    .ok()
+                    .map(|repeated| Val::Str(repeated.into())),
+                _ => None,
+            },
+        ),
+        Op::Sqrt(var, n)
+        | Op::Floor(var, n)
+        | Op::Ceil(var, n)
+        | Op::Round(var, n)
+        | Op::Sin(var, n)
+        | Op::Cos(var, n)
+        | Op::Ln(var, n) => (var, value(n, known).and_then(|n| math(op, &n))),
+        Op::Eq(var, a, b) => (
+            var,
+            value(a, known)
+                .zip(value(b, known))
+                .map(|(a, b)| Val::Int((a == b) as i64)),
+        ),
+        Op::Gt(var, a, b)
+        | Op::Add(var, a, b)
+        | Op::Sub(var, a, b)
+        | Op::Mul(var, a, b)
+        | Op::Div(var, a, b)
+        | Op::Mod(var, a, b)
+        | Op::And(var, a, b)
+        | Op::Or(var, a, b)
+        | Op::Xor(var, a, b)
+        | Op::Rotl(var, a, b)
+        | Op::Rotr(var, a, b)
+        | Op::Rdiv(var, a, b)
+        | Op::Radd(var, a, b)
+        | Op::Rsub(var, a, b)
+        | Op::Rmul(var, a, b) => (
+            var,
+            value(a, known)
+                .zip(value(b, known))
+                .and_then(|(a, b)| arith(op, &a, &b)),
+        ),
+        Op::Get(var, _)
+        | Op::Ref(var, _)
+        | Op::Deref(var, _)
+        | Op::Del(var)
+        | Op::Exists(var, _)
+        | Op::Is(var, ..)
+        | Op::Next(var, ..)
+        | Op::Match(var, ..)
+        | Op::Inpln(var, _)
+        | Op::Inb(var)
+        | Op::Key(var)
+        | Op::Clock(var)
+        | Op::Numer(var, _)
+        | Op::Denom(var, _)
+        | Op::Chars(var, _)
+        | Op::Graphemes(var, _)
+        | Op::Dump(var, _)
+        | Op::Load(var, _)
+        | Op::Meta(var, _) => (var, None),
+        _ => {
+            known.clear();
+            return None;
+        }
+    };
+    let name = match var {
+        Get::Val(name) => name.to_string(),
+        Get::Var(_) => {
+            known.clear();
+            return None;
+        }
+    };
+    // Only whole variables are tracked, so writing into a table forgets it.
+    match (name.split_once('/'), val) {
+        (None, Some(val)) => {
+            known.insert(name, val.clone());
+            let src = literal(val)?;
+            match op {
+                Op::Set(_, Get::Val(_)) => None,
+                _ => Some(Op::Set(var.clone(), src)),
+            }
+        }
+        (Some((table, _)), _) => {
+            known.remove(table);
+            None
+        }
+        (None, None) => {
+            known.remove(&name);
+            None
+        }
+    }
+}
+
+fn value(get: &Get, known: &Known) -> Option<Val> {
+    match get {
+        Get::Val(val) => Some(val.clone()),
+        Get::Var(var) => known.get(&**var).cloned(),
+    }
+}
+
+// `val` as an argument, unless it would be read back as something else, like
+// a string starting with `$`.
+fn literal(val: Val) -> Option<Get> {
+    match &val {
+        Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_) => Some(Get::Val(val)),
+        _ => match val.to_string().parse::<Get>() {
+            Ok(Get::Val(parsed)) if parsed == val => Some(Get::Val(val)),
+            _ => None,
+        },
+    }
+}
+
+// Why the op of `id` does nothing, if it does not.
+fn removable(
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    children: &tree::Children,
+    id: Oid,
+    op: &Op,
+) -> Option<&'static str> {
+    let nexts = children.get(&id).filter(|nexts| nexts.len() == 1)?;
+    match op {
+        Op::Nop => Some("removed empty op"),
+        Op::Branch(Get::Val(tag)) => {
+            let target = tree::find_tag(source, replacements, &tag.to_string(), nexts)?;
+            (target.id == nexts[0].id).then_some("removed branch to the only child")
+        }
+        _ => None,
+    }
+}
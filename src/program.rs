@@ -0,0 +1,80 @@
+use crate::interp::Op;
+use crate::tree::{self, Children};
+use crate::{Oid, ProgramSource, Replacements, UndagError};
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+
+// A program's commit graph with every commit message parsed once up front.
+// Parse errors are kept rather than reported so that, like before, they only
+// surface if the offending commit is actually executed.
+pub struct Program {
+    pub children: Children,
+    ops: HashMap<Oid, Result<Op>>,
+}
+
+impl Program {
+    #[tracing::instrument(level = "debug", skip(source, replacements), fields(%start, %end))]
+    pub fn load(
+        source: &dyn ProgramSource,
+        replacements: &Replacements,
+        start: Oid,
+        end: Oid,
+    ) -> Result<Self> {
+        let parse = |id| {
+            source
+                .message(id)
+                .context("syntax error")
+                .and_then(|message| message.parse::<Op>())
+        };
+
+        let mut ops = HashMap::new();
+        ops.insert(start, parse(start));
+        ops.insert(end, parse(end));
+
+        let children = tree::collect_children(source, replacements, start, end)?;
+        // The history of `end` only leads back to `start` if it is reached by
+        // going forward from `start`, or else running would stop at `start`
+        // for want of a child.
+        if start != end && !children.contains_key(&start) {
+            let (start, end) = (replacements.original(start), replacements.original(end));
+            let reason = match source.descendant_of(start, end) {
+                true => "it comes before the start, so the tags may be the wrong way around",
+                false => {
+                    "the start is not in its history; the end must be tagged on a descendant \
+                     of the start, or on the start itself"
+                }
+            };
+            return Err(UndagError::EndNotReached {
+                start,
+                end,
+                reason: reason.to_owned(),
+            }
+            .into());
+        }
+        for nexts in children.values() {
+            for next in nexts {
+                ops.entry(next.id).or_insert_with(|| parse(next.id));
+            }
+        }
+
+        tracing::debug!(commits = ops.len(), "parsed program");
+        Ok(Self { children, ops })
+    }
+
+    // The ops of every commit whose message parses.
+    pub fn ops(&self) -> impl Iterator<Item = &Op> {
+        self.ops.values().filter_map(|op| op.as_ref().ok())
+    }
+
+    pub fn op(&self, id: Oid) -> Result<&Op> {
+        match self.ops.get(&id) {
+            Some(Ok(op)) => Ok(op),
+            Some(Err(e)) => Err(UndagError::SyntaxError {
+                oid: id,
+                message: format!("{:#}", e),
+            }
+            .into()),
+            None => Err(UndagError::NotInProgram { oid: id }.into()),
+        }
+    }
+}
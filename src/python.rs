@@ -0,0 +1,142 @@
+// The `#[pymethods]` and `#[pyfunction]` expansions convert `PyErr` into
+// itself.
+#![allow(clippy::useless_conversion)]
+
+use crate::{Instance, ProgramSource, StepResult, Val};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::VecDeque;
+
+fn to_py(py: Python<'_>, val: &Val) -> PyResult<PyObject> {
+    Ok(match val {
+        Val::Int(n) => n.into_py(py),
+        Val::BigInt(n) => n.to_object(py),
+        Val::Ratio(r) => py
+            .import_bound("fractions")?
+            .getattr("Fraction")?
+            .call1((r.numer().to_object(py), r.denom().to_object(py)))?
+            .into_py(py),
+        Val::Float(x) => x.into_py(py),
+        // Python has nothing like them, so references are given as the path
+        // they refer to.
+        Val::Str(s) | Val::Ref(s) => s.into_py(py),
+        Val::Table(table) => {
+            let dict = PyDict::new_bound(py);
+            for (key, val) in table {
+                dict.set_item(&**key, to_py(py, val)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", e))
+}
+
+// A program loaded from a repository or exported DAG file, run a step at a
+// time with input supplied up front.
+#[pyclass(name = "Instance", unsendable)]
+struct PyInstance {
+    source: Box<dyn ProgramSource>,
+    instance: Instance,
+    running: bool,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+#[pymethods]
+impl PyInstance {
+    #[new]
+    #[pyo3(signature = (path, seed=None))]
+    fn new(path: &str, seed: Option<u64>) -> PyResult<Self> {
+        let source = crate::open(path).map_err(to_py_err)?;
+        let (start, end) = crate::endpoints(&*source).map_err(to_py_err)?;
+        let mut instance = Instance::new();
+        if let Some(seed) = seed {
+            instance.set_seed(seed);
+        }
+        instance.start(&*source, start, end).map_err(to_py_err)?;
+        Ok(Self {
+            source,
+            instance,
+            running: true,
+            input: VecDeque::new(),
+            output: Vec::new(),
+        })
+    }
+
+    #[getter]
+    fn running(&self) -> bool {
+        self.running
+    }
+
+    fn write_input(&mut self, data: &str) {
+        self.input.extend(data.bytes());
+    }
+
+    // Executes one commit, returning whether the program is still running.
+    // Once the written input runs out, reading input gives empty lines.
+    fn step(&mut self) -> PyResult<bool> {
+        if self.running {
+            match self.instance.step(&*self.source, &mut self.output) {
+                StepResult::Continued => {}
+                StepResult::Halted => self.running = false,
+                StepResult::NeedsInput => {
+                    let len = self.input.iter().position(|&byte| byte == b'\n');
+                    let line = self
+                        .input
+                        .drain(..len.map_or(self.input.len(), |len| len + 1))
+                        .collect::<Vec<_>>();
+                    self.instance.push_input(line);
+                }
+                StepResult::Error(e) => {
+                    self.running = false;
+                    return Err(to_py_err(e));
+                }
+            }
+        }
+        Ok(self.running)
+    }
+
+    fn run(&mut self) -> PyResult<()> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    // Everything the program has printed since the last call.
+    fn read_output(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.output)).into_owned()
+    }
+
+    fn get(&self, py: Python<'_>, var: &str) -> PyResult<Option<PyObject>> {
+        self.instance
+            .get(var)
+            .ok()
+            .map(|val| to_py(py, val))
+            .transpose()
+    }
+
+    #[getter]
+    fn table(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_py(py, &Val::Table(self.instance.root_table().clone()))
+    }
+}
+
+// Runs the program at `path` to completion and returns what it printed.
+#[pyfunction]
+#[pyo3(signature = (path, stdin="", seed=None))]
+fn run(path: &str, stdin: &str, seed: Option<u64>) -> PyResult<String> {
+    let mut instance = PyInstance::new(path, seed)?;
+    instance.write_input(stdin);
+    instance.run()?;
+    Ok(instance.read_output())
+}
+
+#[pymodule]
+fn undag(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyInstance>()?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tungstenite::Message;
+use undag::dag::Dag;
+use undag::{state, Instance, Oid, Order, ProgramSource, StepResult};
+
+const PAGE: &str = include_str!("serve.html");
+
+// How long a watcher's connection waits for input before sending it the
+// states that have come in.
+const POLL: Duration = Duration::from_millis(50);
+
+// What every watcher is sent: the latest state whole, for those who connect
+// later, and a channel to each of them for the states that follow.
+#[derive(Default)]
+struct Watchers {
+    snapshot: Mutex<String>,
+    senders: Mutex<Vec<mpsc::Sender<String>>>,
+}
+
+impl Watchers {
+    // Sends `update` to every watcher and keeps `snapshot` for new ones.
+    fn publish(&self, update: String, snapshot: String) {
+        let mut latest = self.snapshot.lock().unwrap();
+        *latest = snapshot;
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(update.clone()).is_ok());
+    }
+}
+
+// Runs the program at `speed` steps per second while serving a page at
+// `addr` that draws its commits and follows the run: which commit is current,
+// the variables and the output. Watchers can type the program's input. Once
+// the program ends, its last state is served until interrupted.
+pub fn serve(
+    source: &dyn ProgramSource,
+    start: Oid,
+    end: Oid,
+    end_tag: &str,
+    order: Order,
+    addr: &str,
+    speed: f64,
+) -> Result<()> {
+    let graph = Dag::export(source, &source.tags()?)?.to_json();
+    // The page names commits by the ids of the commits replacements stand in
+    // for, as the graph does.
+    let replaced = source
+        .replacements()?
+        .into_iter()
+        .map(|(id, replace)| (replace, id))
+        .collect::<HashMap<_, _>>();
+    let original = |mut id: Oid| {
+        while let Some(&old) = replaced.get(&id) {
+            id = old;
+        }
+        id
+    };
+    let graph = Arc::new(format!(
+        "{{\"graph\":{},\"start\":\"{}\"}}",
+        graph,
+        original(start)
+    ));
+
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("--addr: failed to listen on {}", addr))?;
+    eprintln!("serving on http://{}", listener.local_addr()?);
+    let watchers = Arc::new(Watchers::default());
+    let (input, lines) = mpsc::channel::<Vec<u8>>();
+    let accepting = {
+        let watchers = watchers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (graph, watchers, input) = (graph.clone(), watchers.clone(), input.clone());
+                // A watcher going away is no concern of the run's.
+                std::thread::spawn(move || handle(stream, &graph, &watchers, &input).ok());
+            }
+        })
+    };
+
+    let mut instance = Instance::new();
+    instance.set_end_tag(end_tag);
+    instance.set_order(order);
+    instance.set_seed(rand::random());
+    instance.set_ansi(false);
+    instance.start(source, start, end)?;
+    let interval = Duration::from_secs_f64(1.0 / speed);
+    let mut output = Vec::new();
+    let mut sent = 0;
+    let mut last = start;
+    let mut steps = 0;
+    let mut publish = |instance: &Instance, output: &[u8], status: &str, last: Oid, steps| {
+        let state = |output: &[u8]| {
+            let commit = instance.current_commit().unwrap_or(last);
+            serde_json::json!({
+                "commit": original(commit).to_string(),
+                "steps": steps,
+                "status": status,
+                "vars": state::to_json(instance.root_table()),
+                "output": String::from_utf8_lossy(output),
+            })
+            .to_string()
+        };
+        watchers.publish(state(&output[sent..]), state(output));
+        sent = output.len();
+    };
+    publish(&instance, &output, "running", last, steps);
+    loop {
+        std::thread::sleep(interval);
+        if let Some(commit) = instance.current_commit() {
+            last = commit;
+        }
+        match instance.step(source, &mut output) {
+            StepResult::Continued => {
+                steps += 1;
+                publish(&instance, &output, "running", last, steps);
+            }
+            StepResult::Halted => {
+                publish(&instance, &output, "halted", last, steps + 1);
+                break;
+            }
+            StepResult::NeedsInput => {
+                publish(&instance, &output, "waiting for input", last, steps);
+                // The watchers' connections hold the other ends, and they
+                // are kept for as long as the server runs.
+                let line = lines.recv()?;
+                output.extend_from_slice(&line);
+                instance.push_input(line);
+            }
+            StepResult::Error(e) => {
+                let status = format!("error: {:#}", e);
+                publish(&instance, &output, &status, last, steps);
+                if let Some(diagnostic) = instance.diagnostic() {
+                    eprint!("{}", diagnostic);
+                }
+                eprintln!("Error: {:#}", e);
+                break;
+            }
+        }
+    }
+    accepting.join().ok();
+    Ok(())
+}
+
+// Serves the page, the graph, or a watcher's websocket, going by the path of
+// the request.
+fn handle(
+    mut stream: TcpStream,
+    graph: &str,
+    watchers: &Watchers,
+    input: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    let mut head = [0; 4096];
+    let len = stream.peek(&mut head)?;
+    let request = String::from_utf8_lossy(&head[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+    if path == "/ws" {
+        return watch(stream, watchers, input);
+    }
+    // The request is taken off the connection so that closing it does not
+    // reset it before the response is read.
+    stream.read_exact(&mut head[..len])?;
+    let (status, kind, body) = match &*path {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE),
+        "/graph.json" => ("200 OK", "application/json", graph),
+        _ => ("404 Not Found", "text/plain", "not found"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        kind,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+// Sends the watcher every state of the run, starting with the latest, and
+// passes on the lines of input it sends as {"input": TEXT}.
+fn watch(stream: TcpStream, watchers: &Watchers, input: &mpsc::Sender<Vec<u8>>) -> Result<()> {
+    let mut socket = tungstenite::accept(stream)?;
+    let (sender, states) = mpsc::channel();
+    {
+        // Held so that no state is published between the snapshot and the
+        // watcher being added.
+        let snapshot = watchers.snapshot.lock().unwrap();
+        socket.send(Message::text(snapshot.clone()))?;
+        watchers.senders.lock().unwrap().push(sender);
+    }
+    socket.get_mut().set_read_timeout(Some(POLL))?;
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let line = serde_json::from_str::<serde_json::Value>(&text)
+                    .ok()
+                    .and_then(|json| Some(json["input"].as_str()?.to_owned()));
+                if let Some(line) = line {
+                    input.send(format!("{}\n", line).into_bytes())?;
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+        for state in states.try_iter() {
+            socket.send(Message::text(state))?;
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use anyhow::Result;
+use hashbrown::HashSet;
+
+// Commit ids share the layout of Git object ids so that sources backed by a
+// repository can use them as they are.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Oid([u8; 20]);
+
+impl Oid {
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    // The abbreviated form that Git shows by default.
+    pub fn short(&self) -> String {
+        self.to_string()[..7].to_owned()
+    }
+}
+
+impl std::fmt::Display for Oid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl std::fmt::Debug for Oid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::str::FromStr for Oid {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        anyhow::ensure!(s.len() == 40 && s.is_ascii(), "invalid object id: {}", s);
+        let mut bytes = [0; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| anyhow::anyhow!("invalid object id: {}", s))?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+// Where the commits of a program come from. The interpreter only ever asks
+// about ids that the source itself handed out, as a tag target, a parent, or
+// a replacement.
+pub trait ProgramSource {
+    fn message(&self, id: Oid) -> Result<String>;
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>>;
+
+    // Commit time in seconds since the Unix epoch.
+    fn time(&self, id: Oid) -> Result<i64>;
+
+    // The name and email of the commit's author, which are empty for sources
+    // that do not record them.
+    fn author(&self, id: Oid) -> Result<(String, String)> {
+        let _ = id;
+        Ok(Default::default())
+    }
+
+    // The commit `name` refers to: that of the tag `name`, peeled through any
+    // chain of annotated tags, or otherwise the commit whose id is abbreviated
+    // by `name`, as `git rev-parse` resolves it.
+    fn tag(&self, name: &str) -> Option<Oid>;
+
+    // The names of the tags and branches that `tag` finds.
+    fn tags(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    // Every pair of a commit and the commit replacing it, as created with
+    // `git replace`.
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>>;
+
+    // The contents of the file at `path` in the tree of commit `id`, if there
+    // is one there. Sources whose commits have no files never find any.
+    fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+        let _ = (id, path);
+        Ok(None)
+    }
+
+    // Writes a new commit, for programs that extend themselves while running.
+    // Sources are read-only unless they implement this and `create_tag`.
+    fn create_commit(&self, message: &str, parents: &[Oid]) -> Result<Oid> {
+        let _ = (message, parents);
+        Err(crate::UndagError::ReadOnlyProgram.into())
+    }
+
+    // Points the tag `name` at `id`, replacing any existing tag.
+    fn create_tag(&self, name: &str, id: Oid) -> Result<()> {
+        let _ = (name, id);
+        Err(crate::UndagError::ReadOnlyProgram.into())
+    }
+
+    // Fails unless `id` has a valid GPG or SSH signature. `keyring` is either
+    // a GnuPG home directory or an SSH allowed signers file.
+    fn verify_signature(&self, id: Oid, keyring: Option<&std::path::Path>) -> Result<()> {
+        let _ = keyring;
+        Err(crate::UndagError::BadSignature {
+            oid: id,
+            reason: "program does not support signatures".to_owned(),
+        }
+        .into())
+    }
+
+    // Whether `id` descends from `ancestor` in the history as written, without
+    // applying replacements.
+    fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+        let mut checked = HashSet::new();
+        let mut stack = self.parents(id).unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if id == ancestor {
+                return true;
+            }
+            if checked.insert(id) {
+                stack.extend(self.parents(id).unwrap_or_default());
+            }
+        }
+        false
+    }
+
+    // Another handle on the same commits that another thread can read from,
+    // for work split between threads. Sources that cannot open one read
+    // everything on the thread that holds them.
+    fn fork(&self) -> Option<Box<dyn ProgramSource + Send>> {
+        None
+    }
+}
+
+#[cfg(feature = "git")]
+pub(crate) use repository::parents;
+
+#[cfg(feature = "gitoxide")]
+pub use gitoxide::Gitoxide;
+
+// The commits at the boundary of a shallow clone whose git directory is
+// `git_dir`, as listed in its `shallow` file, which is read once rather than
+// for every commit visited. Other repositories have none.
+#[cfg(any(feature = "git", feature = "gitoxide"))]
+pub(crate) fn shallow_commits(git_dir: &std::path::Path) -> HashSet<Oid> {
+    std::fs::read_to_string(git_dir.join("shallow"))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(feature = "git")]
+mod repository {
+    use super::*;
+    use anyhow::Context;
+    use git::Repository;
+
+    impl From<git::Oid> for Oid {
+        fn from(id: git::Oid) -> Self {
+            let mut bytes = [0; 20];
+            bytes.copy_from_slice(id.as_bytes());
+            Self(bytes)
+        }
+    }
+
+    impl From<Oid> for git::Oid {
+        fn from(id: Oid) -> Self {
+            git::Oid::from_bytes(&id.0).unwrap()
+        }
+    }
+
+    // Commits at the boundary of a shallow clone, listed in `shallow`, have
+    // parents that are missing, so reading past them is an error rather than
+    // leaving the history silently truncated.
+    pub(crate) fn parents(repo: &Repository, id: Oid, shallow: &HashSet<Oid>) -> Result<Vec<Oid>> {
+        if shallow.contains(&id) {
+            return Err(crate::UndagError::ShallowHistory { oid: id }.into());
+        }
+        let commit = match repo.find_commit(id.into()) {
+            Err(_) if !shallow.is_empty() => {
+                return Err(crate::UndagError::ShallowHistory { oid: id }.into())
+            }
+            commit => commit?,
+        };
+        Ok(commit.parent_ids().map(Oid::from).collect())
+    }
+
+    impl ProgramSource for Repository {
+        fn message(&self, id: Oid) -> Result<String> {
+            self.find_commit(id.into())?
+                .message()
+                .map(str::to_owned)
+                .context("commit message is not valid UTF-8")
+        }
+
+        // A repository used directly has nowhere to keep the boundary of a
+        // shallow clone, so it is only checked for once a commit turns out to
+        // be missing, past the boundary. `open` reads the boundary up front.
+        fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+            match self.find_commit(id.into()) {
+                Err(_) if self.is_shallow() => {
+                    Err(crate::UndagError::ShallowHistory { oid: id }.into())
+                }
+                commit => Ok(commit?.parent_ids().map(Oid::from).collect()),
+            }
+        }
+
+        fn time(&self, id: Oid) -> Result<i64> {
+            Ok(self.find_commit(id.into())?.time().seconds())
+        }
+
+        fn author(&self, id: Oid) -> Result<(String, String)> {
+            let commit = self.find_commit(id.into())?;
+            let author = commit.author();
+            Ok((
+                author.name().unwrap_or_default().to_owned(),
+                author.email().unwrap_or_default().to_owned(),
+            ))
+        }
+
+        fn tag(&self, name: &str) -> Option<Oid> {
+            let mut namespaces = Vec::new();
+            if let Ok(config) = self.config() {
+                if let Ok(entries) = config.multivar("undag.refnamespace", None) {
+                    for entry in &entries {
+                        if let Some(namespace) = entry.ok().as_ref().and_then(|e| e.value()) {
+                            namespaces.push(namespace.to_owned());
+                        }
+                    }
+                }
+            }
+            revisions(name, namespaces)
+                .iter()
+                .find_map(|revision| self.revparse_single(revision).ok())
+                .map(|commit| commit.id().into())
+        }
+
+        fn tags(&self) -> Result<Vec<String>> {
+            let mut tags = self
+                .tag_names(None)?
+                .iter()
+                .flatten()
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            // Branches can be branch targets too.
+            for branch in self.branches(Some(git::BranchType::Local))? {
+                if let Some(name) = branch?.0.name()? {
+                    tags.push(name.to_owned());
+                }
+            }
+            Ok(tags)
+        }
+
+        fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+            let mut replacements = Vec::new();
+            for reference in self.references_glob("refs/replace/*")? {
+                let reference = reference?;
+                let id = reference
+                    .name()
+                    .and_then(|name| name.strip_prefix("refs/replace/"))
+                    .and_then(|id| id.parse::<Oid>().ok());
+                if let (Some(id), Ok(replace)) = (id, reference.peel_to_commit()) {
+                    replacements.push((id, replace.id().into()));
+                }
+            }
+            Ok(replacements)
+        }
+
+        fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+            let tree = self.find_commit(id.into())?.tree()?;
+            let entry = match tree.get_path(std::path::Path::new(path)) {
+                Ok(entry) => entry,
+                Err(e) if e.code() == git::ErrorCode::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let blob = entry.to_object(self)?.peel_to_blob()?;
+            Ok(Some(blob.content().to_owned()))
+        }
+
+        // New commits keep their first parent's files.
+        fn create_commit(&self, message: &str, parents: &[Oid]) -> Result<Oid> {
+            let parents = parents
+                .iter()
+                .map(|&parent| self.find_commit(parent.into()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let tree = match parents.first() {
+                Some(parent) => parent.tree()?,
+                None => self.find_tree(self.treebuilder(None)?.write()?)?,
+            };
+            let signature = self
+                .signature()
+                .or_else(|_| git::Signature::now("undag", "undag"))?;
+            let id = self.commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )?;
+            Ok(id.into())
+        }
+
+        // libgit2 cannot check signatures, so git does.
+        fn verify_signature(&self, id: Oid, keyring: Option<&std::path::Path>) -> Result<()> {
+            let mut command = std::process::Command::new("git");
+            command.arg("--git-dir").arg(self.path());
+            match keyring {
+                Some(keyring) if keyring.is_dir() => {
+                    command.env("GNUPGHOME", keyring);
+                }
+                Some(keyring) => {
+                    command
+                        .arg("-c")
+                        .arg(format!("gpg.ssh.allowedSignersFile={}", keyring.display()));
+                }
+                None => {}
+            }
+            let output = command
+                .args(["verify-commit", &id.to_string()])
+                .output()
+                .context("failed to run git to verify signature")?;
+            if !output.status.success() {
+                let reason = String::from_utf8_lossy(&output.stderr);
+                let reason = match reason.lines().last() {
+                    Some(line) => line.trim().to_owned(),
+                    None => "no signature".to_owned(),
+                };
+                return Err(crate::UndagError::BadSignature { oid: id, reason }.into());
+            }
+            Ok(())
+        }
+
+        fn create_tag(&self, name: &str, id: Oid) -> Result<()> {
+            self.reference(&format!("refs/tags/{}", name), id.into(), true, "mktag")?;
+            Ok(())
+        }
+
+        fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+            self.graph_descendant_of(id.into(), ancestor.into())
+                .unwrap_or(false)
+        }
+
+        fn fork(&self) -> Option<Box<dyn ProgramSource + Send>> {
+            Some(Box::new(Repository::open(self.path()).ok()?))
+        }
+    }
+}
+
+// Whether `name` could be an abbreviated object id. Like git, at least four
+// hex digits are required.
+pub(crate) fn is_abbreviated_id(name: &str) -> bool {
+    (4..=40).contains(&name.len()) && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// The revisions `name` may refer to as a branch target, in order of priority.
+// Branches are searched after tags, followed by any namespaces listed in the
+// `undag.refNamespace` config variable, such as `refs/remotes/origin`.
+#[cfg(any(feature = "git", feature = "gitoxide"))]
+fn revisions(name: &str, namespaces: Vec<String>) -> Vec<String> {
+    let mut revisions = ["refs/tags", "refs/heads"]
+        .into_iter()
+        .chain(
+            namespaces
+                .iter()
+                .map(|namespace| namespace.trim_end_matches('/')),
+        )
+        .map(|namespace| format!("{}/{}^{{commit}}", namespace, name))
+        .collect::<Vec<_>>();
+    if is_abbreviated_id(name) {
+        revisions.push(format!("{}^{{commit}}", name));
+    }
+    revisions
+}
+
+#[cfg(feature = "gitoxide")]
+mod gitoxide {
+    use super::*;
+    use anyhow::Context;
+    use gix::Repository;
+
+    impl From<gix::ObjectId> for Oid {
+        fn from(id: gix::ObjectId) -> Self {
+            let mut bytes = [0; 20];
+            bytes.copy_from_slice(id.as_bytes());
+            Self(bytes)
+        }
+    }
+
+    impl From<Oid> for gix::ObjectId {
+        fn from(id: Oid) -> Self {
+            gix::ObjectId::from_bytes_or_panic(&id.0)
+        }
+    }
+
+    // A repository read with gitoxide rather than libgit2.
+    pub struct Gitoxide {
+        repo: Repository,
+        // The boundary of a shallow clone, read once when the repository
+        // opens rather than for every commit visited.
+        shallow: HashSet<Oid>,
+    }
+
+    impl Gitoxide {
+        pub fn new(repo: Repository) -> Self {
+            let shallow = shallow_commits(repo.path());
+            Self { repo, shallow }
+        }
+    }
+
+    // Programs are read without any of the writing or signature checking
+    // that the libgit2 backend supports.
+    impl ProgramSource for Gitoxide {
+        fn message(&self, id: Oid) -> Result<String> {
+            let commit = self.repo.find_commit(gix::ObjectId::from(id))?;
+            let message = std::str::from_utf8(commit.message_raw_sloppy())
+                .context("commit message is not valid UTF-8")?;
+            Ok(message.to_owned())
+        }
+
+        fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+            if self.shallow.contains(&id) {
+                return Err(crate::UndagError::ShallowHistory { oid: id }.into());
+            }
+            let commit = match self.repo.find_commit(gix::ObjectId::from(id)) {
+                Err(_) if !self.shallow.is_empty() => {
+                    return Err(crate::UndagError::ShallowHistory { oid: id }.into())
+                }
+                commit => commit?,
+            };
+            Ok(commit
+                .parent_ids()
+                .map(|parent| parent.detach().into())
+                .collect())
+        }
+
+        fn time(&self, id: Oid) -> Result<i64> {
+            Ok(self
+                .repo
+                .find_commit(gix::ObjectId::from(id))?
+                .time()?
+                .seconds)
+        }
+
+        fn author(&self, id: Oid) -> Result<(String, String)> {
+            let commit = self.repo.find_commit(gix::ObjectId::from(id))?;
+            let author = commit.author()?;
+            Ok((author.name.to_string(), author.email.to_string()))
+        }
+
+        fn tag(&self, name: &str) -> Option<Oid> {
+            let namespaces = self
+                .repo
+                .config_snapshot()
+                .plumbing()
+                .strings("undag.refNamespace")
+                .unwrap_or_default()
+                .iter()
+                .map(|namespace| namespace.to_string())
+                .collect();
+            revisions(name, namespaces)
+                .iter()
+                .find_map(|revision| self.repo.rev_parse_single(revision.as_str()).ok())
+                .map(|id| id.detach().into())
+        }
+
+        fn tags(&self) -> Result<Vec<String>> {
+            let mut tags = Vec::new();
+            for prefix in ["refs/tags/", "refs/heads/"] {
+                for reference in self.repo.references()?.prefixed(prefix)? {
+                    let reference = reference.map_err(|e| anyhow::anyhow!(e))?;
+                    let name = reference.name().as_bstr().to_string();
+                    tags.push(name[prefix.len()..].to_owned());
+                }
+            }
+            Ok(tags)
+        }
+
+        fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+            let mut replacements = Vec::new();
+            for reference in self.repo.references()?.prefixed("refs/replace/")? {
+                let mut reference = reference.map_err(|e| anyhow::anyhow!(e))?;
+                let id = reference
+                    .name()
+                    .as_bstr()
+                    .to_string()
+                    .strip_prefix("refs/replace/")
+                    .and_then(|id| id.parse::<Oid>().ok());
+                if let (Some(id), Ok(replace)) = (id, reference.peel_to_id()) {
+                    replacements.push((id, replace.detach().into()));
+                }
+            }
+            Ok(replacements)
+        }
+
+        fn file(&self, id: Oid, path: &str) -> Result<Option<Vec<u8>>> {
+            let tree = self.repo.find_commit(gix::ObjectId::from(id))?.tree()?;
+            match tree.lookup_entry_by_path(path)? {
+                Some(entry) => Ok(Some(entry.object()?.detach().data)),
+                None => Ok(None),
+            }
+        }
+    }
+}
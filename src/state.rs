@@ -0,0 +1,158 @@
+use crate::intern::intern;
+use crate::interp::{Table, Val};
+use crate::Oid;
+use anyhow::{Context, Result};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+const REF_KEY: &str = "$ref";
+// Like references, fractions and integers too large for 64 bits are written as
+// tables whose only key names what they are, holding the text they print as,
+// so that they are read back as the same kind of number.
+const RATIO_KEY: &str = "$ratio";
+const BIGINT_KEY: &str = "$bigint";
+
+pub fn to_json(table: &Table) -> Value {
+    fn val_to_json(val: &Val) -> Value {
+        match val {
+            Val::Int(n) => Value::from(*n),
+            Val::BigInt(n) => serde_json::json!({ BIGINT_KEY: n.to_string() }),
+            Val::Ratio(r) => serde_json::json!({ RATIO_KEY: r.to_string() }),
+            Val::Float(x) => Value::from(*x),
+            Val::Str(s) => Value::from(&**s),
+            Val::Table(table) => to_json(table),
+            // As a table whose only key, `$ref`, is read as a variable if
+            // written in a program, so that it is unlikely to be a real one.
+            Val::Ref(path) => serde_json::json!({ REF_KEY: &**path }),
+        }
+    }
+
+    Value::Object(
+        table
+            .iter()
+            .map(|(key, val)| (key.to_string(), val_to_json(val)))
+            .collect(),
+    )
+}
+
+pub fn from_json(json: &Value) -> Result<Table> {
+    fn val_from_json(json: &Value) -> Result<Val> {
+        match json {
+            // Numbers written with a `.` or an exponent are floats, as in
+            // programs.
+            Value::Number(n) if n.is_f64() => n
+                .as_f64()
+                .map(Val::Float)
+                .with_context(|| format!("not a number: {}", n)),
+            Value::Number(n) => n
+                .as_i64()
+                .map(Val::Int)
+                .or_else(|| n.to_string().parse::<BigInt>().ok().map(Val::from))
+                .with_context(|| format!("not an integer: {}", n)),
+            Value::String(s) => Ok(Val::Str(s.as_str().into())),
+            Value::Bool(b) => Ok(Val::Int(*b as i64)),
+            Value::Object(map) => match map.iter().next() {
+                Some((key, Value::String(text))) if map.len() == 1 => match key.as_str() {
+                    REF_KEY => Ok(Val::Ref(intern(text))),
+                    RATIO_KEY => text
+                        .parse::<BigRational>()
+                        .map(Val::from)
+                        .with_context(|| format!("not a fraction: {}", text)),
+                    BIGINT_KEY => text
+                        .parse::<BigInt>()
+                        .map(Val::from)
+                        .with_context(|| format!("not an integer: {}", text)),
+                    _ => from_json(json).map(Val::Table),
+                },
+                _ => from_json(json).map(Val::Table),
+            },
+            // Arrays become tables laid out like the ones `chars` creates.
+            Value::Array(vals) => {
+                let mut table = vals
+                    .iter()
+                    .enumerate()
+                    .map(|(i, val)| val_from_json(val).map(|val| (intern(&i.to_string()), val)))
+                    .collect::<Result<Table>>()?;
+                table.insert(intern("len"), Val::Int(vals.len() as i64));
+                Ok(Val::Table(table))
+            }
+            Value::Null => Err(anyhow::anyhow!("unsupported value: {}", json)),
+        }
+    }
+
+    match json {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, val)| {
+                val_from_json(val)
+                    .map(|val| (intern(key), val))
+                    .with_context(|| format!("invalid value for {}", key))
+            })
+            .collect(),
+        _ => Err(anyhow::anyhow!("expected an object, found {}", json)),
+    }
+}
+
+pub fn load(path: &Path) -> Result<Table> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let json: Value = serde_json::from_reader(std::io::BufReader::new(file))?;
+    from_json(&json).with_context(|| format!("invalid state: {}", path.display()))
+}
+
+// Writes `table` as JSON to `out`.
+pub fn dump(mut out: impl Write, table: &Table) -> Result<()> {
+    serde_json::to_writer_pretty(&mut out, &to_json(table))?;
+    writeln!(out)?;
+    out.flush().map_err(From::from)
+}
+
+pub struct Checkpoint {
+    pub commit: Oid,
+    pub entered: Vec<String>,
+    pub table: Table,
+}
+
+impl Checkpoint {
+    pub fn write(path: &Path, commit: Oid, entered: &[Arc<str>], table: &Table) -> Result<()> {
+        let json = serde_json::json!({
+            "commit": commit.to_string(),
+            "entered": entered.iter().map(|name| &**name).collect::<Vec<_>>(),
+            "table": to_json(table),
+        });
+        // Write to a temporary file first so that a crash mid-write never
+        // leaves a truncated checkpoint behind.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&json)?)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .with_context(|| format!("failed to write checkpoint: {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open checkpoint: {}", path.display()))?;
+        let json: Value = serde_json::from_reader(std::io::BufReader::new(file))?;
+        let commit = json["commit"]
+            .as_str()
+            .context("checkpoint: missing commit")
+            .and_then(str::parse::<Oid>)?;
+        let entered = json["entered"]
+            .as_array()
+            .context("checkpoint: missing entered")?
+            .iter()
+            .map(|name| name.as_str().map(str::to_owned))
+            .collect::<Option<_>>()
+            .context("checkpoint: invalid entered")?;
+        let table = from_json(&json["table"]).context("checkpoint: invalid table")?;
+        Ok(Self {
+            commit,
+            entered,
+            table,
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use crate::interp::Op;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::interp::{Hook, Table};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+// Figures describing the shape of a program.
+pub struct Stats {
+    // Every commit between `start` and `end`, and those of them that can be
+    // reached from `start`, which the rest of the figures count.
+    pub commits: usize,
+    pub reachable: usize,
+    pub tags: usize,
+    // The most children any commit has.
+    pub max_children: usize,
+    // The most commits run one after another with no way to leave or enter
+    // the run partway through.
+    pub longest_chain: usize,
+    // How many commits have each kind of op, most common first. Empty ops
+    // are counted as `nop`, and commits that fail to parse as `invalid`.
+    pub ops: Vec<(String, usize)>,
+}
+
+impl Stats {
+    pub fn collect(source: &dyn ProgramSource, start: Oid, end: Oid) -> Result<Self> {
+        let replacements = Replacements::load(source)?;
+        let start = replacements.resolve(start);
+        let end = replacements.resolve(end);
+        let children = tree::collect_children(source, &replacements, start, end)?;
+        let order = tree::walk(&children, start);
+        let reachable = order.iter().copied().collect::<HashSet<_>>();
+        let mut all = children
+            .values()
+            .flatten()
+            .map(|next| next.id)
+            .chain(children.keys().copied())
+            .collect::<HashSet<_>>();
+        all.insert(end);
+
+        let mut parents = HashMap::<Oid, usize>::new();
+        for (id, nexts) in &children {
+            if reachable.contains(id) {
+                for next in nexts {
+                    *parents.entry(next.id).or_default() += 1;
+                }
+            }
+        }
+        let only_child = |id: &Oid| -> Option<Oid> {
+            match children.get(id).map(Vec::as_slice) {
+                Some([next]) if parents.get(&next.id) == Some(&1) && next.id != start => {
+                    Some(next.id)
+                }
+                _ => None,
+            }
+        };
+        let continues = order.iter().filter_map(only_child).collect::<HashSet<_>>();
+        let mut longest_chain = 0;
+        for &id in order.iter().filter(|id| !continues.contains(*id)) {
+            let mut len = 1;
+            let mut cur = id;
+            while let Some(next) = only_child(&cur) {
+                len += 1;
+                cur = next;
+            }
+            longest_chain = longest_chain.max(len);
+        }
+
+        let mut ops = HashMap::<String, usize>::new();
+        for &id in &order {
+            let name = match source.message(id).and_then(|message| message.parse::<Op>()) {
+                Ok(Op::Nop) => "nop".to_owned(),
+                Ok(op) => op.name().to_owned(),
+                Err(_) => "invalid".to_owned(),
+            };
+            *ops.entry(name).or_default() += 1;
+        }
+        let mut ops = ops.into_iter().collect::<Vec<_>>();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut tags = 0;
+        for name in source.tags()? {
+            if source
+                .tag(&name)
+                .is_some_and(|id| reachable.contains(&replacements.resolve(id)))
+            {
+                tags += 1;
+            }
+        }
+
+        Ok(Self {
+            commits: all.len(),
+            reachable: reachable.len(),
+            tags,
+            max_children: order
+                .iter()
+                .map(|id| children.get(id).map_or(0, Vec::len))
+                .max()
+                .unwrap_or_default(),
+            longest_chain,
+            ops,
+        })
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "commits: {}", self.commits)?;
+        writeln!(f, "reachable: {}", self.reachable)?;
+        writeln!(f, "tags: {}", self.tags)?;
+        writeln!(f, "max children: {}", self.max_children)?;
+        writeln!(f, "longest chain: {}", self.longest_chain)?;
+        writeln!(f, "ops:")?;
+        for (name, count) in &self.ops {
+            writeln!(f, "    {:<10} {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+// Figures describing a run of a program, counted as it runs.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct RunStats(Rc<RefCell<Run>>);
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Run {
+    started: Instant,
+    steps: usize,
+    branches: usize,
+    // The most variables the global table held at once.
+    peak_vars: usize,
+    // How many times each kind of op was executed, counted like in `Stats`.
+    ops: HashMap<String, usize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for RunStats {
+    fn default() -> Self {
+        Self(Rc::new(RefCell::new(Run {
+            started: Instant::now(),
+            steps: 0,
+            branches: 0,
+            peak_vars: 0,
+            ops: HashMap::new(),
+        })))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RunStats {
+    // Notes the size of the global table, after each step.
+    pub fn sample(&self, table: &Table) {
+        let mut run = self.0.borrow_mut();
+        run.peak_vars = run.peak_vars.max(table.len());
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Hook for RunStats {
+    fn before_op(&mut self, _commit: Oid, op: &Op) {
+        let mut run = self.0.borrow_mut();
+        run.steps += 1;
+        let name = match op {
+            Op::Nop => "nop",
+            op => op.name(),
+        };
+        match run.ops.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                run.ops.insert(name.to_owned(), 1);
+            }
+        }
+    }
+
+    fn after_branch(&mut self, _from: Oid, _tag: &str, _to: Oid) {
+        self.0.borrow_mut().branches += 1;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl std::fmt::Display for RunStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let run = self.0.borrow();
+        writeln!(f, "steps: {}", run.steps)?;
+        writeln!(f, "branches: {}", run.branches)?;
+        writeln!(f, "peak variables: {}", run.peak_vars)?;
+        writeln!(f, "time: {:.3?}", run.started.elapsed())?;
+        let mut ops = run.ops.iter().collect::<Vec<_>>();
+        ops.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        writeln!(f, "ops:")?;
+        for (name, count) in ops {
+            writeln!(f, "    {:<10} {}", name, count)?;
+        }
+        Ok(())
+    }
+}
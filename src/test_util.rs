@@ -0,0 +1,153 @@
+use crate::dag::{Dag, Graph};
+use crate::{endpoints, Instance, Order};
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+
+// Builds a program in code, for tests that would otherwise make commits by
+// shelling out to git. Each commit follows the one before it unless
+// `branch_to` names its children instead, and the first and last commits are
+// tagged `_start` and `_end` unless others are:
+//
+//     let output = Program::new()
+//         .commit("set x #0")
+//         .commit("add x $x #1")
+//         .tag("loop")
+//         .commit("gt end $x #2")
+//         .commit("match path $end #0 loop #1 _end")
+//         .commit("branch $path")
+//         .branch_to(&["loop", "_end"])
+//         .commit("println $x")
+//         .tag("_end")
+//         .run("")?;
+//
+// Children that lead back to an earlier commit are grafted, the same way
+// loops are made with `git replace --graft`.
+#[derive(Default)]
+pub struct Program {
+    ops: Vec<String>,
+    tags: Vec<(String, usize)>,
+    children: HashMap<usize, Vec<String>>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a commit with `op` as its message after the last one.
+    pub fn commit(mut self, op: impl Into<String>) -> Self {
+        self.ops.push(op.into());
+        self
+    }
+
+    // Tags the last commit.
+    pub fn tag(mut self, name: impl Into<String>) -> Self {
+        let last = self.ops.len().checked_sub(1).expect("no commit to tag");
+        self.tags.push((name.into(), last));
+        self
+    }
+
+    // Makes the last commit lead to the commits tagged `tags`, which may be
+    // tagged later, rather than to the commit after it.
+    pub fn branch_to(mut self, tags: &[&str]) -> Self {
+        let last = self
+            .ops
+            .len()
+            .checked_sub(1)
+            .expect("no commit to branch from");
+        self.children
+            .insert(last, tags.iter().map(|&tag| tag.to_owned()).collect());
+        self
+    }
+
+    // The program held in memory, which runs without a repository.
+    pub fn dag(&self) -> Result<Dag> {
+        anyhow::ensure!(!self.ops.is_empty(), "program has no commits");
+        let mut graph = Graph::default();
+        for (i, op) in self.ops.iter().enumerate() {
+            graph.node(&i.to_string());
+            graph.labels.insert(i.to_string(), op.clone());
+        }
+        let mut tagged = HashMap::new();
+        for (name, i) in &self.tags {
+            anyhow::ensure!(
+                tagged.insert(name.as_str(), *i).is_none(),
+                "tag {} is already defined",
+                name
+            );
+            graph
+                .tags
+                .get_mut(&i.to_string())
+                .unwrap()
+                .push(name.clone());
+        }
+        let last = self.ops.len() - 1;
+        for i in 0..self.ops.len() {
+            let children = match self.children.get(&i) {
+                Some(tags) => tags
+                    .iter()
+                    .map(|tag| {
+                        let child = tagged
+                            .get(tag.as_str())
+                            .with_context(|| format!("undefined tag: {}", tag))?;
+                        Ok(child.to_string())
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                None if i == last => Vec::new(),
+                None => vec![(i + 1).to_string()],
+            };
+            graph.children.insert(i.to_string(), children);
+        }
+        for (tag, i) in [("_start", 0), ("_end", last)] {
+            if !tagged.contains_key(tag) {
+                graph
+                    .tags
+                    .get_mut(&i.to_string())
+                    .unwrap()
+                    .push(tag.to_owned());
+            }
+        }
+        Ok(graph.to_dag())
+    }
+
+    // Runs the program in memory on `input`, taking children in turn with a
+    // fixed seed so that runs repeat, and returns what it printed.
+    pub fn run(&self, input: &str) -> Result<String> {
+        let dag = self.dag()?;
+        let (start, end) = endpoints(&dag)?;
+        let mut instance = Instance::new();
+        instance.set_order(Order::Deterministic);
+        instance.set_seed(0);
+        let mut output = Vec::new();
+        instance.run(&dag, start, end, &mut input.as_bytes(), &mut output)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    // Writes the program into a new repository in a temporary directory,
+    // which is deleted when the returned `Repo` is dropped.
+    #[cfg(feature = "git")]
+    pub fn build(&self) -> Result<Repo> {
+        let dir = tempfile::tempdir()?;
+        let repo = git::Repository::init(dir.path())?;
+        self.dag()?.write_to(&repo)?;
+        Ok(Repo { dir, repo })
+    }
+}
+
+// A program's repository in a temporary directory.
+#[cfg(feature = "git")]
+pub struct Repo {
+    dir: tempfile::TempDir,
+    repo: git::Repository,
+}
+
+#[cfg(feature = "git")]
+impl Repo {
+    pub fn path(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+
+    pub fn repo(&self) -> &git::Repository {
+        &self.repo
+    }
+}
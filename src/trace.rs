@@ -0,0 +1,318 @@
+use crate::interp::{Hook, Op, Order, Table, Terminal};
+use crate::{state, Oid};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+// Everything that decides how a run goes besides the program itself: the seed
+// that random choices of children are drawn from, the order children are
+// chosen in, the global table it starts with, the lines of input read, and
+// whether `inpln` wrote its prompt before reading each one and terminal
+// control ops their escape sequences, and every key read by `key` and time
+// read by `clock`.
+// Running the program again with the same seed, order, table and input
+// executes the same commits and prints the same output, which are kept to
+// check that it does.
+pub struct Trace {
+    pub seed: u64,
+    pub order: Order,
+    pub state: Table,
+    pub input: Vec<String>,
+    pub prompts: bool,
+    pub ansi: bool,
+    // Reads that found no key pressed are kept as empty strings.
+    pub keys: Vec<String>,
+    pub clock: Vec<i64>,
+    pub commits: Vec<Oid>,
+    // Traces recorded before output was kept have none to check.
+    pub output: Option<String>,
+}
+
+impl Trace {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::json!({
+            "seed": self.seed,
+            "order": order_name(self.order),
+            "state": state::to_json(&self.state),
+            "input": self.input,
+            "prompts": self.prompts,
+            "ansi": self.ansi,
+            "keys": self.keys,
+            "clock": self.clock,
+            "commits": self.commits.iter().map(Oid::to_string).collect::<Vec<_>>(),
+            "output": self.output,
+        });
+        std::fs::write(path, serde_json::to_vec(&json)?)
+            .with_context(|| format!("failed to write trace: {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open trace: {}", path.display()))?;
+        let json: Value = serde_json::from_reader(std::io::BufReader::new(file))?;
+        let seed = json["seed"].as_u64().context("trace: missing seed")?;
+        let order = json["order"]
+            .as_str()
+            .and_then(order_from_name)
+            .context("trace: invalid order")?;
+        let strings = |key: &str| {
+            json[key]
+                .as_array()
+                .with_context(|| format!("trace: missing {}", key))?
+                .iter()
+                .map(|val| val.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .with_context(|| format!("trace: invalid {}", key))
+        };
+        let state = match &json["state"] {
+            Value::Null => Table::new(),
+            table => state::from_json(table).context("trace: invalid state")?,
+        };
+        let input = strings("input")?;
+        let prompts = json["prompts"].as_bool().unwrap_or_default();
+        let ansi = json["ansi"].as_bool().unwrap_or(true);
+        let keys = match &json["keys"] {
+            Value::Null => Vec::new(),
+            _ => strings("keys")?,
+        };
+        let clock = match &json["clock"] {
+            Value::Null => Vec::new(),
+            clock => clock
+                .as_array()
+                .and_then(|clock| clock.iter().map(Value::as_i64).collect())
+                .context("trace: invalid clock")?,
+        };
+        let commits = strings("commits")?
+            .iter()
+            .map(|id| id.parse())
+            .collect::<Result<_>>()?;
+        let output = match &json["output"] {
+            Value::Null => None,
+            output => Some(output.as_str().context("trace: invalid output")?.to_owned()),
+        };
+        Ok(Self {
+            seed,
+            order,
+            state,
+            input,
+            prompts,
+            ansi,
+            keys,
+            clock,
+            commits,
+            output,
+        })
+    }
+
+    // Fails at the first commit where `commits` departs from the trace.
+    pub fn compare(&self, commits: &[Oid]) -> Result<()> {
+        let step = self
+            .commits
+            .iter()
+            .zip(commits)
+            .position(|(expected, executed)| expected != executed)
+            .unwrap_or(self.commits.len().min(commits.len()));
+        match (self.commits.get(step), commits.get(step)) {
+            (None, None) => Ok(()),
+            (Some(expected), Some(executed)) => anyhow::bail!(
+                "replay diverged at step {}: expected {}, executed {}",
+                step,
+                expected,
+                executed
+            ),
+            (Some(expected), None) => anyhow::bail!(
+                "replay stopped at step {} before executing {}",
+                step,
+                expected
+            ),
+            (None, Some(executed)) => {
+                anyhow::bail!("replay went on past the end of the trace to {}", executed)
+            }
+        }
+    }
+
+    // Fails where `output` first departs from the output in the trace.
+    pub fn compare_output(&self, output: &[u8]) -> Result<()> {
+        let Some(expected) = &self.output else {
+            return Ok(());
+        };
+        let expected = expected.as_bytes();
+        let at = expected
+            .iter()
+            .zip(output)
+            .position(|(expected, printed)| expected != printed)
+            .unwrap_or(expected.len().min(output.len()));
+        anyhow::ensure!(
+            at == expected.len() && at == output.len(),
+            "replay printed different output from byte {}",
+            at
+        );
+        Ok(())
+    }
+}
+
+fn order_name(order: Order) -> &'static str {
+    match order {
+        Order::Random => "random",
+        Order::Deterministic => "deterministic",
+        Order::Chronological => "chronological",
+        Order::ParentIndex => "first-parent",
+    }
+}
+
+fn order_from_name(name: &str) -> Option<Order> {
+    match name {
+        "random" => Some(Order::Random),
+        "deterministic" => Some(Order::Deterministic),
+        "chronological" => Some(Order::Chronological),
+        "first-parent" => Some(Order::ParentIndex),
+        _ => None,
+    }
+}
+
+// Keeps every commit executed, in order.
+#[derive(Clone, Default)]
+pub struct Recorder(Rc<RefCell<Vec<Oid>>>);
+
+impl Recorder {
+    pub fn commits(&self) -> Vec<Oid> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Hook for Recorder {
+    fn before_op(&mut self, commit: Oid, _op: &Op) {
+        self.0.borrow_mut().push(commit);
+    }
+}
+
+// When each commit executed started, to be written as a trace that Perfetto
+// and `chrome://tracing` can show. Each op lasts until the next one starts, so
+// that the time between them, such as finding a `branch`'s target or waiting
+// for input, is counted against the op that spent it.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct Timeline {
+    created: Instant,
+    ops: Rc<RefCell<Vec<TimedOp>>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct TimedOp {
+    commit: Oid,
+    op: String,
+    started: Duration,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            created: Instant::now(),
+            ops: Rc::default(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Timeline {
+    // Writes one complete event per op, named after it and with its commit
+    // as an argument, in microseconds from when the timeline was created.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let ops = self.ops.borrow();
+        let ended = self.created.elapsed();
+        let micros = |duration: Duration| duration.as_secs_f64() * 1e6;
+        let events = ops
+            .iter()
+            .enumerate()
+            .map(|(i, timed)| {
+                let next = ops.get(i + 1).map_or(ended, |next| next.started);
+                serde_json::json!({
+                    "name": if timed.op.is_empty() { "nop" } else { &timed.op },
+                    "cat": "op",
+                    "ph": "X",
+                    "ts": micros(timed.started),
+                    "dur": micros(next - timed.started),
+                    "pid": 1,
+                    "tid": 1,
+                    "args": { "commit": timed.commit.to_string() },
+                })
+            })
+            .collect::<Vec<_>>();
+        let json = serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" });
+        std::fs::write(path, serde_json::to_vec(&json)?)
+            .with_context(|| format!("failed to write trace: {}", path.display()))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Hook for Timeline {
+    fn before_op(&mut self, commit: Oid, op: &Op) {
+        self.ops.borrow_mut().push(TimedOp {
+            commit,
+            op: op.to_string(),
+            started: self.created.elapsed(),
+        });
+    }
+}
+
+// Keeps every key read from the terminal it wraps, in order.
+#[derive(Clone, Default)]
+pub struct KeyRecorder(Rc<RefCell<Vec<String>>>);
+
+impl KeyRecorder {
+    pub fn wrap(&self, terminal: impl Terminal + 'static) -> impl Terminal {
+        Recorded {
+            terminal,
+            keys: self.clone(),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.0.borrow().clone()
+    }
+}
+
+struct Recorded<T> {
+    terminal: T,
+    keys: KeyRecorder,
+}
+
+impl<T: Terminal> Terminal for Recorded<T> {
+    fn set_raw(&mut self, raw: bool) -> Result<()> {
+        self.terminal.set_raw(raw)
+    }
+
+    fn key(&mut self) -> Result<Option<String>> {
+        let key = self.terminal.key()?;
+        self.keys
+            .0
+            .borrow_mut()
+            .push(key.clone().unwrap_or_default());
+        Ok(key)
+    }
+}
+
+// Reads the keys of a trace in place of the terminal.
+pub struct ReplayedKeys(VecDeque<String>);
+
+impl ReplayedKeys {
+    pub fn new(keys: &[String]) -> Self {
+        Self(keys.iter().cloned().collect())
+    }
+}
+
+impl Terminal for ReplayedKeys {
+    fn set_raw(&mut self, _raw: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn key(&mut self) -> Result<Option<String>> {
+        Ok(self.0.pop_front().filter(|key| !key.is_empty()))
+    }
+}
@@ -0,0 +1,754 @@
+use crate::interp::{Get, Op, Order, Val};
+use crate::{tree, Oid, ProgramSource, Replacements};
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+
+// Writes the program between `start` and `end` as a standalone Python script
+// that behaves the same. Each commit becomes a function that executes its op
+// and returns the index of the commit to go on to, with `branch` targets
+// resolved ahead of time and children chosen in `order`. Ops that need the
+// repository or the embedder, and branches into modules, cannot be written.
+pub fn to_python(source: &dyn ProgramSource, start: Oid, end: Oid, order: Order) -> Result<String> {
+    let replacements = Replacements::load(source)?;
+    let start = replacements.resolve(start);
+    let end = replacements.resolve(end);
+    let children = tree::collect_children(source, &replacements, start, end)?;
+    let mut nodes = tree::walk(&children, start);
+    if !nodes.contains(&end) {
+        nodes.push(end);
+    }
+    let index = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, &id)| (id, i))
+        .collect::<HashMap<_, _>>();
+    let mut tags = source.tags()?;
+    tags.sort();
+
+    let mut script = String::from(RUNTIME);
+    for (i, &id) in nodes.iter().enumerate() {
+        let original = replacements.original(id);
+        let op = source
+            .message(id)
+            .and_then(|message| message.parse::<Op>())
+            .with_context(|| format!("{}: syntax error", original))?;
+        let nexts = children.get(&id).map_or(&[][..], Vec::as_slice);
+        script += &format!("\n\ndef n{}():\n", i);
+        let next = if id == end {
+            "None".to_owned()
+        } else {
+            match nexts {
+                [] => format!(
+                    "fail({})",
+                    string(&format!("{}: failed to find child to continue", original))
+                ),
+                [next] => index[&next.id].to_string(),
+                _ => match order {
+                    Order::Random => format!("random.choice([{}])", list(nexts, &index)),
+                    Order::Deterministic => format!("visit({}, [{}])", i, list(nexts, &index)),
+                    Order::Chronological => index[&nexts[0].id].to_string(),
+                    Order::ParentIndex => {
+                        let next = nexts.iter().min_by_key(|next| next.parent).unwrap();
+                        index[&next.id].to_string()
+                    }
+                },
+            }
+        };
+        let statements = match &op {
+            Op::Branch(Get::Val(tag)) => {
+                let tag = tag.to_string();
+                match tree::find_tag(source, &replacements, &tag, nexts) {
+                    Some(target) => vec![format!("return {}", index[&target.id])],
+                    None if tag.contains("::") => {
+                        anyhow::bail!("{}: branches into modules cannot be transpiled", original)
+                    }
+                    None => vec![format!(
+                        "fail({})",
+                        string(&format!(
+                            "{}: branch: failed to find target: {}",
+                            original, tag
+                        ))
+                    )],
+                }
+            }
+            Op::Branch(tag) => {
+                let targets = tags
+                    .iter()
+                    .filter_map(|name| {
+                        let target = tree::find_tag(source, &replacements, name, nexts)?;
+                        Some(format!("{}: {}", string(name), index[&target.id]))
+                    })
+                    .collect::<Vec<_>>();
+                vec![format!(
+                    "return branch({}, {}, {{{}}})",
+                    string(&original.to_string()),
+                    show(tag),
+                    targets.join(", ")
+                )]
+            }
+            Op::Meta(var, field) => {
+                let author = source.author(original)?;
+                let summary = source.message(original)?;
+                let fields = [
+                    ("author", string(&author.0)),
+                    ("email", string(&author.1)),
+                    ("time", source.time(original)?.to_string()),
+                    ("oid", string(&original.to_string())),
+                    (
+                        "summary",
+                        string(summary.lines().next().unwrap_or_default()),
+                    ),
+                ];
+                let fields = fields
+                    .iter()
+                    .map(|(name, val)| format!("{}: {}", string(name), val))
+                    .collect::<Vec<_>>();
+                vec![format!(
+                    "set_({}, meta({}, {{{}}}))",
+                    show(var),
+                    show(field),
+                    fields.join(", ")
+                )]
+            }
+            op => statements(op)
+                .with_context(|| format!("{}: {} cannot be transpiled", original, op.name()))?,
+        };
+        let returns = statements
+            .last()
+            .is_some_and(|line| line.starts_with("return"));
+        for line in &statements {
+            script += &format!("    {}\n", line);
+        }
+        if !returns {
+            script += &format!("    return {}\n", next);
+        }
+    }
+    script += &format!(
+        "\n\nNODES = [{}]\n",
+        (0..nodes.len())
+            .map(|i| format!("n{}", i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    script += MAIN;
+    Ok(script)
+}
+
+// The Python statements executing `op`, unless it has to be written another
+// way or cannot be written at all.
+fn statements(op: &Op) -> Option<Vec<String>> {
+    let binop = |name: &str, var: &Get, a: &Get, b: &Get| {
+        vec![format!(
+            "set_({}, binop({}, {}, {}))",
+            show(var),
+            string(name),
+            arg(a),
+            arg(b)
+        )]
+    };
+    let math = |name: &str, var: &Get, n: &Get| {
+        vec![format!(
+            "set_({}, math_op({}, {}))",
+            show(var),
+            string(name),
+            arg(n)
+        )]
+    };
+    Some(match op {
+        Op::Nop => Vec::new(),
+        Op::Set(var, src) => vec![format!("set_({}, {})", show(var), arg(src))],
+        Op::Get(var, src) => vec![format!("set_({}, get({}))", show(var), show(src))],
+        Op::Del(var) => vec![format!("delete({})", show(var))],
+        Op::Exists(var, symbol) => vec![format!("set_({}, exists({}))", show(var), show(symbol))],
+        Op::Next(var, table, cursor) => vec![format!(
+            "set_({}, next_key({}, {}))",
+            show(var),
+            show(table),
+            arg(cursor)
+        )],
+        Op::Enter(table) => vec![format!("enter({})", show(table))],
+        Op::Exit => vec!["exit()".to_owned()],
+        Op::Match(var, src, branches) => {
+            let mut lines = vec![format!("val = {}", arg(src))];
+            for (i, (case, val)) in branches.iter().enumerate() {
+                let keyword = if i == 0 { "if" } else { "elif" };
+                lines.push(format!("{} val == {}:", keyword, arg(case)));
+                lines.push(format!("    set_({}, {})", show(var), arg(val)));
+            }
+            lines
+        }
+        Op::Print(val) => vec![format!("write({})", show(val))],
+        Op::Println(val) => vec![format!("write({} + \"\\n\")", show(val))],
+        Op::Inpln(var, prompt) => vec![format!(
+            "set_({}, read_line({}))",
+            show(var),
+            prompt.as_ref().map_or("None".to_owned(), show)
+        )],
+        Op::Outb(byte) => vec![format!("outb({})", arg(byte))],
+        Op::Inb(var) => vec![format!("set_({}, inb())", show(var))],
+        Op::Clock(var) => vec![format!("set_({}, clock())", show(var))],
+        Op::Cls => vec!["write(\"\\x1b[2J\\x1b[H\")".to_owned()],
+        Op::GotoXy(x, y) => vec![format!("goto_xy({}, {})", arg(x), arg(y))],
+        Op::Color(n) => vec![format!("color({})", arg(n))],
+        Op::Concat(var, a, b) => vec![format!("set_({}, {} + {})", show(var), show(a), show(b))],
+        Op::Chars(var, s) => vec![format!("set_({}, chars({}))", show(var), show(s))],
+        Op::Tostr(var, n, base) => vec![format!(
+            "set_({}, tostr({}, {}))",
+            show(var),
+            arg(n),
+            arg(base)
+        )],
+        Op::Fromstr(var, s, base) => vec![format!(
+            "set_({}, fromstr({}, {}))",
+            show(var),
+            show(s),
+            arg(base)
+        )],
+        Op::Dump(var, t) => vec![format!("set_({}, dump({}))", show(var), arg(t))],
+        Op::Load(var, s) => vec![format!("set_({}, load({}))", show(var), show(s))],
+        Op::Strrev(var, s) => vec![format!("set_({}, {}[::-1])", show(var), show(s))],
+        Op::Strrep(var, s, n) => vec![format!(
+            "set_({}, strrep({}, {}))",
+            show(var),
+            show(s),
+            arg(n)
+        )],
+        Op::Contains(var, a, b) => vec![format!(
+            "set_({}, int({} in {}))",
+            show(var),
+            show(b),
+            show(a)
+        )],
+        Op::Startswith(var, a, b) => vec![format!(
+            "set_({}, int({}.startswith({})))",
+            show(var),
+            show(a),
+            show(b)
+        )],
+        Op::Endswith(var, a, b) => vec![format!(
+            "set_({}, int({}.endswith({})))",
+            show(var),
+            show(a),
+            show(b)
+        )],
+        Op::Charat(var, s, i) => vec![format!(
+            "set_({}, charat({}, {}))",
+            show(var),
+            show(s),
+            arg(i)
+        )],
+        Op::Eq(var, a, b) => vec![format!(
+            "set_({}, int({} == {}))",
+            show(var),
+            arg(a),
+            arg(b)
+        )],
+        Op::Gt(var, a, b) => binop("gt", var, a, b),
+        Op::Add(var, a, b) => binop("add", var, a, b),
+        Op::Sub(var, a, b) => binop("sub", var, a, b),
+        Op::Mul(var, a, b) => binop("mul", var, a, b),
+        Op::Div(var, a, b) => binop("div", var, a, b),
+        Op::Mod(var, a, b) => binop("mod", var, a, b),
+        Op::And(var, a, b) => binop("and", var, a, b),
+        Op::Or(var, a, b) => binop("or", var, a, b),
+        Op::Xor(var, a, b) => binop("xor", var, a, b),
+        Op::Rotl(var, a, b) => binop("rotl", var, a, b),
+        Op::Rotr(var, a, b) => binop("rotr", var, a, b),
+        Op::Rdiv(var, a, b) => binop("rdiv", var, a, b),
+        Op::Radd(var, a, b) => binop("radd", var, a, b),
+        Op::Rsub(var, a, b) => binop("rsub", var, a, b),
+        Op::Rmul(var, a, b) => binop("rmul", var, a, b),
+        Op::Numer(var, n) => vec![format!("set_({}, part(\"numer\", {}))", show(var), arg(n))],
+        Op::Denom(var, n) => vec![format!("set_({}, part(\"denom\", {}))", show(var), arg(n))],
+        Op::Sqrt(var, n) => math("sqrt", var, n),
+        Op::Floor(var, n) => math("floor", var, n),
+        Op::Ceil(var, n) => math("ceil", var, n),
+        Op::Round(var, n) => math("round", var, n),
+        Op::Sin(var, n) => math("sin", var, n),
+        Op::Cos(var, n) => math("cos", var, n),
+        Op::Ln(var, n) => math("ln", var, n),
+        Op::Branch(_)
+        | Op::Ref(..)
+        | Op::Deref(..)
+        | Op::Is(..)
+        | Op::Graphemes(..)
+        | Op::Rawmode(_)
+        | Op::Key(_)
+        | Op::Meta(..)
+        | Op::Mkcommit(..)
+        | Op::Mktag(..)
+        | Op::Extern(..) => return None,
+    })
+}
+
+// An argument as a Python expression.
+fn arg(get: &Get) -> String {
+    match get {
+        Get::Val(val @ (Val::Int(_) | Val::BigInt(_) | Val::Float(_))) => val.to_string(),
+        Get::Val(Val::Ratio(r)) => format!("Fraction({}, {})", r.numer(), r.denom()),
+        Get::Val(val) => string(&val.to_string()),
+        Get::Var(var) => format!("get({})", string(var)),
+    }
+}
+
+// An argument as a Python expression for the text it is written as.
+fn show(get: &Get) -> String {
+    match get {
+        Get::Val(val) => string(&val.to_string()),
+        get => format!("show({})", arg(get)),
+    }
+}
+
+fn list(nexts: &[tree::Child], index: &HashMap<Oid, usize>) -> String {
+    nexts
+        .iter()
+        .map(|next| index[&next.id].to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// A Python string literal.
+fn string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => literal += "\\\"",
+            '\\' => literal += "\\\\",
+            '\n' => literal += "\\n",
+            '\r' => literal += "\\r",
+            '\t' => literal += "\\t",
+            c if c.is_control() => literal += &format!("\\u{:04x}", c as u32),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+const RUNTIME: &str = r#"#!/usr/bin/env python3
+# Transpiled from an undag program by `undag transpile`.
+import copy
+from fractions import Fraction
+import decimal
+import json
+import math
+import random
+import sys
+import time
+
+root = {}
+entered = []
+visits = {}
+STARTED = time.monotonic()
+
+
+class UndagError(Exception):
+    pass
+
+
+def fail(message):
+    raise UndagError(message)
+
+
+def show(val):
+    if isinstance(val, dict):
+        return "<table>"
+    if isinstance(val, float):
+        return float_text(val)
+    return str(val)
+
+
+# Floats are written as the interpreter writes them, which only differs from
+# Python in leaving out the `+` and leading zeros of exponents.
+def float_text(x):
+    mantissa, e, exponent = repr(x).partition("e")
+    return mantissa + e + str(int(exponent)) if e else mantissa
+
+
+# And in JSON as serde_json writes them, with an exponent only for numbers
+# below 1e-5 or from 1e16 up.
+def json_float(x):
+    sign, digits, exponent = decimal.Decimal(repr(x)).normalize().as_tuple()
+    digits = "".join(map(str, digits))
+    point = len(digits) + exponent
+    if digits == "0":
+        text = "0.0"
+    elif point <= -5 or point > 16:
+        text = digits[0] + ("." + digits[1:] if len(digits) > 1 else "") + "e%+d" % (point - 1)
+    elif point <= 0:
+        text = "0." + "0" * -point + digits
+    elif point >= len(digits):
+        text = digits + "0" * (point - len(digits)) + ".0"
+    else:
+        text = digits[:point] + "." + digits[point:]
+    return "-" + text if sign else text
+
+
+def table():
+    cur = root
+    for name in entered:
+        cur = cur.get(name)
+        if not isinstance(cur, dict):
+            return {}
+    return cur
+
+
+def table_mut():
+    cur = root
+    for name in entered:
+        if not isinstance(cur.get(name), dict):
+            cur[name] = {}
+        cur = cur[name]
+    return cur
+
+
+def get(var):
+    cur = table()
+    *subs, tail = var.split("/")
+    for sub in subs:
+        if sub not in cur:
+            fail(f"undefined symbol: {var}")
+        cur = cur[sub]
+        if not isinstance(cur, dict):
+            fail(f"tried to access non-table as table: {var}")
+    if tail not in cur:
+        fail(f"undefined symbol: {var}")
+    return cur[tail]
+
+
+def parent(var):
+    cur = table_mut()
+    *subs, tail = var.split("/")
+    for sub in subs:
+        cur = cur.setdefault(sub, {})
+        if not isinstance(cur, dict):
+            fail(f"tried to access non-table as table: {var}")
+    return cur, tail
+
+
+def set_(var, val):
+    cur, tail = parent(var)
+    cur[tail] = copy.deepcopy(val)
+
+
+def delete(var):
+    cur, tail = parent(var)
+    cur.pop(tail, None)
+
+
+def next_key(name, cursor):
+    t = get(name)
+    if not isinstance(t, dict):
+        fail(f"tried to access non-table as table: {name}")
+    after = [key for key in t if cursor == -1 or key > show(cursor)]
+    return min(after) if after else -1
+
+
+# Fractions and integers too large for 64 bits are written as tables naming
+# what they are, the same way as the interpreter writes them.
+def dump(t):
+    def val(v):
+        if isinstance(v, dict):
+            return {key: val(x) for key, x in v.items()}
+        if isinstance(v, Fraction):
+            return {"$ratio": str(v)}
+        if isinstance(v, int) and not -(2**63) <= v < 2**63:
+            return {"$bigint": str(v)}
+        return v
+
+    def encode(v):
+        if isinstance(v, dict):
+            items = (json.dumps(key, ensure_ascii=False) + ":" + encode(v[key]) for key in sorted(v))
+            return "{" + ",".join(items) + "}"
+        if isinstance(v, float):
+            return json_float(v)
+        return json.dumps(v, ensure_ascii=False)
+
+    if not isinstance(t, dict):
+        fail(f"dump: invalid args: {show(t)}")
+    return encode(val(t))
+
+
+def load(s):
+    def val(v):
+        if isinstance(v, bool):
+            return int(v)
+        if isinstance(v, dict) and len(v) == 1 and isinstance(next(iter(v.values())), str):
+            key, x = next(iter(v.items()))
+            try:
+                if key == "$ratio":
+                    return Fraction(x) if Fraction(x).denominator != 1 else int(Fraction(x))
+                if key == "$bigint":
+                    return int(x)
+            except (ValueError, ZeroDivisionError):
+                fail(f"load: invalid table: {s}")
+        if isinstance(v, dict):
+            return {key: val(x) for key, x in v.items()}
+        if isinstance(v, list):
+            t = {str(i): val(x) for i, x in enumerate(v)}
+            t["len"] = len(v)
+            return t
+        if v is None or isinstance(v, float) and not math.isfinite(v):
+            fail(f"load: invalid table: {s}")
+        return v
+
+    try:
+        t = json.loads(s)
+    except ValueError:
+        t = None
+    if not isinstance(t, dict):
+        fail(f"load: invalid table: {s}")
+    return val(t)
+
+
+def exists(symbol):
+    cur = table()
+    *subs, tail = symbol.split("/")
+    for sub in subs:
+        if sub not in cur:
+            return 0
+        cur = cur[sub]
+        if not isinstance(cur, dict):
+            fail(f"tried to access non-table as table: {symbol}")
+    return int(tail in cur)
+
+
+def enter(name):
+    for sub in name.split("/"):
+        cur = table_mut()
+        if not isinstance(cur.setdefault(sub, {}), dict):
+            fail(f"tried to access non-table as table: {sub}")
+        entered.append(sub)
+
+
+def exit():
+    if entered:
+        entered.pop()
+
+
+def write(s):
+    sys.stdout.write(s)
+    sys.stdout.flush()
+
+
+def read_line(prompt):
+    if prompt is not None and sys.stdin.isatty():
+        write(prompt)
+    line = sys.stdin.buffer.readline().decode(errors="replace")
+    if line.endswith("\n"):
+        line = line[:-1]
+    if line.endswith("\r"):
+        line = line[:-1]
+    return line
+
+
+def outb(n):
+    if not isinstance(n, int) or not 0 <= n <= 255:
+        fail(f"outb: invalid args: {show(n)}")
+    sys.stdout.flush()
+    sys.stdout.buffer.write(bytes([n]))
+    sys.stdout.buffer.flush()
+
+
+def goto_xy(x, y):
+    if not all(isinstance(n, int) and n >= 0 for n in (x, y)):
+        fail(f"goto_xy: invalid args: {show(x)} {show(y)}")
+    write(f"\x1b[{y + 1};{x + 1}H")
+
+
+def color(n):
+    if not isinstance(n, int) or not 0 <= n <= 255:
+        fail(f"color: invalid args: {show(n)}")
+    write(f"\x1b[{n}m")
+
+
+def clock():
+    return int((time.monotonic() - STARTED) * 1000)
+
+
+def inb():
+    byte = sys.stdin.buffer.read(1)
+    return byte[0] if byte else -1
+
+
+def charat(s, i):
+    if not isinstance(i, int) or not 0 <= i < len(s):
+        fail(f"charat: invalid args: {show(s)} {show(i)}")
+    return s[i]
+
+
+DIGITS = "0123456789abcdefghijklmnopqrstuvwxyz"
+
+
+def tostr(n, base):
+    if not isinstance(n, int) or not isinstance(base, int) or not 2 <= base <= 36:
+        fail(f"tostr: invalid args: {show(n)} {show(base)}")
+    digits = ""
+    rest = abs(n)
+    while True:
+        digits = DIGITS[rest % base] + digits
+        rest //= base
+        if rest == 0:
+            break
+    return "-" + digits if n < 0 else digits
+
+
+def fromstr(s, base):
+    digits = s[1:] if s[:1] in ("+", "-") else s
+    if isinstance(base, int) and 2 <= base <= 36 and digits:
+        if all(c in DIGITS[:base] for c in digits.lower()):
+            return int(s, base)
+    fail(f"fromstr: invalid args: {s} {show(base)}")
+
+
+# The same limit as the interpreter's on the strings strrep makes.
+MAX_REPEAT_LEN = 1 << 28
+
+
+def strrep(s, n):
+    if not isinstance(n, int) or n < 0:
+        fail(f"strrep: invalid args: {s} {show(n)}")
+    if len(s.encode()) * n > MAX_REPEAT_LEN:
+        fail(f"strrep: result would be longer than {MAX_REPEAT_LEN} bytes")
+    return s * n
+
+
+def chars(s):
+    table = {str(i): c for i, c in enumerate(s)}
+    table["len"] = len(table)
+    return table
+
+
+def div(a, b):
+    if b == 0:
+        fail("attempt to divide by zero")
+    q = abs(a) // abs(b)
+    return q if (a < 0) == (b < 0) else -q
+
+
+def rotl(a, n):
+    n %= 64
+    a &= (1 << 64) - 1
+    a = (a << n | a >> (64 - n)) & ((1 << 64) - 1)
+    return a - (1 << 64) if a >= 1 << 63 else a
+
+
+BINOPS = {
+    "gt": lambda a, b: int(a > b),
+    "add": lambda a, b: a + b,
+    "sub": lambda a, b: a - b,
+    "mul": lambda a, b: a * b,
+    "div": div,
+    "mod": lambda a, b: a - b * div(a, b),
+    "and": lambda a, b: a & b,
+    "or": lambda a, b: a | b,
+    "xor": lambda a, b: a ^ b,
+    "rotl": rotl,
+    "rotr": lambda a, b: rotl(a, -b),
+}
+
+
+# Exact ops take fractions as well as integers, and so does `gt`. Whole
+# fractions become integers again.
+RATIO_OPS = {
+    "gt": lambda a, b: int(a > b),
+    "rdiv": lambda a, b: a / b,
+    "radd": lambda a, b: a + b,
+    "rsub": lambda a, b: a - b,
+    "rmul": lambda a, b: a * b,
+}
+
+
+# Arithmetic with a float gives a float, while the exact ops refuse them.
+FLOAT_OPS = {
+    "add": lambda a, b: a + b,
+    "sub": lambda a, b: a - b,
+    "mul": lambda a, b: a * b,
+    "div": lambda a, b: a / b,
+}
+
+
+def binop(name, a, b):
+    if name in ("div", "mod", "rdiv") and isinstance(b, (int, Fraction, float)) and b == 0:
+        fail("attempt to divide by zero")
+    floats = float in (type(a), type(b))
+    if floats and all(isinstance(x, (int, Fraction, float)) for x in (a, b)):
+        if name == "gt":
+            return int(a > b)
+        try:
+            r = FLOAT_OPS[name](float(a), float(b)) if name in FLOAT_OPS else None
+        except OverflowError:
+            r = None
+        if r is None or not math.isfinite(r):
+            fail(f"{name}: invalid args: {show(a)} {show(b)}")
+        return r
+    numbers = all(isinstance(x, (int, Fraction)) for x in (a, b))
+    if numbers and name in RATIO_OPS and (name != "gt" or Fraction in (type(a), type(b))):
+        r = RATIO_OPS[name](Fraction(a), Fraction(b))
+        return r.numerator if r.denominator == 1 else r
+    if not isinstance(a, int) or not isinstance(b, int):
+        fail(f"{name}: invalid args: {show(a)} {show(b)}")
+    return BINOPS[name](a, b)
+
+
+def part(name, n):
+    if not isinstance(n, (int, Fraction)):
+        fail(f"{name}: invalid args: {show(n)}")
+    return Fraction(n).numerator if name == "numer" else Fraction(n).denominator
+
+
+# `floor`, `ceil` and `round` give integers, with `round` rounding halves away
+# from zero, and the rest give floats, failing where those would not be finite.
+def math_op(name, n):
+    if not isinstance(n, (int, Fraction, float)):
+        fail(f"{name}: invalid args: {show(n)}")
+    if name in ("floor", "ceil", "round") and isinstance(n, int):
+        return n
+    if name == "floor":
+        return math.floor(n)
+    if name == "ceil":
+        return math.ceil(n)
+    if name == "round":
+        whole = math.floor(abs(n))
+        whole += abs(n) - whole >= Fraction(1, 2)
+        return whole if n >= 0 else -whole
+    try:
+        x = float(n)
+    except OverflowError:
+        x = None
+    if x is None or name == "sqrt" and x < 0 or name == "ln" and x <= 0:
+        fail(f"{name}: invalid args: {show(n)}")
+    return {"sqrt": math.sqrt, "sin": math.sin, "cos": math.cos, "ln": math.log}[name](x)
+
+
+def meta(field, fields):
+    if field not in fields:
+        fail(f"meta: unknown field: {field}")
+    return fields[field]
+
+
+def branch(oid, tag, targets):
+    if tag not in targets:
+        fail(f"{oid}: branch: failed to find target: {tag}")
+    return targets[tag]
+
+
+def visit(node, nexts):
+    visits[node] = visits.get(node, 0) + 1
+    return nexts[(visits[node] - 1) % len(nexts)]
+"#;
+
+const MAIN: &str = r#"
+
+def main():
+    node = 0
+    while node is not None:
+        node = NODES[node]()
+
+
+if __name__ == "__main__":
+    try:
+        main()
+    except UndagError as e:
+        sys.exit(f"error: {e}")
+"#;
@@ -1,57 +1,246 @@
-use crate::replace;
-use git::{Commit, Oid, Repository};
+use crate::{Oid, ProgramSource, Replacements};
+use anyhow::Result;
 use hashbrown::{HashMap, HashSet};
 
-pub type Children<'a> = HashMap<Oid, Vec<Commit<'a>>>;
+pub type Children = HashMap<Oid, Vec<Child>>;
 
-pub fn collect_children<'a>(repo: &'a Repository, mut end: Commit<'a>) -> Children<'a> {
-    let mut children = HashMap::<Oid, HashSet<Oid>>::new();
-    let mut equals = HashMap::<Oid, Oid>::new();
+#[derive(Clone, Copy)]
+pub struct Child {
+    pub id: Oid,
+    // The index at which the commit `id` lists its parent among its parents.
+    pub parent: usize,
+}
 
-    if let Some(old_id) = replace(repo, &mut end) {
-        equals.insert(old_id, end.id());
-    }
-    let mut stack = vec![(end, 0)];
-    while let Some((commit, i)) = stack.last_mut() {
-        if let Ok(mut parent) = commit.parent(*i) {
-            *i += 1;
-            let mut done = true;
-            if let Some(old_id) = replace(repo, &mut parent) {
-                equals.insert(old_id, parent.id());
-            }
-            children
-                .entry(parent.id())
-                .or_insert_with(|| {
+// The fewest commits in a generation worth splitting between threads.
+const PARALLEL_BATCH: usize = 256;
+
+// The parents of a commit that the program follows, each with the index at
+// which the commit lists it, and the commit's time.
+type Visit = (Vec<(usize, Oid)>, i64);
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn collect_children(
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    start: Oid,
+    end: Oid,
+) -> Result<Children> {
+    let original_start = replacements.original(start);
+    let visit = |source: &dyn ProgramSource, id| {
+        followed_parents(source, replacements, start, original_start, id)
+    };
+    let mut children = HashMap::<Oid, Vec<Child>>::new();
+    let mut times = HashMap::new();
+
+    // History is walked a generation at a time, so that a large generation
+    // can be split between threads, each reading from a fork of the source.
+    let mut forks = None;
+    let mut generation = vec![replacements.resolve(end)];
+    while !generation.is_empty() {
+        let visits = visit_each(source, &mut forks, &generation, visit);
+        let mut next = Vec::new();
+        for (id, visited) in generation.into_iter().zip(visits) {
+            let (parents, time) = visited?;
+            times.insert(id, time);
+            for (index, parent) in parents {
+                let mut done = true;
+                let parent = replacements.resolve(parent);
+                let nexts = children.entry(parent).or_insert_with(|| {
                     done = false;
-                    HashSet::new()
-                })
-                .insert(commit.id());
-            if !done {
-                stack.push((parent, 0));
+                    Vec::new()
+                });
+                nexts.push(Child { id, parent: index });
+                if !done {
+                    next.push(parent);
+                }
             }
-        } else {
-            stack.pop();
         }
+        generation = next;
     }
 
-    children.iter_mut().for_each(|(_, set)| {
-        for id in std::mem::take(set) {
-            match equals.get(&id) {
-                Some(&new_id) => set.insert(new_id),
-                None => set.insert(id),
-            };
+    // Children are ordered by commit time and then by id, which traversal
+    // modes other than random selection rely on.
+    for nexts in children.values_mut() {
+        // A child listing the same parent more than once keeps its lowest
+        // parent index.
+        nexts.sort_by_key(|next| (times[&next.id], next.id, next.parent));
+        nexts.dedup_by_key(|next| next.id);
+    }
+    tracing::debug!(parents = children.len(), "collected children");
+    Ok(children)
+}
+
+fn followed_parents(
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    start: Oid,
+    original_start: Oid,
+    id: Oid,
+) -> Result<Visit> {
+    // Every commit reached must have readable parents, except for `_start`,
+    // whose history is not part of the program.
+    let parents = match source.parents(id) {
+        Err(_) if id == start => Vec::new(),
+        parents => parents?,
+    };
+    let merge = parents.len() > 1;
+    let mut followed = Vec::new();
+    for (index, parent) in parents.into_iter().enumerate() {
+        // History preceding `_start` is not part of the program, so only
+        // follow the parents of `_start` that loop back around to it, which
+        // are always readable.
+        if id == start
+            && (source.descendant_of(original_start, parent) || source.parents(parent).is_err())
+        {
+            continue;
         }
+        // Nor is history merged in from elsewhere, so a merge's parents are
+        // only followed if they lead back to `_start`. Replacements may
+        // rewrite where history leads, which `descendant_of` does not see, so
+        // with any in place every parent is followed.
+        if merge
+            && parent != original_start
+            && replacements.is_empty()
+            && !source.descendant_of(parent, original_start)
+        {
+            continue;
+        }
+        followed.push((index, parent));
+    }
+    Ok((followed, source.time(id).unwrap_or_default()))
+}
+
+// Visits each of `ids`, on as many threads as there are forks of `source`
+// along with this one once there are enough of them. The forks are opened for
+// the first generation large enough and kept for the rest.
+fn visit_each(
+    source: &dyn ProgramSource,
+    forks: &mut Option<Vec<Box<dyn ProgramSource + Send>>>,
+    ids: &[Oid],
+    visit: impl Fn(&dyn ProgramSource, Oid) -> Result<Visit> + Sync,
+) -> Vec<Result<Visit>> {
+    if ids.len() < PARALLEL_BATCH {
+        return ids.iter().map(|&id| visit(source, id)).collect();
+    }
+    let forks = forks.get_or_insert_with(|| {
+        let threads = std::thread::available_parallelism().map_or(1, usize::from);
+        (1..threads).map_while(|_| source.fork()).collect()
     });
-    children
-        .into_iter()
-        .map(|(id, children)| {
-            (
-                id,
-                children
-                    .into_iter()
-                    .map(|id| repo.find_commit(id).unwrap())
-                    .collect(),
-            )
-        })
-        .collect()
+    let mut chunks = ids.chunks(ids.len().div_ceil(forks.len() + 1));
+    let own = chunks.next().unwrap_or_default();
+    let visit = &visit;
+    std::thread::scope(|scope| {
+        let handles = chunks
+            .zip(forks.iter_mut())
+            .map(|(chunk, fork)| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&id| visit(&**fork, id))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut visits = own.iter().map(|&id| visit(source, id)).collect::<Vec<_>>();
+        for handle in handles {
+            visits.extend(handle.join().unwrap());
+        }
+        visits
+    })
+}
+
+pub fn reachable(children: &Children, start: Oid) -> HashSet<Oid> {
+    walk(children, start).into_iter().collect()
+}
+
+// Breadth-first order of the commits reachable from `start`.
+pub fn walk(children: &Children, start: Oid) -> Vec<Oid> {
+    let mut reached = HashSet::new();
+    let mut order = Vec::new();
+    let mut queue = std::collections::VecDeque::from([start]);
+    while let Some(id) = queue.pop_front() {
+        if reached.insert(id) {
+            order.push(id);
+            if let Some(nexts) = children.get(&id) {
+                queue.extend(nexts.iter().map(|next| next.id));
+            }
+        }
+    }
+    order
+}
+
+pub fn find_tag<'a>(
+    source: &dyn ProgramSource,
+    replacements: &Replacements,
+    tag: &str,
+    commits: &'a [Child],
+) -> Option<&'a Child> {
+    Ancestry::of(source, replacements, tag)?.nearest(commits)
+}
+
+// The history of a tag's commit, searched depth first through each commit's
+// parents in turn. Every commit reached is kept with how deep the search was
+// when it got there and how many commits it had reached before, which is all
+// `branch` needs to pick one of several children.
+pub struct Ancestry {
+    tag: Oid,
+    reached: HashMap<Oid, (usize, usize)>,
+}
+
+impl Ancestry {
+    #[tracing::instrument(level = "debug", skip(source, replacements))]
+    pub fn of(source: &dyn ProgramSource, replacements: &Replacements, tag: &str) -> Option<Self> {
+        let tag = replacements.resolve(source.tag(tag)?);
+        let mut reached = HashMap::new();
+        reached.insert(tag, (1, 0));
+
+        // The tag's commit is searched from once more if the search loops
+        // back around to it.
+        let mut looped = false;
+        let mut stack = vec![(source.parents(tag).unwrap_or_default(), 0)];
+        while let Some((parents, i)) = stack.last_mut() {
+            if let Some(&parent) = parents.get(*i) {
+                let parent = replacements.resolve(parent);
+                let first = match parent == tag {
+                    true => !std::mem::replace(&mut looped, true),
+                    false => !reached.contains_key(&parent),
+                };
+                if first {
+                    let len = reached.len();
+                    reached.entry(parent).or_insert((stack.len() + 1, len));
+                    stack.push((source.parents(parent).unwrap_or_default(), 0));
+                } else {
+                    *i += 1;
+                }
+            } else {
+                stack.pop();
+            }
+        }
+        tracing::debug!(ancestors = reached.len(), "searched history");
+        Some(Self { tag, reached })
+    }
+
+    // The commit the tag resolved to, after replacements.
+    pub fn tag(&self) -> Oid {
+        self.tag
+    }
+
+    // How deep the search was when it reached `id` and how many commits it
+    // had reached before, if it did.
+    pub fn reached(&self, id: Oid) -> Option<(usize, usize)> {
+        self.reached.get(&id).copied()
+    }
+
+    // The commit among `commits` nearest the tag, the one reached first if
+    // several are as near.
+    pub fn nearest<'a>(&self, commits: &'a [Child]) -> Option<&'a Child> {
+        commits
+            .iter()
+            .filter_map(|commit| Some((commit, self.reached.get(&commit.id)?)))
+            .inspect(|(commit, (depth, order))| {
+                tracing::trace!(commit = %commit.id, depth, order, "candidate");
+            })
+            .min_by_key(|(_, reached)| **reached)
+            .map(|(commit, _)| commit)
+    }
 }
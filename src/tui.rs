@@ -0,0 +1,326 @@
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::{Duration, Instant};
+use undag::{Instance, Oid, Order, ProgramSource, StepResult, Table, Val};
+
+// How many commits above and below the current one are drawn.
+const RADIUS: usize = 4;
+// The speed that running starts at if none was given.
+const DEFAULT_SPEED: f64 = 10.0;
+// The longest the screen goes without being redrawn while running, however
+// many steps are due.
+const FRAME: Duration = Duration::from_millis(33);
+
+// Runs the program in the terminal, drawing the commits around the current
+// one, the variables and the program's output as it goes. It starts paused
+// unless `speed`, in steps per second, is given.
+pub fn run(
+    source: &dyn ProgramSource,
+    start: Oid,
+    end: Oid,
+    end_tag: &str,
+    order: Order,
+    speed: Option<f64>,
+) -> Result<()> {
+    let mut instance = Instance::new();
+    instance.set_end_tag(end_tag);
+    instance.set_order(order);
+    instance.set_seed(rand::random());
+    // Escape sequences the program prints would draw over the screen.
+    instance.set_ansi(false);
+    instance.start(source, start, end)?;
+    let mut app = App {
+        instance,
+        source,
+        output: Vec::new(),
+        state: State::Paused,
+        speed: speed.unwrap_or(DEFAULT_SPEED),
+        typed: None,
+        last: start,
+        steps: 0,
+    };
+    if speed.is_some() {
+        app.state = State::Running;
+    }
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result?;
+    if let State::Failed(e) = app.state {
+        if let Some(diagnostic) = app.instance.diagnostic() {
+            eprint!("{}", diagnostic);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+enum State {
+    Paused,
+    Running,
+    Halted,
+    Failed(anyhow::Error),
+}
+
+struct App<'a> {
+    instance: Instance,
+    source: &'a dyn ProgramSource,
+    output: Vec<u8>,
+    state: State,
+    // Steps per second while running.
+    speed: f64,
+    // The line being typed while the program waits for input.
+    typed: Option<String>,
+    // The commit last executed, which is still drawn once the program ends.
+    last: Oid,
+    steps: usize,
+}
+
+impl App<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let mut due = Instant::now();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let running = matches!(self.state, State::Running) && self.typed.is_none();
+            let timeout = match running {
+                true => due.saturating_duration_since(Instant::now()).min(FRAME),
+                false => Duration::from_secs(1),
+            };
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                    if ctrl && key.code == KeyCode::Char('c') {
+                        return Ok(());
+                    }
+                    if let Some(typed) = &mut self.typed {
+                        match key.code {
+                            KeyCode::Char('d') if ctrl => self.give_input(Vec::new()),
+                            KeyCode::Char(c) if !ctrl => typed.push(c),
+                            KeyCode::Backspace => {
+                                typed.pop();
+                            }
+                            KeyCode::Enter => {
+                                let line = format!("{}\n", typed);
+                                self.give_input(line.into_bytes());
+                            }
+                            KeyCode::Esc => return Ok(()),
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char(' ') | KeyCode::Char('s') => {
+                            if matches!(self.state, State::Paused | State::Running) {
+                                self.state = State::Paused;
+                                self.step();
+                            }
+                        }
+                        KeyCode::Char('r') => match self.state {
+                            State::Paused => {
+                                self.state = State::Running;
+                                due = Instant::now();
+                            }
+                            State::Running => self.state = State::Paused,
+                            _ => {}
+                        },
+                        KeyCode::Char('+') | KeyCode::Char('=') => self.speed *= 2.0,
+                        KeyCode::Char('-') => self.speed = (self.speed / 2.0).max(0.5),
+                        _ => {}
+                    }
+                }
+            }
+            if matches!(self.state, State::Running) && self.typed.is_none() {
+                // Fast runs take as many steps as are due each frame.
+                let interval = Duration::from_secs_f64(1.0 / self.speed);
+                let frame = Instant::now() + FRAME;
+                while due <= Instant::now() && Instant::now() < frame {
+                    self.step();
+                    due += interval;
+                    if !matches!(self.state, State::Running) || self.typed.is_some() {
+                        break;
+                    }
+                }
+                // Steps that could not be taken in time are dropped rather
+                // than rushed through later.
+                if let Some(late) = Instant::now().checked_sub(interval) {
+                    due = due.max(late);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        if let Some(commit) = self.instance.current_commit() {
+            self.last = commit;
+        }
+        match self.instance.step(self.source, &mut self.output) {
+            StepResult::Continued => self.steps += 1,
+            StepResult::Halted => {
+                self.steps += 1;
+                self.state = State::Halted;
+            }
+            StepResult::NeedsInput => self.typed = Some(String::new()),
+            StepResult::Error(e) => self.state = State::Failed(e),
+        }
+    }
+
+    // Input is echoed into the output, as a terminal would.
+    fn give_input(&mut self, line: Vec<u8>) {
+        self.output.extend_from_slice(&line);
+        self.instance.push_input(line);
+        self.typed = None;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, io, status] = Layout::vertical([
+            Constraint::Min(RADIUS as u16 * 2 + 3),
+            Constraint::Percentage(30),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [graph, vars] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+
+        frame.render_widget(
+            Paragraph::new(self.neighborhood()).block(Block::bordered().title(" commits ")),
+            graph,
+        );
+
+        let mut lines = Vec::new();
+        variables(self.instance.root_table(), 0, &mut lines);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" variables ")),
+            vars,
+        );
+
+        let mut text = String::from_utf8_lossy(&self.output).into_owned();
+        if let Some(typed) = &self.typed {
+            text += typed;
+            text.push('_');
+        }
+        let lines = text.lines().map(Line::raw).collect::<Vec<_>>();
+        // The latest output is kept in view, though wrapped lines may push
+        // some of it off the bottom.
+        let height = io.height.saturating_sub(2) as usize;
+        let skip = lines.len().saturating_sub(height);
+        frame.render_widget(
+            Paragraph::new(lines[skip..].to_vec())
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" output ")),
+            io,
+        );
+
+        let state = match &self.state {
+            _ if self.typed.is_some() => "waiting for input, enter to send".to_owned(),
+            State::Paused => "paused".to_owned(),
+            State::Running => format!("running at {} steps/s", self.speed),
+            State::Halted => "halted".to_owned(),
+            State::Failed(e) => format!("error: {:#}", e),
+        };
+        frame.render_widget(
+            Line::from(vec![
+                Span::raw(format!(" {} steps, {} ", self.steps, state)).bold(),
+                Span::raw("| space step, r run/pause, +/- speed, q quit"),
+            ]),
+            status,
+        );
+    }
+
+    // The commits within `RADIUS` of the current one, those leading to it
+    // first, each with the commits that follow it.
+    fn neighborhood(&self) -> Vec<Line<'static>> {
+        let cur = self.instance.current_commit().unwrap_or(self.last);
+        let children = self
+            .instance
+            .current_program()
+            .map(|program| &program.children);
+        let mut parents = HashMap::<Oid, Vec<Oid>>::new();
+        for (&id, nexts) in children.into_iter().flatten() {
+            for next in nexts {
+                parents.entry(next.id).or_default().push(id);
+            }
+        }
+        let nexts = |id: &Oid| {
+            children
+                .and_then(|children| children.get(id))
+                .map(|nexts| nexts.iter().map(|next| next.id).collect())
+                .unwrap_or_default()
+        };
+        let mut above = near(cur, |id| parents.get(id).cloned().unwrap_or_default());
+        above.reverse();
+        let below = near(cur, nexts);
+
+        let mut lines = Vec::new();
+        for id in above.into_iter().chain([cur]).chain(below) {
+            let message = self.source.message(id).unwrap_or_default();
+            let message = message.lines().next().unwrap_or_default().to_owned();
+            let style = match id == cur {
+                true => Style::new().reversed().add_modifier(Modifier::BOLD),
+                false => Style::new(),
+            };
+            let mut spans = vec![
+                Span::styled(format!("{} ", id.short()), style.yellow()),
+                Span::styled(message, style),
+            ];
+            let nexts = nexts(&id)
+                .iter()
+                .map(Oid::short)
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !nexts.is_empty() {
+                spans.push(Span::raw(format!("  -> {}", nexts)).dark_gray());
+            }
+            lines.push(Line::from(spans));
+        }
+        lines
+    }
+}
+
+// The commits within `RADIUS` steps of `from` along `edges`, nearest first.
+fn near(from: Oid, edges: impl Fn(&Oid) -> Vec<Oid>) -> Vec<Oid> {
+    let mut seen = [from].into_iter().collect::<HashSet<_>>();
+    let mut found = Vec::new();
+    let mut frontier = vec![from];
+    for _ in 0..RADIUS {
+        frontier = frontier
+            .iter()
+            .flat_map(&edges)
+            .filter(|id| seen.insert(*id))
+            .collect();
+        found.extend(&frontier);
+    }
+    found
+}
+
+// Every variable of `table` in order of name, with the entries of tables
+// indented below them.
+fn variables(table: &Table, indent: usize, lines: &mut Vec<Line<'static>>) {
+    let mut vars = table.iter().collect::<Vec<_>>();
+    vars.sort_by_key(|(name, _)| *name);
+    for (name, val) in vars {
+        let pad = "  ".repeat(indent);
+        let line = match val {
+            Val::Table(_) => format!("{}{}/", pad, name),
+            Val::Str(s) => format!("{}{} = {:?}", pad, name, s),
+            Val::Ref(path) => format!("{}{} -> {}", pad, name, path),
+            Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_) => {
+                format!("{}{} = {}", pad, name, val)
+            }
+        };
+        lines.push(Line::raw(line));
+        if let Val::Table(table) = val {
+            variables(table, indent + 1, lines);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use undag::check;
+use undag::test_util::Program;
+
+// Every commit is checked, rather than stopping at the first that fails.
+#[test]
+fn errors_are_collected() {
+    let dag = Program::new()
+        .commit("set x #0")
+        .commit("prnt $x")
+        .commit("branch nowhere")
+        .commit("println $x")
+        .dag()
+        .unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let report = check::check(&dag, start, end).unwrap();
+    assert_eq!(report.checked, 4);
+    let errors = report
+        .errors
+        .iter()
+        .map(|e| format!("{:#}", e))
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(
+        errors[0].contains("invalid operation: prnt"),
+        "{}",
+        errors[0]
+    );
+    assert!(
+        errors[1].contains("undefined tag: nowhere"),
+        "{}",
+        errors[1]
+    );
+}
+
+#[test]
+fn valid_program_checks() {
+    let dag = Program::new()
+        .commit("set x #0")
+        .commit("println $x")
+        .dag()
+        .unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let report = check::check(&dag, start, end).unwrap();
+    assert!(report.errors.is_empty());
+    assert!(report.warnings.is_empty());
+    assert_eq!(report.to_string(), "");
+}
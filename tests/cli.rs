@@ -0,0 +1,95 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs the program written as `.undag` text with `args`, giving it `input`,
+// and returns what it printed.
+fn undag(program: &str, args: &[&str], input: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("program.undag");
+    std::fs::write(&path, program).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_undag"))
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Each print is a line of JSON, even when several are printed between reads.
+#[test]
+fn json_io_prints_each_line() {
+    let program = "println \"a\"\nprintln \"b\"\nprint \"c\"\nprintln \"d\"\n";
+    assert_eq!(
+        undag(program, &["--json-io"], ""),
+        "{\"out\":\"a\\n\"}\n{\"out\":\"b\\n\"}\n{\"out\":\"c\"}\n{\"out\":\"d\\n\"}\n"
+    );
+}
+
+// A character written a byte at a time comes out whole.
+#[test]
+fn json_io_keeps_characters_whole() {
+    let program = "outb #195\noutb #169\nprintln \"\"\n";
+    assert_eq!(
+        undag(program, &["--json-io"], ""),
+        "{\"out\":\"é\"}\n{\"out\":\"\\n\"}\n"
+    );
+}
+
+#[test]
+fn json_io_reads_input() {
+    let program = "inpln name\nprintln $name\n";
+    let input = "{\"in\": \"héllo\"}\n";
+    assert_eq!(
+        undag(program, &["--json-io"], input),
+        "{\"out\":\"héllo\\n\"}\n"
+    );
+}
+
+// The table a run ends with is written as JSON, and reading it back in
+// starts the next run from it.
+#[test]
+fn state_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.json");
+    let path = path.to_str().unwrap();
+    let program = "set n #42\nset t/s \"text\"\nrdiv t/r #1 #3\n";
+    undag(program, &["--dump-state", path], "");
+    let json = std::fs::read_to_string(path).unwrap();
+    let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "n": 42,
+            "t": { "s": "text", "r": { "$ratio": "1/3" } },
+        })
+    );
+
+    let program = "add n $n #1\nprintln $n\nprintln $t/s\nprintln $t/r\n";
+    assert_eq!(
+        undag(program, &["--init-state", path], ""),
+        "43\ntext\n1/3\n"
+    );
+}
+
+#[test]
+fn bench_counts_steps() {
+    let program = "set x #0\nadd x $x #1\nprintln $x\n";
+    let output = undag(program, &["bench", "-n", "3"], "");
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..2], ["runs:  3", "steps: 3 per run"]);
+}
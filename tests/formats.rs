@@ -0,0 +1,129 @@
+use anyhow::Result;
+use undag::dag::Dag;
+use undag::test_util::Program;
+use undag::{asm, bytecode, dot, Instance, Order, ProgramSource};
+
+// Counts to three around a loop, which is written as a replacement in every
+// format.
+fn counter() -> Program {
+    Program::new()
+        .commit("set x #0")
+        .commit("add x $x #1")
+        .tag("loop")
+        .commit("gt end $x #2")
+        .commit("match path $end #0 loop #1 _end")
+        .commit("branch $path")
+        .branch_to(&["loop", "_end"])
+        .commit("println $x")
+        .tag("_end")
+}
+
+// Greets whoever is named on the first line of input, with a different
+// greeting for one name.
+fn greeter() -> Program {
+    Program::new()
+        .commit("inpln name")
+        .commit(r#"eq bob $name "bob""#)
+        .commit("match path $bob #1 bob #0 other")
+        .commit("branch $path")
+        .branch_to(&["bob", "other"])
+        .commit(r#"println "hey bob""#)
+        .tag("bob")
+        .branch_to(&["_end"])
+        .commit(r#"concat message "hello, " $name"#)
+        .tag("other")
+        .commit("println $message")
+        .commit(r#"println "bye""#)
+        .tag("_end")
+}
+
+// Runs a program the way `Program::run` does, from a source read back from
+// another format.
+fn run(source: &dyn ProgramSource, input: &str) -> Result<String> {
+    let (start, end) = undag::endpoints(source)?;
+    let mut instance = Instance::new();
+    instance.set_order(Order::Deterministic);
+    instance.set_seed(0);
+    let mut output = Vec::new();
+    instance.run(source, start, end, &mut input.as_bytes(), &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+// Checks that `program` runs the same after `round_trip` on every input.
+fn runs_the_same(program: Program, inputs: &[&str], round_trip: fn(&Dag) -> Result<Dag>) {
+    let dag = program.dag().unwrap();
+    let read = round_trip(&dag).unwrap();
+    for input in inputs {
+        assert_eq!(run(&read, input).unwrap(), program.run(input).unwrap());
+    }
+}
+
+fn json(dag: &Dag) -> Result<Dag> {
+    Dag::from_json(&dag.to_json())
+}
+
+fn dot(dag: &Dag) -> Result<Dag> {
+    let (start, end) = undag::endpoints(dag)?;
+    dot::from_dot(&dot::to_dot(dag, start, end)?)
+}
+
+fn asm(dag: &Dag) -> Result<Dag> {
+    let (start, end) = undag::endpoints(dag)?;
+    asm::assemble(&asm::disassemble(dag, start, end)?)
+}
+
+fn bytecode(dag: &Dag) -> Result<Dag> {
+    let (start, end) = undag::endpoints(dag)?;
+    bytecode::load(&bytecode::compile(dag, start, end)?)
+}
+
+#[test]
+fn programs_run() {
+    assert_eq!(counter().run("").unwrap(), "3\n");
+    assert_eq!(greeter().run("bob\n").unwrap(), "hey bob\nbye\n");
+    assert_eq!(greeter().run("alice\n").unwrap(), "hello, alice\nbye\n");
+}
+
+#[test]
+fn json_round_trip() {
+    runs_the_same(counter(), &[""], json);
+    runs_the_same(greeter(), &["bob\n", "alice\n"], json);
+}
+
+#[test]
+fn dot_round_trip() {
+    runs_the_same(counter(), &[""], dot);
+    runs_the_same(greeter(), &["bob\n", "alice\n"], dot);
+}
+
+#[test]
+fn asm_round_trip() {
+    runs_the_same(counter(), &[""], asm);
+    runs_the_same(greeter(), &["bob\n", "alice\n"], asm);
+}
+
+#[test]
+fn bytecode_round_trip() {
+    runs_the_same(counter(), &[""], bytecode);
+    runs_the_same(greeter(), &["bob\n", "alice\n"], bytecode);
+}
+
+// Disassembling what was assembled gives back the same text.
+#[test]
+fn asm_is_stable() {
+    let dag = counter().dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let text = asm::disassemble(&dag, start, end).unwrap();
+    let read = asm::assemble(&text).unwrap();
+    let (start, end) = undag::endpoints(&read).unwrap();
+    assert_eq!(asm::disassemble(&read, start, end).unwrap(), text);
+}
+
+#[test]
+fn invalid_bytecode_fails() {
+    assert!(bytecode::load(b"not a program").is_err());
+    let dag = counter().dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let bytes = bytecode::compile(&dag, start, end).unwrap();
+    assert!(bytecode::load(&bytes[..bytes.len() / 2]).is_err());
+}
@@ -0,0 +1,439 @@
+use anyhow::Result;
+use undag::test_util::Program;
+use undag::{Instance, UndagError, Val};
+
+// Runs `ops` one after another as a program and returns what it printed.
+fn run(ops: &[&str]) -> Result<String> {
+    ops.iter()
+        .fold(Program::new(), |program, &op| program.commit(op))
+        .run("")
+}
+
+// The error that running `ops` fails with.
+fn error(ops: &[&str]) -> UndagError {
+    let error = run(ops).expect_err("program did not fail");
+    match error.downcast::<UndagError>() {
+        Ok(error) => error,
+        Err(error) => panic!("not an UndagError: {:#}", error),
+    }
+}
+
+#[test]
+fn strrep() {
+    assert_eq!(
+        run(&[r#"strrep r "ab" #3"#, "println $r"]).unwrap(),
+        "ababab\n"
+    );
+    assert_eq!(run(&[r#"strrep r "ab" #0"#, "println $r"]).unwrap(), "\n");
+    assert!(matches!(
+        error(&[r#"strrep r "ab" #-1"#]),
+        UndagError::TypeMismatch { .. }
+    ));
+    assert!(matches!(
+        error(&[r#"strrep r "ab" "3""#]),
+        UndagError::TypeMismatch { .. }
+    ));
+}
+
+// A count whose result would not fit in memory fails rather than panicking.
+#[test]
+fn strrep_too_long() {
+    for count in ["#9223372036854775807", "#1000000000"] {
+        let op = format!(r#"strrep r "abc" {}"#, count);
+        assert!(matches!(error(&[&op]), UndagError::TooLong { .. }));
+    }
+}
+
+// Numbers keep their kind through `dump` and `load`.
+#[test]
+fn dump_and_load_keep_numbers() {
+    let output = run(&[
+        "rdiv r #1 #3",
+        "set t/r $r",
+        "mul t/b #9223372036854775807 #2",
+        "dump s $t",
+        "load u $s",
+        "radd x $u/r #1",
+        "println $x",
+        "add y $u/b #1",
+        "println $y",
+        "eq same $u $t",
+        "println $same",
+    ])
+    .unwrap();
+    assert_eq!(output, "4/3\n18446744073709551615\n1\n");
+}
+
+#[test]
+fn float_math() {
+    let output = run(&[
+        "sqrt a #2",
+        "println $a",
+        "floor b #-2.5",
+        "println $b",
+        "ceil c #-2.5",
+        "println $c",
+        "round d #-2.5",
+        "println $d",
+        "round e #5/2",
+        "println $e",
+        "sin f #0",
+        "println $f",
+        "cos g #0",
+        "println $g",
+        "ln h #1",
+        "println $h",
+    ])
+    .unwrap();
+    assert_eq!(output, "1.4142135623730951\n-3\n-2\n-3\n3\n0.0\n1.0\n0.0\n");
+}
+
+// Arithmetic with a float gives a float, but numbers are compared by value.
+#[test]
+fn float_arithmetic() {
+    let output = run(&[
+        "div a #7 #2.0",
+        "println $a",
+        "add b #1e16 #1",
+        "println $b",
+        "eq c #2 #2.0",
+        "println $c",
+        "gt d #9007199254740993 #9007199254740992.0",
+        "println $d",
+    ])
+    .unwrap();
+    assert_eq!(output, "3.5\n1e16\n1\n1\n");
+}
+
+// Results that would not be finite fail, and the exact ops refuse floats.
+#[test]
+fn float_errors() {
+    for op in [
+        "sqrt r #-1",
+        "ln r #0",
+        r#"sqrt r "a""#,
+        "mul r #1e308 #10",
+        "mod r #1.5 #1",
+        "rdiv r #1 #0.5",
+    ] {
+        assert!(
+            matches!(error(&[op]), UndagError::TypeMismatch { .. }),
+            "{}",
+            op
+        );
+    }
+    assert!(matches!(
+        error(&["div r #1 #0.0"]),
+        UndagError::DivisionByZero
+    ));
+}
+
+#[test]
+fn errors_are_structured() {
+    assert!(matches!(
+        error(&["div r #1 #0"]),
+        UndagError::DivisionByZero
+    ));
+    assert!(matches!(
+        error(&["mod r #1 #0"]),
+        UndagError::DivisionByZero
+    ));
+    assert!(matches!(
+        error(&[r#"add r #1 "a""#]),
+        UndagError::TypeMismatch { .. }
+    ));
+    assert!(matches!(
+        error(&["println $missing"]),
+        UndagError::UndefinedSymbol(_)
+    ));
+}
+
+#[test]
+fn charat() {
+    assert_eq!(
+        run(&[r#"charat c "héllo" #1"#, "println $c"]).unwrap(),
+        "é\n"
+    );
+    for index in ["#5", "#-1", r#""0""#] {
+        let op = format!(r#"charat c "héllo" {}"#, index);
+        assert!(
+            matches!(error(&[&op]), UndagError::TypeMismatch { .. }),
+            "{}",
+            op
+        );
+    }
+}
+
+#[test]
+fn contains() {
+    let output = run(&[
+        r#"contains a "haystack" "st""#,
+        "println $a",
+        r#"contains b "haystack" "ts""#,
+        "println $b",
+        r#"startswith c "haystack" "hay""#,
+        "println $c",
+        r#"startswith d "haystack" "stack""#,
+        "println $d",
+        r#"endswith e "haystack" "stack""#,
+        "println $e",
+        "endswith f #1230 #30",
+        "println $f",
+    ])
+    .unwrap();
+    assert_eq!(output, "1\n0\n1\n0\n1\n1\n");
+}
+
+#[test]
+fn strrev() {
+    assert_eq!(
+        run(&[r#"strrev r "héllo""#, "println $r"]).unwrap(),
+        "olléh\n"
+    );
+    assert_eq!(run(&["strrev r #-120", "println $r"]).unwrap(), "021-\n");
+}
+
+#[test]
+fn radix() {
+    let output = run(&[
+        "tostr a #255 #16",
+        "println $a",
+        "tostr b #-5 #2",
+        "println $b",
+        r#"fromstr c "FF" #16"#,
+        "println $c",
+        r#"fromstr d "z" #36"#,
+        "println $d",
+    ])
+    .unwrap();
+    assert_eq!(output, "ff\n-101\n255\n35\n");
+    for op in [
+        "tostr r #1 #1",
+        "tostr r #1 #37",
+        r#"fromstr r "12" #2"#,
+        r#"fromstr r "" #10"#,
+        r#"tostr r "1" #10"#,
+    ] {
+        assert!(
+            matches!(error(&[op]), UndagError::TypeMismatch { .. }),
+            "{}",
+            op
+        );
+    }
+}
+
+#[test]
+fn rotate() {
+    let output = run(&[
+        "rotl a #1 #1",
+        "println $a",
+        "rotr b #1 #1",
+        "println $b",
+        "rotl c #-9223372036854775808 #1",
+        "println $c",
+        "rotl d #3 #65",
+        "println $d",
+    ])
+    .unwrap();
+    assert_eq!(output, "2\n-9223372036854775808\n1\n6\n");
+    assert!(matches!(
+        error(&[r#"rotl r #1 "a""#]),
+        UndagError::TypeMismatch { .. }
+    ));
+}
+
+// Integer results too big for 64 bits become big integers rather than
+// overflowing, and come back down when they fit again.
+#[test]
+fn big_integers() {
+    let output = run(&[
+        "add a #9223372036854775807 #1",
+        "println $a",
+        "mul b $a $a",
+        "println $b",
+        "sub c $a #1",
+        "println $c",
+        "add d $c #1",
+        "println $d",
+        "sub e #-9223372036854775808 #1",
+        "println $e",
+        "div f $b $a",
+        "eq g $f $a",
+        "println $g",
+        "mod h $b #7",
+        "println $h",
+    ])
+    .unwrap();
+    assert_eq!(
+        output,
+        "9223372036854775808\n85070591730234615865843651857942052864\n\
+         9223372036854775807\n9223372036854775808\n-9223372036854775809\n1\n1\n"
+    );
+    assert!(matches!(
+        error(&["div r #9223372036854775808 #0"]),
+        UndagError::DivisionByZero
+    ));
+}
+
+#[test]
+fn fractions() {
+    let output = run(&[
+        "rdiv a #2 #6",
+        "println $a",
+        "radd b $a #1/6",
+        "println $b",
+        "rdiv c #4 #2",
+        "println $c",
+        "numer d #-6/4",
+        "println $d",
+        "denom e #-6/4",
+        "println $e",
+    ])
+    .unwrap();
+    assert_eq!(output, "1/3\n1/2\n2\n-3\n2\n");
+    assert!(matches!(
+        error(&["rdiv r #1 #0"]),
+        UndagError::DivisionByZero
+    ));
+    assert!(matches!(
+        error(&[r#"radd r #1/2 "a""#]),
+        UndagError::TypeMismatch { .. }
+    ));
+}
+
+// Iterating from -1 visits every key in sorted order.
+#[test]
+fn next() {
+    let output = run(&[
+        "set t/b #2",
+        "set t/a #1",
+        "set t/c #3",
+        "next k t #-1",
+        "println $k",
+        "next k t $k",
+        "println $k",
+        "next k t $k",
+        "println $k",
+        "next k t $k",
+        "println $k",
+        "set e/x #1",
+        "del e/x",
+        "next k e #-1",
+        "println $k",
+    ])
+    .unwrap();
+    assert_eq!(output, "a\nb\nc\n-1\n-1\n");
+    assert!(matches!(
+        error(&["set n #1", "next k n #-1"]),
+        UndagError::NotATable(_)
+    ));
+}
+
+// Paths through a reference reach the table it refers to, while setting the
+// reference itself replaces it.
+#[test]
+fn references() {
+    let output = run(&[
+        "set t/x #1",
+        "ref p t",
+        "deref v $p",
+        "println $v/x",
+        "set p/x #2",
+        "println $t/x",
+        "set p #3",
+        "println $t/x",
+    ])
+    .unwrap();
+    assert_eq!(output, "1\n2\n2\n");
+    assert!(matches!(
+        error(&["set n #1", "deref v $n"]),
+        UndagError::TypeMismatch { .. }
+    ));
+    assert!(matches!(
+        error(&["ref a b", "ref b a", "println $a/x"]),
+        UndagError::RefDepth { .. }
+    ));
+}
+
+#[test]
+fn eq_tables() {
+    let output = run(&[
+        "set a/x/y #1",
+        "set a/z #2",
+        "set b/z #2",
+        "set b/x/y #1",
+        "eq same $a $b",
+        "println $same",
+        "set b/x/w #3",
+        "eq differ $a $b",
+        "println $differ",
+        "eq mixed $a #1",
+        "println $mixed",
+    ])
+    .unwrap();
+    assert_eq!(output, "1\n0\n0\n");
+}
+
+// Keys are written in sorted order, so equal tables dump the same.
+#[test]
+fn dump() {
+    let output = run(&[
+        "set t/b #1",
+        r#"set t/a "x""#,
+        "set t/c/d #2",
+        "dump s $t",
+        "println $s",
+    ])
+    .unwrap();
+    assert_eq!(output, "{\"a\":\"x\",\"b\":1,\"c\":{\"d\":2}}\n");
+    assert!(matches!(
+        error(&["dump s #1"]),
+        UndagError::TypeMismatch { .. }
+    ));
+}
+
+#[test]
+fn load() {
+    let output = run(&[
+        r#"load t "{\"a\":{\"b\":5},\"c\":\"x\"}""#,
+        "println $t/a/b",
+        "println $t/c",
+    ])
+    .unwrap();
+    assert_eq!(output, "5\nx\n");
+    for json in [r#""{""#, r#""[1]""#, r#""{\"a\":null}""#] {
+        let op = format!("load t {}", json);
+        let error = run(&[&op]).expect_err(&op);
+        assert!(
+            format!("{:#}", error).contains("load: invalid table"),
+            "{}",
+            op
+        );
+    }
+}
+
+// Registered ops are called with `@`, so that other names are still errors.
+#[test]
+fn registered_ops() {
+    let run = |op: &str| -> Result<String> {
+        let dag = Program::new().commit(op).commit("println $x").dag()?;
+        let (start, end) = undag::endpoints(&dag)?;
+        let mut instance = Instance::new();
+        instance.register("double", |instance, args| match args {
+            [Val::Str(var), Val::Int(n)] => instance.set(var, Val::Int(n * 2)),
+            _ => anyhow::bail!("double: invalid args"),
+        });
+        let mut output = Vec::new();
+        instance.run(&dag, start, end, &mut "".as_bytes(), &mut output)?;
+        Ok(String::from_utf8(output)?)
+    };
+    assert_eq!(run("@double x #21").unwrap(), "42\n");
+    for op in ["double x #21", "@tripled x #21"] {
+        assert!(run(op).is_err(), "{}", op);
+    }
+    let error = "double x #21".parse::<undag::Op>().unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<UndagError>(),
+        Some(UndagError::InvalidOperation(_))
+    ));
+}
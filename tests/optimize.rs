@@ -0,0 +1,58 @@
+use undag::optimize::optimize;
+use undag::test_util::Program;
+use undag::{Instance, Order};
+
+// Optimizing a program changes its commits but not what it prints.
+#[test]
+fn optimized_program_runs_the_same() {
+    let program = Program::new()
+        .commit("set a #6")
+        .commit("mul b $a #7")
+        .commit("")
+        .commit("tostr s $b #10")
+        .commit("println $s")
+        .commit(r#"println "done""#);
+    let repo = program.build().unwrap();
+    let (start, end) = undag::endpoints(repo.repo()).unwrap();
+
+    let planned = optimize(repo.repo(), start, end, true).unwrap();
+    assert!(!planned.is_empty());
+    let source = undag::open(repo.path()).unwrap();
+    assert!(
+        source.replacements().unwrap().is_empty(),
+        "dry run replaced commits"
+    );
+
+    let changed = optimize(repo.repo(), start, end, false).unwrap();
+    assert_eq!(changed, planned);
+    let source = undag::open(repo.path()).unwrap();
+    assert!(!source.replacements().unwrap().is_empty());
+
+    let mut instance = Instance::new();
+    instance.set_order(Order::Deterministic);
+    let mut output = Vec::new();
+    let (start, end) = undag::endpoints(&*source).unwrap();
+    instance
+        .run(&*source, start, end, &mut "".as_bytes(), &mut output)
+        .unwrap();
+    assert_eq!(output, program.run("").unwrap().as_bytes());
+    assert_eq!(output, b"42\ndone\n");
+}
+
+// Only short results of `strrep` are folded, since they are written into
+// commit messages.
+#[test]
+fn strrep_is_folded_when_short() {
+    let program = Program::new()
+        .commit(r#"strrep short "ab" #3"#)
+        .commit(r#"strrep long "ab" #1000"#)
+        .commit(r#"strrep huge "ab" #9223372036854775807"#);
+    let repo = program.build().unwrap();
+    let (start, end) = undag::endpoints(repo.repo()).unwrap();
+    let changes = optimize(repo.repo(), start, end, true).unwrap();
+    let changes = changes
+        .iter()
+        .map(|(_, change)| change.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(changes, ["set short ababab"]);
+}
@@ -0,0 +1,54 @@
+use proptest::prelude::*;
+use undag::{Instance, Op, Val};
+
+// Messages made of an op's name and the kinds of words its arguments are, so
+// that most of them get past the name to the parsing of arguments.
+const OP: &str = concat!(
+    "(set|get|ref|deref|del|exists|is|next|branch|enter|exit|match|print|println|",
+    "inpln|outb|inb|cls|goto_xy|color|rawmode|key|clock|concat|chars|graphemes|",
+    "charat|contains|startswith|endswith|strrep|strrev|tostr|fromstr|dump|load|meta|",
+    "mkcommit|mktag|eq|gt|add|sub|mul|div|mod|and|or|xor|rotl|rotr|rdiv|radd|rsub|",
+    "rmul|numer|denom|@[a-z]{1,4})",
+    r#"( +(\$[a-z/]{0,4}|#-?[0-9]{0,24}(/-?[0-9]{0,3})?|"[^"\\]{0,4}"?|'[^']{0,4}'?|\\.?|[a-z]{0,4}))*"#,
+);
+
+// Paths of a few names, separated by `/` and sometimes empty, and names that
+// look like values.
+const PATH: &str = r"(([a-z]|\$|#1|/){0,6})";
+
+proptest! {
+    #[test]
+    fn any_message_parses_or_fails(message in any::<String>()) {
+        let _ = message.parse::<Op>();
+    }
+
+    #[test]
+    fn op_like_message_parses_or_fails(message in OP) {
+        let _ = message.parse::<Op>();
+    }
+
+    // A parsed op is written back as a message that parses to the same text.
+    #[test]
+    fn parsed_op_is_written_back(message in OP) {
+        if let Ok(op) = message.parse::<Op>() {
+            let written = op.to_string();
+            let reparsed = written.parse::<Op>();
+            prop_assert!(reparsed.is_ok(), "{:?} was written as {:?}", message, written);
+            prop_assert_eq!(reparsed.unwrap().to_string(), written);
+        }
+    }
+
+    #[test]
+    fn paths_are_written_and_read_or_fail(paths in prop::collection::vec(PATH, 1..6)) {
+        let mut instance = Instance::new();
+        for (i, path) in paths.iter().enumerate() {
+            let _ = match i % 3 {
+                0 => instance.set(path, Val::Int(i as i64)),
+                1 => instance.set(path, Val::Ref(path.as_str().into())),
+                _ => instance.enter_table(path),
+            };
+            let _ = instance.get(path);
+            let _ = instance.root_path(path);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
+use undag::explain::{BranchExplainer, Reason};
+use undag::test_util::Program;
+use undag::{Instance, Order, UndagError};
+
+// Runs `program` with loops reported once a commit repeats `limit` times
+// without anything changing.
+fn run_limited(program: &Program, limit: usize) -> Result<()> {
+    let dag = program.dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let mut instance = Instance::new();
+    instance.set_order(Order::Deterministic);
+    instance.set_loop_limit(limit, true);
+    instance.run(&dag, start, end, &mut "".as_bytes(), &mut Vec::new())
+}
+
+#[test]
+fn stuck_loop_fails_when_strict() {
+    let program = Program::new()
+        .commit("set x #1")
+        .tag("loop")
+        .commit("branch loop")
+        .branch_to(&["loop", "_end"])
+        .commit("println $x")
+        .tag("_end");
+    let error = run_limited(&program, 5).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<UndagError>(),
+            Some(UndagError::StuckLoop { times: 6, .. })
+        ),
+        "{:#}",
+        error
+    );
+}
+
+// A loop that changes a variable each time around is making progress.
+#[test]
+fn counting_loop_is_not_stuck() {
+    let program = Program::new()
+        .commit("set x #0")
+        .commit("add x $x #1")
+        .tag("loop")
+        .commit("gt end $x #9")
+        .commit("match path $end #0 loop #1 _end")
+        .commit("branch $path")
+        .branch_to(&["loop", "_end"])
+        .commit("println $x")
+        .tag("_end");
+    run_limited(&program, 3).unwrap();
+}
+
+// A failing op is shown as it would be written, next to the message it was
+// parsed from.
+#[test]
+fn diagnostic_shows_op() {
+    let program = Program::new().commit("set x #1").commit("div  r $x   #0");
+    let dag = program.dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let mut instance = Instance::new();
+    let result = instance.run(&dag, start, end, &mut "".as_bytes(), &mut Vec::new());
+    assert!(result.is_err());
+    let diagnostic = instance.diagnostic().unwrap();
+    assert_eq!(diagnostic.op.as_deref(), Some("div r $x #0"));
+    assert!(diagnostic
+        .to_string()
+        .contains("parsed as div r $x #0\nvariables:\n    x = 1\n"));
+}
+
+// Each `branch` is explained with where the search of its tag's history
+// reached the children.
+#[test]
+fn branches_are_explained() {
+    let program = Program::new()
+        .commit("set x #0")
+        .commit("add x $x #1")
+        .tag("loop")
+        .commit("gt end $x #1")
+        .commit("match path $end #0 loop #1 _end")
+        .commit("branch $path")
+        .branch_to(&["loop", "_end"])
+        .commit("println $x")
+        .tag("_end");
+    let dag = program.dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let explanations = Rc::new(RefCell::new(Vec::new()));
+    let mut instance = Instance::new();
+    instance.set_order(Order::Deterministic);
+    instance.set_hook(BranchExplainer::new({
+        let explanations = explanations.clone();
+        move |explanation| explanations.borrow_mut().push(explanation)
+    }));
+    instance
+        .run(&dag, start, end, &mut "".as_bytes(), &mut Vec::new())
+        .unwrap();
+
+    let explanations = explanations.borrow();
+    let tags = explanations
+        .iter()
+        .map(|explanation| explanation.tag.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(tags, ["loop", "_end"]);
+    for explanation in explanations.iter() {
+        assert_eq!(explanation.resolved, Some(explanation.to));
+        assert!(matches!(explanation.reason, Reason::Nearest { .. }));
+        assert_eq!(explanation.children.len(), 2);
+    }
+}
+
+// Commits on the path not taken are reported as never executed.
+#[test]
+fn coverage_reports_unexecuted() {
+    let program = Program::new()
+        .commit("branch yes")
+        .branch_to(&["yes", "no"])
+        .commit(r#"println "yes""#)
+        .tag("yes")
+        .branch_to(&["_end"])
+        .commit(r#"println "no""#)
+        .tag("no")
+        .commit("")
+        .tag("_end");
+    let dag = program.dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let mut instance = Instance::new();
+    instance.enable_coverage();
+    instance
+        .run(&dag, start, end, &mut "".as_bytes(), &mut Vec::new())
+        .unwrap();
+    let report = instance.coverage().unwrap().report(&dag);
+    assert_eq!((report.executed, report.total), (3, 4));
+    let summaries = report
+        .unexecuted
+        .iter()
+        .map(|(_, summary)| summary.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(summaries, [r#"println "no""#]);
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use std::path::Path;
+use std::process::Command;
+use undag::commit_graph::CommitGraph;
+use undag::test_util::Program;
+use undag::{Instance, Order, ProgramSource, UndagError};
+
+// Counts to three around a loop, which is made with `git replace --graft`
+// once the program is written into a repository.
+fn counter() -> Program {
+    Program::new()
+        .commit("set x #0")
+        .commit("add x $x #1")
+        .tag("loop")
+        .commit("gt end $x #2")
+        .commit("match path $end #0 loop #1 _end")
+        .commit("branch $path")
+        .branch_to(&["loop", "_end"])
+        .commit("println $x")
+        .tag("_end")
+}
+
+// Runs a program the way `Program::run` does, from whichever source it was
+// read from.
+fn run(source: &dyn ProgramSource) -> Result<String> {
+    let (start, end) = undag::endpoints(source)?;
+    let mut instance = Instance::new();
+    instance.set_order(Order::Deterministic);
+    instance.set_seed(0);
+    let mut output = Vec::new();
+    instance.run(source, start, end, &mut "".as_bytes(), &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+// Runs git in `dir`, which local submodules need to be allowed for.
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "protocol.file.allow=always", "-c", "user.name=undag"])
+        .args(["-c", "user.email=undag@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        status.status.success(),
+        "git {}: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&status.stderr)
+    );
+}
+
+// Points a branch at the program's end and checks it out, so that the
+// repository can be cloned.
+fn check_out(dir: &Path) {
+    git(dir, &["branch", "main", "_end"]);
+    git(dir, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+    git(dir, &["reset", "--quiet", "--hard"]);
+}
+
+#[test]
+fn repository() {
+    let repo = counter().build().unwrap();
+    let source = undag::open(repo.path()).unwrap();
+    assert_eq!(run(&*source).unwrap(), "3\n");
+}
+
+#[test]
+fn bare_repository() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = undag::git::Repository::init_bare(dir.path()).unwrap();
+    counter().dag().unwrap().write_to(&repo).unwrap();
+    let source = undag::open(dir.path()).unwrap();
+    assert_eq!(run(&*source).unwrap(), "3\n");
+}
+
+#[test]
+fn bundle() {
+    let repo = counter().build().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let bundle = dir.path().join("counter.bundle");
+    git(
+        repo.path(),
+        &[
+            "bundle",
+            "create",
+            "--quiet",
+            bundle.to_str().unwrap(),
+            "--all",
+        ],
+    );
+    let source = undag::open(&bundle).unwrap();
+    assert_eq!(run(&*source).unwrap(), "3\n");
+}
+
+// A submodule's tags are branched to with its name as a prefix.
+#[test]
+fn submodule() {
+    let module = Program::new()
+        .commit(r#"println "in lib""#)
+        .build()
+        .unwrap();
+    check_out(module.path());
+    let repo = Program::new()
+        .commit(r#"println "before""#)
+        .commit("branch lib::_start")
+        .commit(r#"println "after""#)
+        .build()
+        .unwrap();
+    let url = module.path().to_str().unwrap();
+    git(repo.path(), &["submodule", "--quiet", "add", url, "lib"]);
+    let source = undag::open(repo.path()).unwrap();
+    assert_eq!(run(&*source).unwrap(), "before\nin lib\nafter\n");
+}
+
+// Submodules that are not checked out, as in a clone that did not recurse
+// into them or a bare one, are left unlinked.
+#[test]
+fn submodule_not_checked_out() {
+    let module = Program::new()
+        .commit(r#"println "in lib""#)
+        .build()
+        .unwrap();
+    check_out(module.path());
+    let repo = Program::new().commit(r#"println "main""#).build().unwrap();
+    check_out(repo.path());
+    let url = module.path().to_str().unwrap();
+    git(repo.path(), &["submodule", "--quiet", "add", url, "lib"]);
+    git(repo.path(), &["commit", "--quiet", "-m", "add lib"]);
+
+    let dir = tempfile::tempdir().unwrap();
+    for (name, flags) in [("clone", &[][..]), ("bare", &["--bare"][..])] {
+        let clone = dir.path().join(name);
+        let mut args = vec!["clone", "--quiet"];
+        args.extend(flags);
+        args.extend([repo.path().to_str().unwrap(), clone.to_str().unwrap()]);
+        git(dir.path(), &args);
+        let source = undag::open(&clone).unwrap();
+        assert_eq!(run(&*source).unwrap(), "main\n", "{}", name);
+    }
+}
+
+// The commit-graph gives the same parents as the commits themselves, and the
+// program runs the same reading them from it.
+#[test]
+fn commit_graph() {
+    let repo = counter().build().unwrap();
+    git(repo.path(), &["commit-graph", "write", "--reachable"]);
+    let graph = CommitGraph::open(&repo.repo().path().join("objects"))
+        .unwrap()
+        .expect("no commit-graph was written");
+
+    let (_, end) = undag::endpoints(repo.repo()).unwrap();
+    let mut pending = vec![end];
+    while let Some(id) = pending.pop() {
+        let parents = ProgramSource::parents(repo.repo(), id).unwrap();
+        assert_eq!(graph.parents(id), Some(parents.clone()));
+        assert_eq!(
+            graph.time(id),
+            Some(ProgramSource::time(repo.repo(), id).unwrap())
+        );
+        pending.extend(parents);
+    }
+
+    let source = undag::open(repo.path()).unwrap();
+    assert_eq!(run(&*source).unwrap(), "3\n");
+}
+
+#[cfg(feature = "gitoxide")]
+#[test]
+fn gitoxide() {
+    let repo = counter().build().unwrap();
+    let source = undag::open_gitoxide(repo.path()).unwrap();
+    assert_eq!(run(&source).unwrap(), "3\n");
+}
+
+// History cut off by a shallow clone fails to run rather than running as
+// though it ended there. The boundary is marked by hand, which git reads the
+// same as one left by `git clone --depth`.
+#[test]
+fn shallow_clone() {
+    let repo = counter().build().unwrap();
+    let (start, end) = undag::endpoints(repo.repo()).unwrap();
+    let boundary = ProgramSource::parents(repo.repo(), end).unwrap()[0];
+    assert_ne!(boundary, start);
+    std::fs::write(
+        repo.repo().path().join("shallow"),
+        format!("{}\n", boundary),
+    )
+    .unwrap();
+
+    let source = undag::open(repo.path()).unwrap();
+    let error = run(&*source).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<UndagError>(),
+            Some(UndagError::ShallowHistory { oid }) if *oid == boundary
+        ),
+        "{:#}",
+        error
+    );
+    #[cfg(feature = "gitoxide")]
+    {
+        let source = undag::open_gitoxide(repo.path()).unwrap();
+        let error = run(&source).unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<UndagError>(),
+                Some(UndagError::ShallowHistory { oid }) if *oid == boundary
+            ),
+            "{:#}",
+            error
+        );
+    }
+}
+
+// Moving the tag the program ends at makes it end at the commit the tag is
+// moved to, while moving another tag on the same commit leaves it be.
+#[test]
+fn mktag_moves_the_end() {
+    let program = |tag: &str| {
+        Program::new()
+            .commit(r#"println "start""#)
+            .commit(r#"mkcommit new next "println generated""#)
+            .commit(format!("mktag {} $new", tag))
+            .tag("next")
+            .commit(r#"println "end""#)
+            .tag("finish")
+    };
+    for (tag, expected) in [("finish", "start\ngenerated\n"), ("_end", "start\nend\n")] {
+        let repo = program(tag).build().unwrap();
+        let (start, end) = undag::tagged_endpoints(repo.repo(), "_start", "finish").unwrap();
+        let mut instance = Instance::new();
+        instance.set_order(Order::Deterministic);
+        instance.set_end_tag("finish");
+        let mut output = Vec::new();
+        instance
+            .run(repo.repo(), start, end, &mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected, "{}", tag);
+    }
+}
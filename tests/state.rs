@@ -0,0 +1,57 @@
+use undag::test_util::Program;
+use undag::{state, Instance, Table, Val};
+
+// The global table that running `program` ends with.
+fn table(program: Program) -> Table {
+    let dag = program.dag().unwrap();
+    let (start, end) = undag::endpoints(&dag).unwrap();
+    let mut instance = Instance::new();
+    instance
+        .run(&dag, start, end, &mut "".as_bytes(), &mut Vec::new())
+        .unwrap();
+    instance.root_table().clone()
+}
+
+// Every kind of value is written as JSON that is read back as the same value.
+#[test]
+fn round_trip() {
+    let table = table(
+        Program::new()
+            .commit("set n #-7")
+            .commit("mul big #9223372036854775807 #4")
+            .commit("rdiv third #1 #3")
+            .commit("rdiv t/neg #-5 #2")
+            .commit("set t/x #2.0")
+            .commit(r#"set t/s "text""#)
+            .commit("ref r t/s"),
+    );
+    let json = state::to_json(&table);
+    assert_eq!(json["third"], serde_json::json!({ "$ratio": "1/3" }));
+    assert_eq!(
+        json["big"],
+        serde_json::json!({ "$bigint": "36893488147419103228" })
+    );
+    assert_eq!(state::from_json(&json).unwrap(), table);
+    // Floats equal the integers they are, so their kind is checked apart.
+    match &state::from_json(&json).unwrap()["t"] {
+        Val::Table(t) => assert!(matches!(t["x"], Val::Float(_))),
+        val => panic!("not a table: {}", val),
+    }
+
+    let mut dumped = Vec::new();
+    state::dump(&mut dumped, &table).unwrap();
+    let dumped = serde_json::from_slice(&dumped).unwrap();
+    assert_eq!(state::from_json(&dumped).unwrap(), table);
+}
+
+#[test]
+fn invalid_numbers_fail() {
+    for json in [
+        serde_json::json!({ "x": { "$ratio": "1/0" } }),
+        serde_json::json!({ "x": { "$ratio": "one" } }),
+        serde_json::json!({ "x": { "$bigint": "1.5" } }),
+        serde_json::json!({ "x": null }),
+    ] {
+        assert!(state::from_json(&json).is_err(), "{}", json);
+    }
+}
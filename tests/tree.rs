@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use undag::{Oid, ProgramSource};
+
+// A repository read without forks, so every commit is read on one thread.
+struct Serial(undag::git::Repository);
+
+impl ProgramSource for Serial {
+    fn message(&self, id: Oid) -> Result<String> {
+        ProgramSource::message(&self.0, id)
+    }
+
+    fn parents(&self, id: Oid) -> Result<Vec<Oid>> {
+        ProgramSource::parents(&self.0, id)
+    }
+
+    fn time(&self, id: Oid) -> Result<i64> {
+        ProgramSource::time(&self.0, id)
+    }
+
+    fn tag(&self, name: &str) -> Option<Oid> {
+        ProgramSource::tag(&self.0, name)
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        ProgramSource::tags(&self.0)
+    }
+
+    fn replacements(&self) -> Result<Vec<(Oid, Oid)>> {
+        ProgramSource::replacements(&self.0)
+    }
+
+    fn descendant_of(&self, id: Oid, ancestor: Oid) -> bool {
+        ProgramSource::descendant_of(&self.0, id, ancestor)
+    }
+}
+
+// A program whose `_end` merges `width` chains of `length` commits that each
+// lead back to `_start`, written with `git fast-import`.
+fn wide(dir: &std::path::Path, width: usize, length: usize) {
+    let status = Command::new("git")
+        .args(["init", "-q"])
+        .arg(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut stream = String::new();
+    let mut mark = 0;
+    let mut commit = |stream: &mut String, op: &str, parents: &[usize]| {
+        mark += 1;
+        stream.push_str(&format!(
+            "commit refs/heads/main\nmark :{}\ncommitter a <a@a> {} +0000\ndata {}\n{}\n",
+            mark,
+            1_000_000 + mark,
+            op.len(),
+            op
+        ));
+        for (i, parent) in parents.iter().enumerate() {
+            let kind = if i == 0 { "from" } else { "merge" };
+            stream.push_str(&format!("{} :{}\n", kind, parent));
+        }
+        mark
+    };
+    let start = commit(&mut stream, "set i #0", &[]);
+    let ends = (0..width)
+        .map(|_| {
+            (0..length).fold(start, |parent, _| {
+                commit(&mut stream, "add i $i #1", &[parent])
+            })
+        })
+        .collect::<Vec<_>>();
+    let end = commit(&mut stream, "println $i", &ends);
+    stream.push_str(&format!("reset refs/tags/_start\nfrom :{}\n", start));
+    stream.push_str(&format!("reset refs/tags/_end\nfrom :{}\n", end));
+    let mut child = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stream.as_bytes())
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+// A generation large enough to be split between threads gives the same
+// program as reading it on one.
+#[test]
+fn wide_generation() {
+    let dir = tempfile::tempdir().unwrap();
+    wide(dir.path(), 300, 3);
+    let source = undag::open(dir.path()).unwrap();
+    let serial = Serial(undag::open_repository(dir.path()).unwrap());
+    let (start, end) = undag::endpoints(&serial).unwrap();
+    let dot = undag::dot::to_dot(&*source, start, end).unwrap();
+    assert_eq!(dot, undag::dot::to_dot(&serial, start, end).unwrap());
+    assert_eq!(dot.matches("add i").count(), 900);
+}